@@ -0,0 +1,157 @@
+//! The discount calculation without storage, so it also builds for
+//! `wasm32-unknown-unknown`. The server loads a `PoolSnapshot` from Redis
+//! and stays authoritative; the chat widget can run `preview_discount` on a
+//! snapshot it was given to show a discount before the bill is sent.
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+/// What a customer's next bill would be paid from, as of when it was taken.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PoolSnapshot {
+    /// Last week's pool, which this week's shares are paid from.
+    pub pool_amount: f64,
+    /// Customers sharing the pool: last week's and this week's so far.
+    pub eligible_customers: f64,
+    /// The customer was billed last week, so has a share.
+    pub in_previous_period: bool,
+    /// The customer was already billed today; shares are paid once a day.
+    pub billed_today: bool,
+    /// What the customer's streak, counting this week, multiplies a share by.
+    pub streak_multiplier: f64,
+    /// What active campaigns multiply a share by.
+    pub campaign_multiplier: f64,
+    /// The share is banked in the customer's wallet instead of taken off.
+    pub banks_to_wallet: bool,
+    /// The business uses eligibility rules or a strategy the preview can't
+    /// run, so the quote may differ from the bill.
+    pub estimate: bool,
+}
+
+/// A previewed bill.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Quote {
+    pub bill_amount: f64,
+    pub pool_share: f64,
+    pub discount: f64,
+    pub final_amount: f64,
+    pub discount_percentage: f64,
+    pub wallet_credited: f64,
+    pub estimate: bool,
+}
+
+/// One equal share of `pool_amount`, or nothing while no one is eligible.
+pub fn equal_share(pool_amount: f64, eligible_customers: f64) -> f64 {
+    if eligible_customers > 0.0 {
+        pool_amount / eligible_customers
+    } else {
+        0.0
+    }
+}
+
+/// A share with the streak and campaign multipliers applied.
+pub fn pool_share(share_value: f64, streak_multiplier: f64, campaign_multiplier: f64) -> f64 {
+    share_value * streak_multiplier * campaign_multiplier
+}
+
+pub fn discount_percentage(discount: f64, bill_amount: f64) -> f64 {
+    if bill_amount != 0.0 {
+        discount / bill_amount * 100.0
+    } else {
+        0.0
+    }
+}
+
+/// What a bill of `bill_amount` would get from the equal split of the pool.
+/// Referral bonuses and wallet redemptions are not previewed.
+pub fn quote(snapshot: &PoolSnapshot, bill_amount: f64) -> Quote {
+    let eligible = !snapshot.billed_today && snapshot.in_previous_period && snapshot.eligible_customers > 0.0;
+    let share = if eligible {
+        pool_share(
+            equal_share(snapshot.pool_amount, snapshot.eligible_customers),
+            snapshot.streak_multiplier,
+            snapshot.campaign_multiplier,
+        )
+        .min(bill_amount)
+    } else {
+        0.0
+    };
+    let (discount, wallet_credited) = if snapshot.banks_to_wallet { (0.0, share) } else { (share, 0.0) };
+    Quote {
+        bill_amount,
+        pool_share: share,
+        discount,
+        final_amount: bill_amount - discount,
+        discount_percentage: discount_percentage(discount, bill_amount),
+        wallet_credited,
+        estimate: snapshot.estimate,
+    }
+}
+
+#[derive(Deserialize)]
+struct PreviewRequest {
+    snapshot: PoolSnapshot,
+    bill_amount: f64,
+}
+
+/// `quote` for JavaScript: takes `{"snapshot": {...}, "bill_amount": 600.5}`
+/// as JSON and returns the quote as JSON, or `{"error": "..."}`.
+#[wasm_bindgen]
+pub fn preview_discount(request: &str) -> String {
+    match serde_json::from_str::<PreviewRequest>(request) {
+        Ok(request) if request.bill_amount.is_finite() && request.bill_amount >= 0.0 => {
+            serde_json::to_string(&quote(&request.snapshot, request.bill_amount)).unwrap()
+        }
+        Ok(_) => serde_json::json!({ "error": "bill_amount must not be negative" }).to_string(),
+        Err(error) => serde_json::json!({ "error": error.to_string() }).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn snapshot() -> PoolSnapshot {
+        PoolSnapshot {
+            pool_amount: 60.0,
+            eligible_customers: 4.0,
+            in_previous_period: true,
+            billed_today: false,
+            streak_multiplier: 1.5,
+            campaign_multiplier: 2.0,
+            banks_to_wallet: false,
+            estimate: false,
+        }
+    }
+
+    #[test]
+    fn test_quote() {
+        let quote = quote(&snapshot(), 300.0);
+        assert_eq!(quote.discount, 45.0);
+        assert_eq!(quote.final_amount, 255.0);
+        assert_eq!(quote.discount_percentage, 15.0);
+
+        let billed = PoolSnapshot { billed_today: true, ..snapshot() };
+        assert_eq!(super::quote(&billed, 300.0).discount, 0.0);
+        let newcomer = PoolSnapshot { in_previous_period: false, ..snapshot() };
+        assert_eq!(super::quote(&newcomer, 300.0).discount, 0.0);
+        let banked = super::quote(&PoolSnapshot { banks_to_wallet: true, ..snapshot() }, 300.0);
+        assert_eq!((banked.discount, banked.wallet_credited), (0.0, 45.0));
+        assert_eq!(equal_share(60.0, 0.0), 0.0);
+
+        let capped = super::quote(&snapshot(), 30.0);
+        assert_eq!((capped.discount, capped.final_amount), (30.0, 0.0));
+    }
+
+    #[test]
+    fn test_preview_discount_json() {
+        let request = serde_json::json!({ "snapshot": snapshot(), "bill_amount": 300.0 }).to_string();
+        let quote: Quote = serde_json::from_str(&preview_discount(&request)).unwrap();
+        assert_eq!(quote.discount, 45.0);
+        assert!(preview_discount("{}").contains("error"));
+        let negative = serde_json::json!({ "snapshot": snapshot(), "bill_amount": -1.0 }).to_string();
+        assert!(preview_discount(&negative).contains("must not be negative"));
+    }
+}
@@ -0,0 +1,57 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// One administrative change, appended to `audit:<business>`. Entries are
+/// never rewritten or removed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct AuditEntry {
+    pub timestamp: String,
+    /// Label of the API key that made the change.
+    pub actor: String,
+    pub action: String,
+    #[schema(value_type = Object)]
+    pub details: serde_json::Value,
+}
+
+pub fn record_audit(
+    business_name: &str,
+    actor: &str,
+    action: &str,
+    details: serde_json::Value,
+    conn: &mut redis::Connection,
+) -> AuditEntry {
+    let entry = AuditEntry {
+        timestamp: Utc::now().to_rfc3339(),
+        actor: actor.to_string(),
+        action: action.to_string(),
+        details,
+    };
+    let _: () = redis::cmd("RPUSH")
+        .arg(audit_redis_key(business_name))
+        .arg(serde_json::to_string(&entry).unwrap())
+        .query(conn)
+        .unwrap_or(());
+    println!(
+        "Audit - Business: {}, Actor: {}, Action: {}",
+        business_name, entry.actor, entry.action
+    );
+    entry
+}
+
+pub fn load_audit(business_name: &str, conn: &mut redis::Connection) -> Vec<AuditEntry> {
+    let entries: Vec<String> = redis::cmd("LRANGE")
+        .arg(audit_redis_key(business_name))
+        .arg(0)
+        .arg(-1)
+        .query(conn)
+        .unwrap_or_default();
+    entries
+        .iter()
+        .filter_map(|entry| serde_json::from_str(entry).ok())
+        .collect()
+}
+
+fn audit_redis_key(business_name: &str) -> String {
+    format!("audit:{}", business_name)
+}
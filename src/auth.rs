@@ -0,0 +1,258 @@
+use crate::business;
+use crate::customer::{self, CustomerId};
+use crate::error::ApiError;
+use crate::secrets::to_hex;
+use crate::{authenticate_token, fetch_data_from_redis, persist_data_to_redis};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// What a token or API key is allowed to do. Roles are ordered, so a higher
+/// role may do everything a lower one can: owners can bill, admins can do
+/// anything for any business. Customers hold widget tokens and only act for
+/// themselves.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Customer,
+    Cashier,
+    Owner,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Customer => "customer",
+            Role::Cashier => "cashier",
+            Role::Owner => "owner",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Role> {
+        match value.trim().to_lowercase().as_str() {
+            "customer" => Some(Role::Customer),
+            "cashier" => Some(Role::Cashier),
+            "owner" => Some(Role::Owner),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+}
+
+/// Long-lived credential stored under `api_key:<key>`. Admin keys are
+/// platform-wide and carry no business.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiKey {
+    pub business_name: Option<String>,
+    pub role: Role,
+    pub label: String,
+}
+
+/// The caller of a request once its token or API key has been checked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Principal {
+    pub business_name: Option<String>,
+    pub role: Role,
+    pub label: String,
+    /// The customer a customer token was issued to.
+    pub customer_key: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AuthError {
+    Unauthenticated(String),
+    Forbidden(String),
+}
+
+/// Checks that `principal` holds at least `required` and, when the route is
+/// scoped to a business, that it belongs to that business.
+pub fn authorize(
+    principal: &Principal,
+    business_name: Option<&str>,
+    required: Role,
+) -> Result<(), AuthError> {
+    if principal.role < required {
+        return Err(AuthError::Forbidden(format!(
+            "Role '{}' required, caller has '{}'.",
+            required.as_str(),
+            principal.role.as_str()
+        )));
+    }
+    if principal.role == Role::Admin {
+        return Ok(());
+    }
+    match business_name {
+        Some(business_name) if principal.business_name.as_deref() != Some(business_name) => {
+            Err(AuthError::Forbidden(format!(
+                "Caller is not allowed to act for business '{}'.",
+                business_name
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+pub fn principal_from_token(
+    token: &str,
+    business_name: &str,
+    conn: &mut redis::Connection,
+) -> Result<Principal, ApiError> {
+    let grant = authenticate_token(token, business_name, conn)?;
+    Ok(Principal {
+        business_name: Some(business_name.to_string()),
+        role: grant.role,
+        label: token_id(token),
+        customer_key: grant.customer_key,
+    })
+}
+
+/// Checks that `principal` may act for the customer whose profile is
+/// `customer_key` at `business_name`. Customer tokens only act for the
+/// profile of the phone verified when they were issued; cashiers and above
+/// act for any customer of their business.
+pub fn authorize_customer(
+    principal: &Principal,
+    business_name: &str,
+    customer_key: &str,
+    conn: &mut redis::Connection,
+) -> Result<(), AuthError> {
+    if principal.role >= Role::Cashier {
+        return Ok(());
+    }
+    let own_profile = principal
+        .customer_key
+        .as_deref()
+        .map(|key| customer::resolve_customer(business_name, &CustomerId::from_key(key), conn));
+    if own_profile.as_deref() != Some(customer_key) {
+        return Err(AuthError::Forbidden(
+            "Token was issued to another customer.".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Identifies a bearer token in logs, counters and review queues without
+/// storing the token itself.
+pub fn token_id(token: &str) -> String {
+    let digest = Sha256::digest(token.as_bytes());
+    format!("token:{}", to_hex(&digest[..8]))
+}
+
+pub fn principal_from_api_key(
+    key: &str,
+    conn: &mut redis::Connection,
+) -> Result<Principal, AuthError> {
+    let data = fetch_data_from_redis(&api_key_redis_key(key), conn);
+    let api_key: ApiKey = serde_json::from_str(&data)
+        .map_err(|_| AuthError::Unauthenticated("Not authorized / Unknown API key.".to_string()))?;
+    // Platform keys carry no business; business keys die with the business.
+    if let Some(business_name) = &api_key.business_name {
+        if !business::is_registered(business_name, conn) {
+            return Err(AuthError::Forbidden(format!(
+                "Business '{}' is not registered.",
+                business_name
+            )));
+        }
+    }
+    Ok(Principal {
+        business_name: api_key.business_name,
+        role: api_key.role,
+        label: api_key.label,
+        customer_key: None,
+    })
+}
+
+/// Creates a new API key and returns it. Only the key itself identifies the
+/// record, so it is shown to the caller once and never listed.
+pub fn issue_api_key(api_key: &ApiKey, conn: &mut redis::Connection) -> String {
+    let key = Uuid::new_v4().simple().to_string();
+    store_api_key(&key, api_key, conn);
+    println!(
+        "Issued API key - Label: {}, Business: {:?}, Role: {}",
+        api_key.label,
+        api_key.business_name,
+        api_key.role.as_str()
+    );
+    key
+}
+
+/// Stores `key` as an API key. Used by `issue_api_key` and to install the
+/// bootstrap admin key from the environment at startup.
+pub fn store_api_key(key: &str, api_key: &ApiKey, conn: &mut redis::Connection) {
+    persist_data_to_redis(
+        &api_key_redis_key(key),
+        serde_json::to_string(api_key).unwrap(),
+        conn,
+    );
+}
+
+fn api_key_redis_key(key: &str) -> String {
+    format!("api_key:{}", key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn principal(business_name: Option<&str>, role: Role) -> Principal {
+        Principal {
+            business_name: business_name.map(str::to_string),
+            role,
+            label: "test".to_string(),
+            customer_key: None,
+        }
+    }
+
+    #[test]
+    fn test_role_parse_round_trip() {
+        for role in [Role::Customer, Role::Cashier, Role::Owner, Role::Admin] {
+            assert_eq!(Role::parse(role.as_str()), Some(role));
+        }
+        assert_eq!(Role::parse(" Owner "), Some(Role::Owner));
+        assert_eq!(Role::parse("clerk"), None);
+    }
+
+    #[test]
+    fn test_cashier_cannot_act_as_owner() {
+        let cashier = principal(Some("test102"), Role::Cashier);
+        assert!(authorize(&cashier, Some("test102"), Role::Cashier).is_ok());
+        assert!(matches!(
+            authorize(&cashier, Some("test102"), Role::Owner),
+            Err(AuthError::Forbidden(_))
+        ));
+    }
+
+    #[test]
+    fn test_customer_cannot_act_as_cashier() {
+        let customer = principal(Some("test102"), Role::Customer);
+        assert!(authorize(&customer, Some("test102"), Role::Customer).is_ok());
+        assert!(matches!(
+            authorize(&customer, Some("test102"), Role::Cashier),
+            Err(AuthError::Forbidden(_))
+        ));
+    }
+
+    #[test]
+    fn test_owner_is_scoped_to_business() {
+        let owner = principal(Some("test102"), Role::Owner);
+        assert!(authorize(&owner, Some("test102"), Role::Cashier).is_ok());
+        assert!(matches!(
+            authorize(&owner, Some("test101"), Role::Owner),
+            Err(AuthError::Forbidden(_))
+        ));
+        assert!(matches!(
+            authorize(&owner, None, Role::Admin),
+            Err(AuthError::Forbidden(_))
+        ));
+    }
+
+    #[test]
+    fn test_admin_can_act_for_any_business() {
+        let admin = principal(None, Role::Admin);
+        assert!(authorize(&admin, Some("test101"), Role::Owner).is_ok());
+        assert!(authorize(&admin, None, Role::Admin).is_ok());
+    }
+}
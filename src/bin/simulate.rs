@@ -0,0 +1,109 @@
+//! Replays a business's stored bills through an alternative policy and
+//! prints what it would have paid out, as JSON. Only reads from Redis.
+//!
+//! theloyalgame-simulate <business> --from 01-Sep-2026 [--to 30-Sep-2026]
+//!     [--pool-percentage 0.05] [--distribution equal|pro_rata]
+//!     [--max-discount 50] [--max-discount-percentage 0.2]
+//!     [--period-days 7] [--min-eligible-spend 100]
+//!     [--redis-url redis://127.0.0.1:6379/]
+
+use chatbot_rust_wasm::policy;
+use chatbot_rust_wasm::simulator::{self, DistributionMode, SimulationPolicy};
+use chrono::NaiveDate;
+use std::process::exit;
+
+const USAGE: &str = "Usage: theloyalgame-simulate <business> --from <dd-Mon-YYYY> [--to <dd-Mon-YYYY>] \
+[--pool-percentage <rate>] [--distribution equal|pro_rata] [--max-discount <amount>] \
+[--max-discount-percentage <fraction>] [--period-days <days>] [--min-eligible-spend <amount>] \
+[--redis-url <url>]";
+
+struct Args {
+    business_name: String,
+    from: NaiveDate,
+    to: NaiveDate,
+    pool_percentage: Option<f64>,
+    policy: SimulationPolicy,
+    redis_url: String,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let business_name = args.next().filter(|arg| !arg.starts_with("--")).ok_or("Missing business name")?;
+    let mut from = None;
+    let mut to = chrono::Utc::now().date_naive();
+    let mut pool_percentage = None;
+    let mut policy = SimulationPolicy::default();
+    let mut redis_url = "redis://127.0.0.1:6379/".to_string();
+
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or_else(|| format!("Missing value for {}", flag))?;
+        let number = || value.parse::<f64>().map_err(|_| format!("{} needs a number, not {}", flag, value));
+        let date = || {
+            NaiveDate::parse_from_str(&value, "%d-%b-%Y")
+                .map_err(|_| format!("{} needs a date like 19-Oct-2026, not {}", flag, value))
+        };
+        match flag.as_str() {
+            "--from" => from = Some(date()?),
+            "--to" => to = date()?,
+            "--pool-percentage" => pool_percentage = Some(number()?),
+            "--distribution" => {
+                policy.distribution = match value.as_str() {
+                    "equal" => DistributionMode::Equal,
+                    "pro_rata" => DistributionMode::ProRata,
+                    _ => return Err(format!("Unknown distribution: {}", value)),
+                }
+            }
+            "--max-discount" => policy.max_discount = Some(number()?),
+            "--max-discount-percentage" => policy.max_discount_percentage = Some(number()?),
+            "--period-days" => {
+                policy.period_days = value
+                    .parse()
+                    .map_err(|_| format!("--period-days needs a whole number, not {}", value))?
+            }
+            "--min-eligible-spend" => policy.min_eligible_spend = number()?,
+            "--redis-url" => redis_url = value,
+            _ => return Err(format!("Unknown option: {}", flag)),
+        }
+    }
+    let from = from.ok_or("--from is required")?;
+    if to < from {
+        return Err("--to must not be before --from".to_string());
+    }
+    Ok(Args {
+        business_name,
+        from,
+        to,
+        pool_percentage,
+        policy,
+        redis_url,
+    })
+}
+
+fn main() {
+    let args = parse_args(std::env::args().skip(1)).unwrap_or_else(|message| {
+        eprintln!("{}\n{}", message, USAGE);
+        exit(2);
+    });
+    let client = redis::Client::open(args.redis_url.as_str()).expect("Invalid Redis URL");
+    let mut conn = client.get_connection().expect("Failed to get Redis connection");
+
+    // Without --pool-percentage, the rate the business uses today.
+    let mut simulation_policy = args.policy;
+    simulation_policy.pool_percentage = args
+        .pool_percentage
+        .unwrap_or_else(|| policy::load_policy(&args.business_name, &mut conn).pool_percentage);
+    if let Err(message) = simulation_policy.validate() {
+        eprintln!("{}", message);
+        exit(2);
+    }
+
+    let bills = simulator::load_history(&args.business_name, args.from, args.to, &mut conn);
+    eprintln!(
+        "Replaying {} bills of {} from {} to {}",
+        bills.len(),
+        args.business_name,
+        args.from.format("%d-%b-%Y"),
+        args.to.format("%d-%b-%Y")
+    );
+    let report = simulator::simulate(&bills, args.from, &simulation_policy);
+    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+}
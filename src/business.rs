@@ -0,0 +1,78 @@
+use crate::error::{ApiError, ErrorCode};
+use crate::{fetch_data_from_redis, persist_data_to_redis};
+
+static BUSINESSES_REDIS_KEY: &str = "businesses";
+
+/// Names of the businesses registered by platform admins, kept as a JSON
+/// array under `businesses`.
+pub fn list_businesses(conn: &mut redis::Connection) -> Vec<String> {
+    let data = fetch_data_from_redis(BUSINESSES_REDIS_KEY, conn);
+    serde_json::from_str(&data).unwrap_or_default()
+}
+
+/// Registers `business_name`. Returns `false` if it was already registered.
+pub fn register_business(business_name: &str, conn: &mut redis::Connection) -> bool {
+    let mut businesses = list_businesses(conn);
+    if businesses.iter().any(|name| name == business_name) {
+        return false;
+    }
+    businesses.push(business_name.to_string());
+    businesses.sort();
+    persist_data_to_redis(
+        BUSINESSES_REDIS_KEY,
+        serde_json::to_string(&businesses).unwrap(),
+        conn,
+    );
+    true
+}
+
+/// Whether platform admins registered `business_name`.
+pub fn is_registered(business_name: &str, conn: &mut redis::Connection) -> bool {
+    list_businesses(conn).iter().any(|name| name == business_name)
+}
+
+/// Refuses businesses that are not registered, or were removed.
+pub fn require_registered(business_name: &str, conn: &mut redis::Connection) -> Result<(), ApiError> {
+    if !is_registered(business_name, conn) {
+        return Err(ApiError::new(
+            ErrorCode::Forbidden,
+            format!("Business '{}' is not registered.", business_name),
+        ));
+    }
+    Ok(())
+}
+
+/// Removes `business_name` from the registry. Its weekly data is left in
+/// place so that reports stay available; its tokens, API keys and terminals
+/// are refused until it is registered again. Returns `false` if it was
+/// unknown.
+pub fn remove_business(business_name: &str, conn: &mut redis::Connection) -> bool {
+    let mut businesses = list_businesses(conn);
+    let before = businesses.len();
+    businesses.retain(|name| name != business_name);
+    if businesses.len() == before {
+        return false;
+    }
+    persist_data_to_redis(
+        BUSINESSES_REDIS_KEY,
+        serde_json::to_string(&businesses).unwrap(),
+        conn,
+    );
+    true
+}
+
+pub fn validate_business_name(business_name: &str) -> Result<(), String> {
+    let valid = !business_name.is_empty()
+        && business_name.len() <= 64
+        && business_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    // "___" separates the business from the week in period keys.
+    if !valid || business_name.contains("___") {
+        return Err(
+            "Business name must be 1-64 letters, digits, '-' or '_' and must not contain '___'"
+                .to_string(),
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,308 @@
+use crate::{fetch_data_from_redis, persist_data_to_redis};
+use chrono::{DateTime, Datelike, Duration, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+const WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// A promotion that boosts the pool for a while, e.g. double rewards this
+/// weekend or a 5% pool from 3 to 5pm. A business's campaigns are stored
+/// together under `campaigns:<business>`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct Campaign {
+    /// Assigned on creation.
+    #[serde(default)]
+    pub campaign_id: String,
+    pub name: String,
+    pub schedule: CampaignSchedule,
+    /// Outlets the campaign runs at; all of them when empty.
+    #[serde(default)]
+    pub outlet_ids: Vec<String>,
+    /// Terminals the campaign runs at; all of them when empty.
+    #[serde(default)]
+    pub terminal_ids: Vec<String>,
+    /// Replaces the policy's `pool_percentage` for bills it applies to.
+    pub pool_percentage: Option<f64>,
+    /// Multiplies the pool share paid out on bills it applies to.
+    pub share_multiplier: Option<f64>,
+}
+
+/// When a campaign runs. `days` and the daily window are in local time,
+/// `utc_offset_minutes` ahead of UTC.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct CampaignSchedule {
+    /// RFC 3339.
+    pub starts_at: String,
+    /// RFC 3339, exclusive.
+    pub ends_at: String,
+    /// `mon` to `sun`; every day when empty.
+    #[serde(default)]
+    pub days: Vec<String>,
+    /// `HH:MM`. A window ending before it starts runs past midnight.
+    pub daily_from: Option<String>,
+    pub daily_until: Option<String>,
+    #[serde(default)]
+    pub utc_offset_minutes: i32,
+}
+
+/// The combined effect of the campaigns active for one bill. Overlapping
+/// campaigns don't stack; the most generous value of each kind wins.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CampaignBoost {
+    pub campaign_ids: Vec<String>,
+    pub pool_percentage: Option<f64>,
+    pub share_multiplier: f64,
+}
+
+impl Campaign {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("name is required".to_string());
+        }
+        let (starts_at, ends_at) = (
+            parse_datetime(&self.schedule.starts_at).ok_or("starts_at must be an RFC 3339 time")?,
+            parse_datetime(&self.schedule.ends_at).ok_or("ends_at must be an RFC 3339 time")?,
+        );
+        if ends_at <= starts_at {
+            return Err("ends_at must be after starts_at".to_string());
+        }
+        if let Some(day) = self.schedule.days.iter().find(|day| !WEEKDAYS.contains(&day.as_str())) {
+            return Err(format!("Unknown day: {}", day));
+        }
+        match (&self.schedule.daily_from, &self.schedule.daily_until) {
+            (None, None) => {}
+            (Some(from), Some(until)) if parse_time(from).is_some() && parse_time(until).is_some() => {}
+            _ => return Err("daily_from and daily_until must both be HH:MM".to_string()),
+        }
+        if self.schedule.utc_offset_minutes.abs() > 14 * 60 {
+            return Err("utc_offset_minutes is out of range".to_string());
+        }
+        if self.pool_percentage.is_none() && self.share_multiplier.is_none() {
+            return Err("A campaign needs a pool_percentage or a share_multiplier".to_string());
+        }
+        if self.pool_percentage.is_some_and(|rate| !(0.0..=1.0).contains(&rate)) {
+            return Err("pool_percentage must be between 0 and 1".to_string());
+        }
+        if self.share_multiplier.is_some_and(|multiplier| !(1.0..=10.0).contains(&multiplier)) {
+            return Err("share_multiplier must be between 1 and 10".to_string());
+        }
+        Ok(())
+    }
+
+    /// Whether the campaign applies to a bill at `now` from `terminal_id`
+    /// at `outlet_id`.
+    pub fn is_active(&self, now: DateTime<Utc>, terminal_id: Option<&str>, outlet_id: Option<&str>) -> bool {
+        let targets = |ids: &[String], id: Option<&str>| {
+            ids.is_empty() || id.is_some_and(|id| ids.iter().any(|target| target == id))
+        };
+        if !targets(&self.terminal_ids, terminal_id) || !targets(&self.outlet_ids, outlet_id) {
+            return false;
+        }
+        let schedule = &self.schedule;
+        let in_range = matches!(
+            (parse_datetime(&schedule.starts_at), parse_datetime(&schedule.ends_at)),
+            (Some(starts_at), Some(ends_at)) if starts_at <= now && now < ends_at
+        );
+        if !in_range {
+            return false;
+        }
+        let local = now.naive_utc() + Duration::minutes(schedule.utc_offset_minutes as i64);
+        let weekday = WEEKDAYS[local.weekday().num_days_from_monday() as usize];
+        if !schedule.days.is_empty() && !schedule.days.iter().any(|day| day == weekday) {
+            return false;
+        }
+        match (
+            schedule.daily_from.as_deref().and_then(parse_time),
+            schedule.daily_until.as_deref().and_then(parse_time),
+        ) {
+            (Some(from), Some(until)) if from <= until => (from..until).contains(&local.time()),
+            (Some(from), Some(until)) => local.time() >= from || local.time() < until,
+            _ => true,
+        }
+    }
+}
+
+/// What the campaigns in `campaigns` add up to for a bill at `now`.
+pub fn active_boost(
+    campaigns: &[Campaign],
+    now: DateTime<Utc>,
+    terminal_id: Option<&str>,
+    outlet_id: Option<&str>,
+) -> CampaignBoost {
+    let mut boost = CampaignBoost {
+        share_multiplier: 1.0,
+        ..CampaignBoost::default()
+    };
+    for campaign in campaigns.iter().filter(|campaign| campaign.is_active(now, terminal_id, outlet_id)) {
+        boost.campaign_ids.push(campaign.campaign_id.clone());
+        if let Some(rate) = campaign.pool_percentage {
+            boost.pool_percentage = Some(boost.pool_percentage.map_or(rate, |current| current.max(rate)));
+        }
+        if let Some(multiplier) = campaign.share_multiplier {
+            boost.share_multiplier = boost.share_multiplier.max(multiplier);
+        }
+    }
+    boost
+}
+
+pub fn load_campaigns(business_name: &str, conn: &mut redis::Connection) -> Vec<Campaign> {
+    serde_json::from_str(&fetch_data_from_redis(&campaigns_redis_key(business_name), conn)).unwrap_or_default()
+}
+
+/// Validates `campaign`, gives it an id and adds it to `business_name`.
+pub fn create_campaign(
+    business_name: &str,
+    mut campaign: Campaign,
+    conn: &mut redis::Connection,
+) -> Result<Campaign, String> {
+    campaign.validate()?;
+    campaign.campaign_id = format!("camp_{}", &Uuid::new_v4().simple().to_string()[..12]);
+    let mut campaigns = load_campaigns(business_name, conn);
+    campaigns.push(campaign.clone());
+    save_campaigns(business_name, &campaigns, conn);
+    println!(
+        "Campaign created - Business: {}, Id: {}, Name: {}",
+        business_name, campaign.campaign_id, campaign.name
+    );
+    Ok(campaign)
+}
+
+/// Returns false if `business_name` has no campaign `campaign_id`.
+pub fn delete_campaign(business_name: &str, campaign_id: &str, conn: &mut redis::Connection) -> bool {
+    let mut campaigns = load_campaigns(business_name, conn);
+    let count = campaigns.len();
+    campaigns.retain(|campaign| campaign.campaign_id != campaign_id);
+    if campaigns.len() == count {
+        return false;
+    }
+    save_campaigns(business_name, &campaigns, conn);
+    true
+}
+
+fn save_campaigns(business_name: &str, campaigns: &[Campaign], conn: &mut redis::Connection) {
+    persist_data_to_redis(
+        &campaigns_redis_key(business_name),
+        serde_json::to_string(campaigns).unwrap(),
+        conn,
+    );
+}
+
+fn campaigns_redis_key(business_name: &str) -> String {
+    format!("campaigns:{}", business_name)
+}
+
+fn parse_datetime(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value).ok().map(|time| time.with_timezone(&Utc))
+}
+
+fn parse_time(value: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(value, "%H:%M").ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn campaign(schedule: CampaignSchedule) -> Campaign {
+        Campaign {
+            campaign_id: "camp_1".to_string(),
+            name: "Happy hour".to_string(),
+            schedule,
+            outlet_ids: Vec::new(),
+            terminal_ids: Vec::new(),
+            pool_percentage: Some(0.05),
+            share_multiplier: None,
+        }
+    }
+
+    fn schedule() -> CampaignSchedule {
+        CampaignSchedule {
+            starts_at: "2026-10-01T00:00:00Z".to_string(),
+            ends_at: "2026-11-01T00:00:00Z".to_string(),
+            days: Vec::new(),
+            daily_from: None,
+            daily_until: None,
+            utc_offset_minutes: 0,
+        }
+    }
+
+    fn at(value: &str) -> DateTime<Utc> {
+        parse_datetime(value).unwrap()
+    }
+
+    #[test]
+    fn test_daily_window_in_local_time() {
+        // 3-5pm in India, UTC+5:30.
+        let happy_hour = campaign(CampaignSchedule {
+            daily_from: Some("15:00".to_string()),
+            daily_until: Some("17:00".to_string()),
+            utc_offset_minutes: 330,
+            ..schedule()
+        });
+        assert!(happy_hour.validate().is_ok());
+        assert!(happy_hour.is_active(at("2026-10-19T09:30:00Z"), None, None));
+        assert!(!happy_hour.is_active(at("2026-10-19T11:30:00Z"), None, None));
+        assert!(!happy_hour.is_active(at("2026-11-02T09:30:00Z"), None, None));
+
+        let overnight = campaign(CampaignSchedule {
+            daily_from: Some("22:00".to_string()),
+            daily_until: Some("02:00".to_string()),
+            ..schedule()
+        });
+        assert!(overnight.is_active(at("2026-10-19T23:00:00Z"), None, None));
+        assert!(overnight.is_active(at("2026-10-20T01:00:00Z"), None, None));
+        assert!(!overnight.is_active(at("2026-10-20T03:00:00Z"), None, None));
+    }
+
+    #[test]
+    fn test_weekend_campaign_for_one_outlet() {
+        let weekend = Campaign {
+            terminal_ids: vec!["term_a".to_string()],
+            pool_percentage: None,
+            share_multiplier: Some(2.0),
+            ..campaign(CampaignSchedule {
+                days: vec!["sat".to_string(), "sun".to_string()],
+                ..schedule()
+            })
+        };
+        // 24 October 2026 is a Saturday.
+        assert!(weekend.is_active(at("2026-10-24T12:00:00Z"), Some("term_a"), None));
+        assert!(!weekend.is_active(at("2026-10-24T12:00:00Z"), Some("term_b"), None));
+        assert!(!weekend.is_active(at("2026-10-24T12:00:00Z"), None, None));
+        assert!(!weekend.is_active(at("2026-10-23T12:00:00Z"), Some("term_a"), None));
+
+        let branch = Campaign {
+            outlet_ids: vec!["out_a".to_string()],
+            ..campaign(schedule())
+        };
+        assert!(branch.is_active(at("2026-10-24T12:00:00Z"), Some("term_b"), Some("out_a")));
+        assert!(!branch.is_active(at("2026-10-24T12:00:00Z"), Some("term_b"), Some("out_b")));
+
+        let boost = active_boost(
+            &[weekend, campaign(schedule())],
+            at("2026-10-24T12:00:00Z"),
+            Some("term_a"),
+            None,
+        );
+        assert_eq!(boost.campaign_ids.len(), 2);
+        assert_eq!(boost.pool_percentage, Some(0.05));
+        assert_eq!(boost.share_multiplier, 2.0);
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_campaigns() {
+        let mut bad = campaign(schedule());
+        bad.schedule.ends_at = bad.schedule.starts_at.clone();
+        assert!(bad.validate().is_err());
+        let mut bad = campaign(schedule());
+        bad.pool_percentage = None;
+        assert!(bad.validate().is_err());
+        let mut bad = campaign(schedule());
+        bad.schedule.days = vec!["someday".to_string()];
+        assert!(bad.validate().is_err());
+        let mut bad = campaign(schedule());
+        bad.schedule.daily_from = Some("15:00".to_string());
+        assert!(bad.validate().is_err());
+    }
+}
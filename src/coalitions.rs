@@ -0,0 +1,229 @@
+use crate::error::{ApiError, ErrorCode};
+use crate::{business, fetch_data_from_redis, persist_data_to_redis, transactions};
+use chrono::{Duration, NaiveDate};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+static COALITIONS_REDIS_KEY: &str = "coalitions";
+
+/// Businesses that run one loyalty pool together: spending at any member
+/// pays into it and its shares are paid out at all of them. All coalitions
+/// are kept as a JSON array under `coalitions`, dissolved ones included.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct Coalition {
+    pub coalition_id: String,
+    pub name: String,
+    pub members: Vec<String>,
+    /// `false` once dissolved. A dissolved coalition pays nothing out but
+    /// can still be settled.
+    #[serde(default = "default_active")]
+    pub active: bool,
+}
+
+fn default_active() -> bool {
+    true
+}
+
+/// Who owes whom for one week of a coalition's payouts. The shares paid out
+/// in a week come from the pool collected the week before, so each member
+/// carries the week's payouts in proportion to what it put into that pool.
+#[derive(Serialize, Debug, PartialEq, ToSchema)]
+pub struct CoalitionSettlement {
+    pub coalition_id: String,
+    pub name: String,
+    /// Week whose payouts are settled.
+    pub period_start: String,
+    /// Week whose bills filled the pool those payouts came from.
+    pub pool_period_start: String,
+    pub total_contributed: f64,
+    pub total_funded: f64,
+    pub members: Vec<MemberSettlement>,
+}
+
+#[derive(Serialize, Debug, PartialEq, ToSchema)]
+pub struct MemberSettlement {
+    pub business_name: String,
+    /// Put into the pool by this member's bills in the pool week.
+    pub contributed: f64,
+    /// Pool shares this member granted on its bills in the settled week.
+    pub funded: f64,
+    /// This member's part of `total_funded`, by its contributions.
+    pub share_of_payouts: f64,
+    /// `funded - share_of_payouts`. Positive when the other members owe this
+    /// one, negative when it owes them. Balances add up to zero.
+    pub balance: f64,
+}
+
+pub fn list_coalitions(conn: &mut redis::Connection) -> Vec<Coalition> {
+    serde_json::from_str(&fetch_data_from_redis(COALITIONS_REDIS_KEY, conn)).unwrap_or_default()
+}
+
+pub fn load_coalition(coalition_id: &str, conn: &mut redis::Connection) -> Option<Coalition> {
+    list_coalitions(conn)
+        .into_iter()
+        .find(|coalition| coalition.coalition_id == coalition_id)
+}
+
+/// The active coalition `business_name` belongs to, if any.
+pub fn coalition_of(business_name: &str, conn: &mut redis::Connection) -> Option<Coalition> {
+    list_coalitions(conn)
+        .into_iter()
+        .find(|coalition| coalition.active && coalition.members.iter().any(|member| member == business_name))
+}
+
+/// Forms a coalition of registered businesses. A business can only be in one.
+pub fn create_coalition(
+    name: &str,
+    members: &[String],
+    conn: &mut redis::Connection,
+) -> Result<Coalition, ApiError> {
+    let name = name.trim();
+    if name.is_empty() || name.len() > 64 {
+        return Err(ApiError::invalid_request("Coalition name must be 1-64 characters"));
+    }
+    let mut members = members.to_vec();
+    members.sort();
+    members.dedup();
+    if members.len() < 2 {
+        return Err(ApiError::invalid_request("A coalition needs at least two members"));
+    }
+    let registered = business::list_businesses(conn);
+    if let Some(unknown) = members.iter().find(|member| !registered.contains(member)) {
+        return Err(ApiError::invalid_request(format!("Business not registered: {}", unknown)));
+    }
+    let mut coalitions = list_coalitions(conn);
+    for coalition in coalitions.iter().filter(|coalition| coalition.active) {
+        if let Some(taken) = members.iter().find(|member| coalition.members.contains(member)) {
+            return Err(ApiError::new(
+                ErrorCode::Conflict,
+                format!("{} is already in coalition {}", taken, coalition.coalition_id),
+            ));
+        }
+    }
+    let coalition = Coalition {
+        coalition_id: format!("coal_{}", &Uuid::new_v4().simple().to_string()[..8]),
+        name: name.to_string(),
+        members,
+        active: true,
+    };
+    coalitions.push(coalition.clone());
+    persist_data_to_redis(COALITIONS_REDIS_KEY, serde_json::to_string(&coalitions).unwrap(), conn);
+    println!(
+        "Coalition created - Id: {}, Members: {:?}",
+        coalition.coalition_id, coalition.members
+    );
+    Ok(coalition)
+}
+
+/// Dissolves a coalition. Its members go back to their own pools from the
+/// next bill; the coalition is kept, marked inactive, and so is its weekly
+/// data, so past weeks can still be settled. Returns the dissolved
+/// coalition, or `None` if it was unknown or already dissolved.
+pub fn delete_coalition(coalition_id: &str, conn: &mut redis::Connection) -> Option<Coalition> {
+    let mut coalitions = list_coalitions(conn);
+    let coalition = coalitions
+        .iter_mut()
+        .find(|coalition| coalition.active && coalition.coalition_id == coalition_id)?;
+    coalition.active = false;
+    let dissolved = coalition.clone();
+    persist_data_to_redis(COALITIONS_REDIS_KEY, serde_json::to_string(&coalitions).unwrap(), conn);
+    println!("Coalition dissolved - Id: {}", coalition_id);
+    Some(dissolved)
+}
+
+/// Name under which the pool of `coalition_id` is stored, in place of the
+/// business name in period keys. Business names can't contain `:`.
+pub fn coalition_pool_name(coalition_id: &str) -> String {
+    format!("coalition:{}", coalition_id)
+}
+
+/// Settles the payouts made in the week starting `monday`, from the members'
+/// transaction logs.
+pub fn settlement(coalition: &Coalition, monday: NaiveDate, conn: &mut redis::Connection) -> CoalitionSettlement {
+    let pool_monday = monday - Duration::days(7);
+    let mut members: Vec<MemberSettlement> = coalition
+        .members
+        .iter()
+        .map(|business_name| {
+            let in_coalition = |record: &transactions::TransactionRecord| {
+                record.coalition_id.as_deref() == Some(coalition.coalition_id.as_str())
+            };
+            let mut contributed = 0.0;
+            let mut funded = 0.0;
+            for day in 0..7 {
+                for record in transactions::load_transactions(business_name, pool_monday + Duration::days(day), conn) {
+                    if in_coalition(&record) {
+                        contributed += record.pool_contribution.unwrap_or(0.0);
+                    }
+                }
+                for record in transactions::load_transactions(business_name, monday + Duration::days(day), conn) {
+                    if in_coalition(&record) {
+                        funded += record.pool_share.unwrap_or(0.0);
+                    }
+                }
+            }
+            MemberSettlement {
+                business_name: business_name.clone(),
+                contributed,
+                funded,
+                share_of_payouts: 0.0,
+                balance: 0.0,
+            }
+        })
+        .collect();
+    let total_contributed: f64 = members.iter().map(|member| member.contributed).sum();
+    let total_funded: f64 = members.iter().map(|member| member.funded).sum();
+    apportion(&mut members, total_contributed, total_funded);
+    CoalitionSettlement {
+        coalition_id: coalition.coalition_id.clone(),
+        name: coalition.name.clone(),
+        period_start: monday.format("%d-%b-%Y").to_string(),
+        pool_period_start: pool_monday.format("%d-%b-%Y").to_string(),
+        total_contributed,
+        total_funded,
+        members,
+    }
+}
+
+fn apportion(members: &mut [MemberSettlement], total_contributed: f64, total_funded: f64) {
+    let member_count = members.len() as f64;
+    for member in members.iter_mut() {
+        // With no contributions on record, members carry the payouts equally.
+        member.share_of_payouts = if total_contributed > 0.0 {
+            total_funded * member.contributed / total_contributed
+        } else {
+            total_funded / member_count
+        };
+        member.balance = member.funded - member.share_of_payouts;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn member(business_name: &str, contributed: f64, funded: f64) -> MemberSettlement {
+        MemberSettlement {
+            business_name: business_name.to_string(),
+            contributed,
+            funded,
+            share_of_payouts: 0.0,
+            balance: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_apportion_balances_sum_to_zero() {
+        let mut members = vec![member("bakery", 30.0, 10.0), member("grocer", 10.0, 30.0)];
+        apportion(&mut members, 40.0, 40.0);
+        assert_eq!(members[0].share_of_payouts, 30.0);
+        assert_eq!(members[0].balance, -20.0);
+        assert_eq!(members[1].balance, 20.0);
+        assert_eq!(coalition_pool_name("coal_1234abcd"), "coalition:coal_1234abcd");
+
+        let mut members = vec![member("bakery", 0.0, 30.0), member("grocer", 0.0, 0.0)];
+        apportion(&mut members, 0.0, 30.0);
+        assert_eq!((members[0].balance, members[1].balance), (15.0, -15.0));
+    }
+}
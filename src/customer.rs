@@ -0,0 +1,446 @@
+use crate::error::{ApiError, ErrorCode};
+use crate::{
+    audit, coalitions, delete_data_from_redis, escape_redis_glob, fetch_data_from_redis, fraud, get_latest_monday, list_redis_keys,
+    outlets, parse_customer_discount_details, period_redis_key, persist_data_to_redis, phone, pseudonym, referrals, streaks, transactions, wallet,
+    CustomerDiscountDetails,
+};
+use chrono::{Datelike, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+const CARD_PREFIX: &str = "LC";
+const CARD_DIGITS: std::ops::RangeInclusive<usize> = 8..=16;
+
+/// How a customer identifies themselves at the counter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CustomerId {
+    /// E.164 phone number.
+    Phone(String),
+    /// Printed loyalty card number, `LC` followed by 8 to 16 digits.
+    Card(String),
+    /// Lower-cased email address.
+    Email(String),
+}
+
+impl CustomerId {
+    /// Reads what the customer or cashier typed. Emails are recognised by
+    /// their `@`, card numbers by their `LC` prefix; anything else must be a
+    /// phone number of `default_region`.
+    pub fn parse(input: &str, default_region: &str) -> Result<CustomerId, ApiError> {
+        let input = input.trim();
+        if input.contains('@') {
+            return parse_email(input).map(CustomerId::Email);
+        }
+        if input
+            .get(..CARD_PREFIX.len())
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case(CARD_PREFIX))
+        {
+            return parse_card(input).map(CustomerId::Card);
+        }
+        phone::normalize_phone_number(input, default_region)
+            .map(CustomerId::Phone)
+            .map_err(|message| ApiError::new(ErrorCode::InvalidPhoneNumber, message))
+    }
+
+    /// The form used in Redis keys and weekly blobs. Phones stay bare so that
+    /// records written before other identifiers existed keep resolving, or
+    /// are pseudonymized when a pepper is installed.
+    pub fn key(&self) -> String {
+        match self {
+            CustomerId::Phone(phone_number) => pseudonym::phone_key(phone_number),
+            CustomerId::Card(card) => format!("card:{}", card),
+            CustomerId::Email(email) => format!("email:{}", email),
+        }
+    }
+
+    /// Inverse of `key`. A pseudonymized phone comes back as its pseudonym.
+    pub fn from_key(key: &str) -> CustomerId {
+        if let Some(card) = key.strip_prefix("card:") {
+            CustomerId::Card(card.to_string())
+        } else if let Some(email) = key.strip_prefix("email:") {
+            CustomerId::Email(email.to_string())
+        } else {
+            CustomerId::Phone(key.to_string())
+        }
+    }
+
+    pub fn is_phone(&self) -> bool {
+        matches!(self, CustomerId::Phone(_))
+    }
+}
+
+fn parse_card(input: &str) -> Result<String, ApiError> {
+    let digits: String = input[CARD_PREFIX.len()..]
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '-'))
+        .collect();
+    if !CARD_DIGITS.contains(&digits.len()) || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(ApiError::new(
+            ErrorCode::InvalidCustomerId,
+            format!("Invalid loyalty card number: {}", input),
+        ));
+    }
+    Ok(format!("{}{}", CARD_PREFIX, digits))
+}
+
+fn parse_email(input: &str) -> Result<String, ApiError> {
+    let email = input.to_lowercase();
+    let valid = email.len() <= 254
+        && !email.chars().any(|c| c.is_whitespace() || c == ':')
+        && matches!(email.split_once('@'), Some((local, domain))
+            if !local.is_empty() && !domain.contains('@') && domain.contains('.')
+                && !domain.starts_with('.') && !domain.ends_with('.'));
+    if !valid {
+        return Err(ApiError::new(
+            ErrorCode::InvalidCustomerId,
+            format!("Invalid email address: {}", input),
+        ));
+    }
+    Ok(email)
+}
+
+/// The identifiers that resolve to one loyalty profile. The profile is
+/// named after the first identifier it was created with; that key is what
+/// the weekly blobs, transactions and feedback are stored under.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct CustomerProfile {
+    pub profile_id: String,
+    pub identifiers: Vec<String>,
+}
+
+/// Returns the profile key `id` belongs to, or its own key if it was never
+/// linked.
+pub fn resolve_customer(business_name: &str, id: &CustomerId, conn: &mut redis::Connection) -> String {
+    let profile_id = fetch_data_from_redis(&link_redis_key(business_name, &id.key()), conn);
+    if profile_id.is_empty() {
+        id.key()
+    } else {
+        profile_id
+    }
+}
+
+pub fn load_profile(business_name: &str, profile_id: &str, conn: &mut redis::Connection) -> CustomerProfile {
+    serde_json::from_str(&fetch_data_from_redis(&profile_redis_key(business_name, profile_id), conn))
+        .unwrap_or_else(|_| CustomerProfile {
+            profile_id: profile_id.to_string(),
+            identifiers: vec![profile_id.to_string()],
+        })
+}
+
+/// Links `new_id` to the profile of `existing_id`, so discounts and history
+/// follow the person whichever identifier they give. An identifier that
+/// already has its own profile or recent history is refused; those have to
+/// be merged instead.
+pub fn link_customer_id(
+    business_name: &str,
+    existing_id: &CustomerId,
+    new_id: &CustomerId,
+    conn: &mut redis::Connection,
+) -> Result<CustomerProfile, ApiError> {
+    let profile_id = resolve_customer(business_name, existing_id, conn);
+    let mut profile = load_profile(business_name, &profile_id, conn);
+    let new_key = new_id.key();
+    if profile.identifiers.contains(&new_key) {
+        return Ok(profile);
+    }
+
+    let conflict = || {
+        ApiError::new(
+            ErrorCode::Conflict,
+            format!("{} already belongs to another customer", new_key),
+        )
+    };
+    if resolve_customer(business_name, new_id, conn) != new_key
+        || !fetch_data_from_redis(&profile_redis_key(business_name, &new_key), conn).is_empty()
+        || has_recent_history(business_name, &new_key, conn)
+    {
+        return Err(conflict());
+    }
+
+    profile.identifiers.push(new_key.clone());
+    persist_data_to_redis(&link_redis_key(business_name, &new_key), profile_id.clone(), conn);
+    persist_data_to_redis(
+        &profile_redis_key(business_name, &profile_id),
+        serde_json::to_string(&profile).unwrap(),
+        conn,
+    );
+    println!(
+        "Linked customer id - Business: {}, Profile: {}, Id: {}",
+        business_name, profile_id, new_key
+    );
+    Ok(profile)
+}
+
+#[derive(Serialize, Debug, Default, PartialEq, ToSchema)]
+pub struct CustomerMergeReport {
+    /// The surviving profile, now holding the identifiers of both.
+    pub profile: Option<CustomerProfile>,
+    /// Weekly blobs the merged customer appeared in.
+    pub periods_updated: usize,
+    /// Weekly blobs where both customers appeared, so one eligible customer
+    /// was removed from the count.
+    pub periods_combined: usize,
+    pub feedback_moved: usize,
+    pub transaction_logs_updated: usize,
+    /// Referral codes of `from` that now credit the surviving profile.
+    pub referral_codes_moved: usize,
+    /// `from` was blocked by a fraud review, so `into` now is.
+    pub block_moved: bool,
+}
+
+/// Folds the customer `from` into `into` for `business_name`: weekly history
+/// in every period, linked identifiers, feedback, transaction logs, referral
+/// codes and fraud blocks. `from` keeps resolving to the merged profile, so
+/// widget tokens issued to either phone keep working; they are not tied to
+/// one business and are left alone.
+///
+/// Feedback keys don't name the business, so only feedback recorded for
+/// `business_name` moves. Feedback stored before it recorded its business
+/// can't be told apart and moves as well.
+/// A phone number change is a merge of the old number into the new one.
+/// The merge is recorded in the business's audit log under `actor`.
+pub fn merge_customers(
+    business_name: &str,
+    from: &CustomerId,
+    into: &CustomerId,
+    actor: &str,
+    conn: &mut redis::Connection,
+) -> Result<CustomerMergeReport, ApiError> {
+    let from_profile_id = resolve_customer(business_name, from, conn);
+    let into_profile_id = resolve_customer(business_name, into, conn);
+    if from_profile_id == into_profile_id {
+        return Err(ApiError::new(
+            ErrorCode::Conflict,
+            format!("{} and {} are already the same customer", from.key(), into.key()),
+        ));
+    }
+    let mut report = CustomerMergeReport::default();
+
+    let mut period_keys = list_redis_keys(&format!("{}___*", business_name), conn);
+    period_keys.extend(list_redis_keys(&format!("{}@*___*", business_name), conn));
+    if let Some(coalition) = coalitions::coalition_of(business_name, conn) {
+        let pool_name = coalitions::coalition_pool_name(&coalition.coalition_id);
+        period_keys.extend(list_redis_keys(&format!("{}___*", pool_name), conn));
+    }
+    for key in period_keys {
+        let mut details = parse_customer_discount_details(&fetch_data_from_redis(&key, conn));
+        if let Some(combined) = merge_expenses(&mut details, &from_profile_id, &into_profile_id) {
+            persist_data_to_redis(&key, serde_json::to_string(&details).unwrap(), conn);
+            report.periods_updated += 1;
+            report.periods_combined += combined as usize;
+        }
+    }
+
+    let from_profile = load_profile(business_name, &from_profile_id, conn);
+    let mut profile = load_profile(business_name, &into_profile_id, conn);
+    for identifier in &from_profile.identifiers {
+        if !profile.identifiers.contains(identifier) {
+            profile.identifiers.push(identifier.clone());
+        }
+        persist_data_to_redis(&link_redis_key(business_name, identifier), into_profile_id.clone(), conn);
+    }
+    delete_data_from_redis(&profile_redis_key(business_name, &from_profile_id), conn);
+    persist_data_to_redis(
+        &profile_redis_key(business_name, &into_profile_id),
+        serde_json::to_string(&profile).unwrap(),
+        conn,
+    );
+
+    let feedback_pattern = format!("feedback:{}:*", escape_redis_glob(&from_profile_id));
+    for key in list_redis_keys(&feedback_pattern, conn) {
+        let timestamp = &key[format!("feedback:{}:", from_profile_id).len()..];
+        let mut feedback: serde_json::Value =
+            serde_json::from_str(&fetch_data_from_redis(&key, conn)).unwrap_or_default();
+        let recorded_for = feedback.get("business_name").and_then(|value| value.as_str());
+        if recorded_for.is_some_and(|recorded_for| recorded_for != business_name) {
+            continue;
+        }
+        if let Some(value) = feedback.get_mut("customer_id") {
+            *value = serde_json::Value::String(into_profile_id.clone());
+        }
+        persist_data_to_redis(&format!("feedback:{}:{}", into_profile_id, timestamp), feedback.to_string(), conn);
+        delete_data_from_redis(&key, conn);
+        report.feedback_moved += 1;
+    }
+
+    for key in list_redis_keys(&format!("transactions:{}:*", business_name), conn) {
+        let mut records = transactions::load_transactions_at(&key, conn);
+        let mut changed = false;
+        for record in records.iter_mut().filter(|record| record.customer_id == from_profile_id) {
+            record.customer_id = into_profile_id.clone();
+            changed = true;
+        }
+        if changed {
+            transactions::replace_transactions_at(&key, &records, conn);
+            report.transaction_logs_updated += 1;
+        }
+    }
+
+    // The surviving customer keeps their own streak if they have one.
+    if let Some(streak) = streaks::load_streak(business_name, &from_profile_id, conn) {
+        if streaks::load_streak(business_name, &into_profile_id, conn).is_none() {
+            streaks::save_streak(business_name, &into_profile_id, &streak, conn);
+        }
+        streaks::delete_streak(business_name, &from_profile_id, conn);
+    }
+
+    report.referral_codes_moved = referrals::merge_referrals(business_name, &from_profile_id, &into_profile_id, conn);
+
+    let reward = referrals::load_pending_reward(business_name, &from_profile_id, conn);
+    if !reward.is_empty() {
+        let mut into_reward = referrals::load_pending_reward(business_name, &into_profile_id, conn);
+        into_reward.pool_shares += reward.pool_shares;
+        into_reward.credit += reward.credit;
+        referrals::save_pending_reward(business_name, &into_profile_id, &into_reward, conn);
+        referrals::save_pending_reward(business_name, &from_profile_id, &Default::default(), conn);
+    }
+
+    let from_wallet = wallet::load_wallet(business_name, &from_profile_id, conn);
+    if from_wallet.enabled || !from_wallet.lots.is_empty() {
+        let mut into_wallet = wallet::load_wallet(business_name, &into_profile_id, conn);
+        into_wallet.enabled |= from_wallet.enabled;
+        into_wallet.lots.extend(from_wallet.lots);
+        wallet::save_wallet(business_name, &into_profile_id, &into_wallet, conn);
+        wallet::delete_wallet(business_name, &from_profile_id, conn);
+    }
+
+    report.block_moved = fraud::move_block(business_name, &from_profile_id, &into_profile_id, conn);
+
+    audit::record_audit(
+        business_name,
+        actor,
+        "customer_merge",
+        serde_json::json!({
+            "from": from_profile_id,
+            "into": into_profile_id,
+            "periods_updated": report.periods_updated,
+            "periods_combined": report.periods_combined,
+            "feedback_moved": report.feedback_moved,
+            "transaction_logs_updated": report.transaction_logs_updated,
+            "referral_codes_moved": report.referral_codes_moved,
+            "block_moved": report.block_moved,
+        }),
+        conn,
+    );
+    report.profile = Some(profile);
+    Ok(report)
+}
+
+/// Moves the days of `from` onto `into` in one weekly blob. Returns `None`
+/// if `from` did not appear, otherwise whether `into` already did; both were
+/// then counted as eligible, so the count drops by one.
+fn merge_expenses(details: &mut CustomerDiscountDetails, from: &str, into: &str) -> Option<bool> {
+    let days = details.customer_expense_map.remove(from)?;
+    let combined = details.customer_expense_map.contains_key(into);
+    let existing = details.customer_expense_map.entry(into.to_string()).or_default();
+    for (day, amounts) in days {
+        let entry = existing.entry(day).or_default();
+        if entry.is_empty() {
+            *entry = amounts;
+        } else if !amounts.is_empty() {
+            *entry = format!("{},{}", entry, amounts);
+        }
+    }
+    if combined {
+        details.total_eligible_customers = (details.total_eligible_customers - 1.0).max(0.0);
+    }
+    Some(combined)
+}
+
+// Whether `key` appears in the weekly blobs that still affect discounts.
+pub(crate) fn has_recent_history(business_name: &str, key: &str, conn: &mut redis::Connection) -> bool {
+    let current_monday = get_latest_monday(Utc::now().iso_week().week());
+    let coalition_pool = coalitions::coalition_of(business_name, conn)
+        .map(|coalition| coalitions::coalition_pool_name(&coalition.coalition_id));
+    let mut redis_keys = Vec::new();
+    for monday in [current_monday, current_monday - Duration::days(7)] {
+        redis_keys.extend(outlets::pool_redis_keys(business_name, monday, conn));
+        redis_keys.extend(coalition_pool.iter().map(|pool_name| period_redis_key(pool_name, monday)));
+    }
+    redis_keys
+        .iter()
+        .any(|redis_key| {
+            parse_customer_discount_details(&fetch_data_from_redis(redis_key, conn))
+                .customer_expense_map
+                .contains_key(key)
+        })
+}
+
+fn link_redis_key(business_name: &str, key: &str) -> String {
+    format!("customer_link:{}:{}", business_name, key)
+}
+
+fn profile_redis_key(business_name: &str, profile_id: &str) -> String {
+    format!("customer_profile:{}:{}", business_name, profile_id)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_customer_ids() {
+        assert_eq!(
+            CustomerId::parse("+91 98765 43210", "IN").unwrap(),
+            CustomerId::Phone("+919876543210".to_string())
+        );
+        assert_eq!(
+            CustomerId::parse("lc 1234-5678", "IN").unwrap(),
+            CustomerId::Card("LC12345678".to_string())
+        );
+        assert_eq!(
+            CustomerId::parse(" Asha@Example.com ", "IN").unwrap(),
+            CustomerId::Email("asha@example.com".to_string())
+        );
+        assert_eq!(
+            CustomerId::parse("LC12", "IN").map_err(|e| e.code),
+            Err(ErrorCode::InvalidCustomerId)
+        );
+        assert_eq!(
+            CustomerId::parse("asha@localhost", "IN").map_err(|e| e.code),
+            Err(ErrorCode::InvalidCustomerId)
+        );
+        assert_eq!(
+            CustomerId::parse("12345", "IN").map_err(|e| e.code),
+            Err(ErrorCode::InvalidPhoneNumber)
+        );
+    }
+
+    #[test]
+    fn test_key_round_trip() {
+        for id in [
+            CustomerId::Phone("+919876543210".to_string()),
+            CustomerId::Card("LC12345678".to_string()),
+            CustomerId::Email("asha@example.com".to_string()),
+        ] {
+            assert_eq!(CustomerId::from_key(&id.key()), id);
+        }
+    }
+
+    #[test]
+    fn test_merge_expenses() {
+        let mut details = CustomerDiscountDetails {
+            total_eligible_customers: 3.0,
+            ..CustomerDiscountDetails::default()
+        };
+        for (customer, day, amounts) in [
+            ("+919876543210", "13-Oct-2026", "100"),
+            ("+919812345678", "13-Oct-2026", "50"),
+            ("card:LC12345678", "14-Oct-2026", "30"),
+        ] {
+            details
+                .customer_expense_map
+                .insert(customer.to_string(), [(day.to_string(), amounts.to_string())].into());
+        }
+
+        assert_eq!(merge_expenses(&mut details, "+919812345678", "+919876543210"), Some(true));
+        assert_eq!(details.total_eligible_customers, 2.0);
+        assert_eq!(details.customer_expense_map["+919876543210"]["13-Oct-2026"], "100,50");
+        assert!(!details.customer_expense_map.contains_key("+919812345678"));
+
+        // Moving onto a customer with no history in the period keeps the count.
+        assert_eq!(merge_expenses(&mut details, "card:LC12345678", "email:asha@example.com"), Some(false));
+        assert_eq!(details.total_eligible_customers, 2.0);
+        assert_eq!(merge_expenses(&mut details, "card:LC12345678", "+919876543210"), None);
+    }
+}
@@ -0,0 +1,241 @@
+use crate::auth::AuthError;
+use crate::otp::OtpError;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Stable, machine-readable error codes. Clients should branch on these,
+/// never on the message text.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    InvalidRequest,
+    InvalidPhoneNumber,
+    InvalidCustomerId,
+    InvalidRating,
+    Unauthenticated,
+    TokenExpired,
+    InvalidOtp,
+    Forbidden,
+    NotFound,
+    Conflict,
+    PayloadTooLarge,
+    RateLimited,
+    /// A bill tripped a fraud rule set to block.
+    FraudSuspected,
+    DeliveryFailed,
+    StorageUnavailable,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidRequest => "invalid_request",
+            ErrorCode::InvalidPhoneNumber => "invalid_phone_number",
+            ErrorCode::InvalidCustomerId => "invalid_customer_id",
+            ErrorCode::InvalidRating => "invalid_rating",
+            ErrorCode::Unauthenticated => "unauthenticated",
+            ErrorCode::TokenExpired => "token_expired",
+            ErrorCode::InvalidOtp => "invalid_otp",
+            ErrorCode::Forbidden => "forbidden",
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::Conflict => "conflict",
+            ErrorCode::PayloadTooLarge => "payload_too_large",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::FraudSuspected => "fraud_suspected",
+            ErrorCode::DeliveryFailed => "delivery_failed",
+            ErrorCode::StorageUnavailable => "storage_unavailable",
+            ErrorCode::Internal => "internal",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErrorCode::InvalidRequest
+            | ErrorCode::InvalidPhoneNumber
+            | ErrorCode::InvalidCustomerId
+            | ErrorCode::InvalidRating => StatusCode::BAD_REQUEST,
+            ErrorCode::Unauthenticated | ErrorCode::TokenExpired | ErrorCode::InvalidOtp => {
+                StatusCode::UNAUTHORIZED
+            }
+            ErrorCode::Forbidden | ErrorCode::FraudSuspected => StatusCode::FORBIDDEN,
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::Conflict => StatusCode::CONFLICT,
+            ErrorCode::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::DeliveryFailed => StatusCode::BAD_GATEWAY,
+            ErrorCode::StorageUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// The one error type handlers return. The server's request middleware
+/// renders it with the request id, as JSON or as plain text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub retry_after_secs: Option<u64>,
+}
+
+/// Wire format of every error response.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ErrorBody {
+    pub code: ErrorCode,
+    pub status: u16,
+    pub message: String,
+    pub request_id: String,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> ApiError {
+        ApiError {
+            code,
+            message: message.into(),
+            retry_after_secs: None,
+        }
+    }
+
+    pub fn invalid_request(message: impl Into<String>) -> ApiError {
+        ApiError::new(ErrorCode::InvalidRequest, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> ApiError {
+        ApiError::new(ErrorCode::NotFound, message)
+    }
+
+    pub fn rate_limited(message: impl Into<String>, retry_after_secs: u64) -> ApiError {
+        ApiError {
+            retry_after_secs: Some(retry_after_secs),
+            ..ApiError::new(ErrorCode::RateLimited, message)
+        }
+    }
+
+    /// Maps errors raised by actix itself (bad JSON, oversized payloads, ...)
+    /// onto the same model as our own.
+    pub fn from_actix(error: &actix_web::Error) -> ApiError {
+        if let Some(api_error) = error.as_error::<ApiError>() {
+            return api_error.clone();
+        }
+        let code = match error.as_response_error().status_code() {
+            StatusCode::BAD_REQUEST | StatusCode::UNSUPPORTED_MEDIA_TYPE => ErrorCode::InvalidRequest,
+            StatusCode::UNAUTHORIZED => ErrorCode::Unauthenticated,
+            StatusCode::FORBIDDEN => ErrorCode::Forbidden,
+            StatusCode::NOT_FOUND => ErrorCode::NotFound,
+            StatusCode::PAYLOAD_TOO_LARGE => ErrorCode::PayloadTooLarge,
+            _ => ErrorCode::Internal,
+        };
+        ApiError::new(code, error.to_string())
+    }
+
+    pub fn to_body(&self, request_id: &str) -> ErrorBody {
+        ErrorBody {
+            code: self.code,
+            status: self.code.status().as_u16(),
+            message: self.message.clone(),
+            request_id: request_id.to_string(),
+        }
+    }
+
+    pub fn render(&self, request_id: &str, plain_text: bool) -> HttpResponse {
+        let mut builder = HttpResponse::build(self.code.status());
+        if let Some(retry_after_secs) = self.retry_after_secs {
+            builder.insert_header(("Retry-After", retry_after_secs.to_string()));
+        }
+        if plain_text {
+            builder.content_type("text/plain; charset=utf-8").body(format!(
+                "{} ({}, request id {})",
+                self.message,
+                self.code.as_str(),
+                request_id
+            ))
+        } else {
+            builder.json(self.to_body(request_id))
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.code.as_str(), self.message)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.code.status()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        self.render("", false)
+    }
+}
+
+impl From<AuthError> for ApiError {
+    fn from(error: AuthError) -> ApiError {
+        match error {
+            AuthError::Unauthenticated(message) => ApiError::new(ErrorCode::Unauthenticated, message),
+            AuthError::Forbidden(message) => ApiError::new(ErrorCode::Forbidden, message),
+        }
+    }
+}
+
+impl From<OtpError> for ApiError {
+    fn from(error: OtpError) -> ApiError {
+        let message = error.to_string();
+        match error {
+            OtpError::RateLimited { retry_after_secs } => ApiError::rate_limited(message, retry_after_secs),
+            OtpError::InvalidCode | OtpError::NoPendingCode | OtpError::TooManyAttempts => {
+                ApiError::new(ErrorCode::InvalidOtp, message)
+            }
+            OtpError::Delivery(_) => ApiError::new(ErrorCode::DeliveryFailed, message),
+        }
+    }
+}
+
+impl From<redis::RedisError> for ApiError {
+    fn from(error: redis::RedisError) -> ApiError {
+        eprintln!("Redis error: {}", error);
+        ApiError::new(ErrorCode::StorageUnavailable, "Storage is unavailable. Try again shortly.")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_codes_map_to_statuses() {
+        assert_eq!(ErrorCode::TokenExpired.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(ErrorCode::Forbidden.status(), StatusCode::FORBIDDEN);
+        assert_eq!(ErrorCode::RateLimited.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            serde_json::to_value(ErrorCode::InvalidPhoneNumber).unwrap(),
+            serde_json::json!(ErrorCode::InvalidPhoneNumber.as_str())
+        );
+    }
+
+    #[test]
+    fn test_error_body() {
+        let error = ApiError::new(ErrorCode::TokenExpired, "Not authorized / Token expired.");
+        assert_eq!(
+            serde_json::to_value(error.to_body("req-1")).unwrap(),
+            serde_json::json!({
+                "code": "token_expired",
+                "status": 401,
+                "message": "Not authorized / Token expired.",
+                "request_id": "req-1",
+            })
+        );
+    }
+
+    #[test]
+    fn test_render_sets_retry_after() {
+        let response = ApiError::rate_limited("Slow down.", 30).render("req-1", true);
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get("Retry-After").unwrap(), "30");
+    }
+}
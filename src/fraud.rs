@@ -0,0 +1,269 @@
+use crate::error::{ApiError, ErrorCode};
+use crate::policy::{FraudAction, FraudPolicy, FraudRule};
+use crate::{audit, delete_data_from_redis, fetch_data_from_redis, increment_counter, persist_data_to_redis};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+const VELOCITY_WINDOW_SECS: u64 = 60 * 60;
+
+/// The rules a bill tripped, by what they do about it.
+#[derive(Debug, Default, PartialEq)]
+pub struct FraudVerdict {
+    pub flagged: Vec<String>,
+    pub blocked: Vec<String>,
+}
+
+impl FraudVerdict {
+    /// Records `reason` if `observed` is over the rule's limit.
+    pub fn check(&mut self, rule: Option<&FraudRule>, observed: f64, reason: impl FnOnce() -> String) {
+        let Some(rule) = rule else {
+            return;
+        };
+        if observed > rule.limit {
+            match rule.action {
+                FraudAction::Flag => self.flagged.push(reason()),
+                FraudAction::Block => self.blocked.push(reason()),
+            }
+        }
+    }
+
+    pub fn into_result(self) -> Result<Vec<String>, ApiError> {
+        if self.blocked.is_empty() {
+            Ok(self.flagged)
+        } else {
+            Err(ApiError::new(
+                ErrorCode::FraudSuspected,
+                format!("Bill refused: {}", self.blocked.join("; ")),
+            ))
+        }
+    }
+}
+
+/// Counts this bill against the hourly velocity rules. `cashier_id` is the
+/// token or terminal billing; bills without one skip the cashier rules.
+pub fn check_velocity(
+    policy: &FraudPolicy,
+    business_name: &str,
+    customer_key: &str,
+    cashier_id: Option<&str>,
+    is_new_customer: bool,
+    conn: &mut redis::Connection,
+) -> FraudVerdict {
+    let mut verdict = FraudVerdict::default();
+    if is_blocked(business_name, customer_key, conn) {
+        verdict.blocked.push("customer was blocked after review".to_string());
+    }
+    if policy.customer_bills_per_hour.is_some() {
+        let redis_key = format!("fraud:customer_bills:{}:{}", business_name, customer_key);
+        let (bills, _) = increment_counter(&redis_key, VELOCITY_WINDOW_SECS, conn);
+        verdict.check(policy.customer_bills_per_hour.as_ref(), bills as f64, || {
+            format!("{} bills for this customer within an hour", bills)
+        });
+    }
+    let Some(cashier_id) = cashier_id else {
+        return verdict;
+    };
+    if policy.cashier_bills_per_hour.is_some() {
+        let redis_key = format!("fraud:cashier_bills:{}:{}", business_name, cashier_id);
+        let (bills, _) = increment_counter(&redis_key, VELOCITY_WINDOW_SECS, conn);
+        verdict.check(policy.cashier_bills_per_hour.as_ref(), bills as f64, || {
+            format!("{} bills by this cashier within an hour", bills)
+        });
+    }
+    if policy.new_customers_per_cashier_per_hour.is_some() && is_new_customer {
+        let redis_key = format!("fraud:new_customers:{}:{}", business_name, cashier_id);
+        let (customers, _) = increment_counter(&redis_key, VELOCITY_WINDOW_SECS, conn);
+        verdict.check(policy.new_customers_per_cashier_per_hour.as_ref(), customers as f64, || {
+            format!("{} new customers by this cashier within an hour", customers)
+        });
+    }
+    verdict
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+/// A flagged bill waiting for, or given, the owner's decision. A business's
+/// reviews are kept as a JSON array under `fraud_reviews:<business>`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct FraudReview {
+    pub review_id: String,
+    pub timestamp: String,
+    pub customer_id: String,
+    pub cashier_id: Option<String>,
+    pub terminal_id: Option<String>,
+    pub bill_amount: f64,
+    pub discount: f64,
+    pub reasons: Vec<String>,
+    pub status: ReviewStatus,
+    pub decided_by: Option<String>,
+    pub decided_at: Option<String>,
+}
+
+impl FraudReview {
+    pub fn new(
+        customer_id: &str,
+        cashier_id: Option<&str>,
+        terminal_id: Option<&str>,
+        bill_amount: f64,
+        discount: f64,
+        reasons: Vec<String>,
+    ) -> FraudReview {
+        FraudReview {
+            review_id: format!("rev_{}", &Uuid::new_v4().simple().to_string()[..12]),
+            timestamp: Utc::now().to_rfc3339(),
+            customer_id: customer_id.to_string(),
+            cashier_id: cashier_id.map(str::to_string),
+            terminal_id: terminal_id.map(str::to_string),
+            bill_amount,
+            discount,
+            reasons,
+            status: ReviewStatus::Pending,
+            decided_by: None,
+            decided_at: None,
+        }
+    }
+}
+
+pub fn load_reviews(business_name: &str, conn: &mut redis::Connection) -> Vec<FraudReview> {
+    serde_json::from_str(&fetch_data_from_redis(&reviews_redis_key(business_name), conn)).unwrap_or_default()
+}
+
+pub fn queue_review(business_name: &str, review: &FraudReview, conn: &mut redis::Connection) {
+    let mut reviews = load_reviews(business_name, conn);
+    reviews.push(review.clone());
+    save_reviews(business_name, &reviews, conn);
+    println!(
+        "Bill flagged for review - Business: {}, Review: {}, Reasons: {:?}",
+        business_name, review.review_id, review.reasons
+    );
+}
+
+/// Approves or rejects a pending review. Rejecting with `block_customer`
+/// refuses the customer's future bills until they are unblocked.
+pub fn decide_review(
+    business_name: &str,
+    review_id: &str,
+    status: ReviewStatus,
+    block_customer: bool,
+    actor: &str,
+    conn: &mut redis::Connection,
+) -> Result<FraudReview, ApiError> {
+    if status == ReviewStatus::Pending {
+        return Err(ApiError::invalid_request("status must be approved or rejected"));
+    }
+    if block_customer && status != ReviewStatus::Rejected {
+        return Err(ApiError::invalid_request("Only rejected reviews can block the customer"));
+    }
+    let mut reviews = load_reviews(business_name, conn);
+    let review = reviews
+        .iter_mut()
+        .find(|review| review.review_id == review_id)
+        .ok_or_else(|| ApiError::not_found("Review not found"))?;
+    if review.status != ReviewStatus::Pending {
+        return Err(ApiError::new(ErrorCode::Conflict, "Review was already decided"));
+    }
+    review.status = status;
+    review.decided_by = Some(actor.to_string());
+    review.decided_at = Some(Utc::now().to_rfc3339());
+    let review = review.clone();
+    save_reviews(business_name, &reviews, conn);
+    if block_customer {
+        persist_data_to_redis(&blocked_redis_key(business_name, &review.customer_id), review_id.to_string(), conn);
+    }
+    audit::record_audit(
+        business_name,
+        actor,
+        "fraud_review",
+        serde_json::json!({
+            "review_id": review_id,
+            "customer_id": review.customer_id,
+            "status": review.status,
+            "block_customer": block_customer,
+        }),
+        conn,
+    );
+    Ok(review)
+}
+
+pub fn is_blocked(business_name: &str, customer_key: &str, conn: &mut redis::Connection) -> bool {
+    !fetch_data_from_redis(&blocked_redis_key(business_name, customer_key), conn).is_empty()
+}
+
+/// Lifts a block. Returns `false` if the customer wasn't blocked.
+pub fn unblock_customer(business_name: &str, customer_key: &str, actor: &str, conn: &mut redis::Connection) -> bool {
+    if !is_blocked(business_name, customer_key, conn) {
+        return false;
+    }
+    delete_data_from_redis(&blocked_redis_key(business_name, customer_key), conn);
+    audit::record_audit(
+        business_name,
+        actor,
+        "fraud_unblock",
+        serde_json::json!({ "customer_id": customer_key }),
+        conn,
+    );
+    true
+}
+
+/// Carries a block on `from` over to `into` when two customers are merged.
+/// Returns whether `from` was blocked.
+pub fn move_block(business_name: &str, from: &str, into: &str, conn: &mut redis::Connection) -> bool {
+    let from_key = blocked_redis_key(business_name, from);
+    let review_id = fetch_data_from_redis(&from_key, conn);
+    if review_id.is_empty() {
+        return false;
+    }
+    if !is_blocked(business_name, into, conn) {
+        persist_data_to_redis(&blocked_redis_key(business_name, into), review_id, conn);
+    }
+    delete_data_from_redis(&from_key, conn);
+    true
+}
+
+fn save_reviews(business_name: &str, reviews: &[FraudReview], conn: &mut redis::Connection) {
+    persist_data_to_redis(
+        &reviews_redis_key(business_name),
+        serde_json::to_string(reviews).unwrap(),
+        conn,
+    );
+}
+
+fn reviews_redis_key(business_name: &str) -> String {
+    format!("fraud_reviews:{}", business_name)
+}
+
+fn blocked_redis_key(business_name: &str, customer_key: &str) -> String {
+    format!("fraud_blocked:{}:{}", business_name, customer_key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_verdict_sorts_reasons_by_action() {
+        let flag = FraudRule { limit: 0.5, action: FraudAction::Flag };
+        let block = FraudRule { limit: 3.0, action: FraudAction::Block };
+        let mut verdict = FraudVerdict::default();
+        verdict.check(Some(&flag), 0.5, || "at the limit".to_string());
+        verdict.check(None, 100.0, || "rule off".to_string());
+        assert_eq!(verdict, FraudVerdict::default());
+
+        verdict.check(Some(&flag), 0.8, || "high ratio".to_string());
+        assert_eq!(verdict.into_result().unwrap(), vec!["high ratio"]);
+
+        let mut verdict = FraudVerdict::default();
+        verdict.check(Some(&block), 4.0, || "too many bills".to_string());
+        let error = verdict.into_result().unwrap_err();
+        assert_eq!(error.code, ErrorCode::FraudSuspected);
+        assert!(error.message.contains("too many bills"));
+    }
+}
@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+/// Most line items accepted on one bill.
+pub const MAX_LINE_ITEMS: usize = 500;
+
+/// One line of an itemized bill.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct LineItem {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Matched case-insensitively against the policy's `category_rates`.
+    pub category: String,
+    pub amount: f64,
+}
+
+/// Checks `items` and returns their total.
+pub fn validate_items(items: &[LineItem]) -> Result<f64, String> {
+    if items.len() > MAX_LINE_ITEMS {
+        return Err(format!("A bill can have at most {} line items", MAX_LINE_ITEMS));
+    }
+    for item in items {
+        if item.category.trim().is_empty() {
+            return Err("Every line item needs a category".to_string());
+        }
+        if !item.amount.is_finite() || item.amount < 0.0 {
+            return Err(format!("Invalid amount for {}: {}", item.category, item.amount));
+        }
+    }
+    Ok(items.iter().map(|item| item.amount).sum())
+}
+
+pub fn normalize_category(category: &str) -> String {
+    category.trim().to_lowercase()
+}
+
+/// What a bill puts into next week's pool. Without items the whole
+/// `final_amount` contributes at `default_rate`. With items, the discount is
+/// spread over them in proportion to their amounts and each contributes at
+/// its category's rate, or `default_rate` if it has none.
+pub fn pool_contribution(
+    items: &[LineItem],
+    bill_amount: f64,
+    final_amount: f64,
+    default_rate: f64,
+    category_rates: &HashMap<String, f64>,
+) -> f64 {
+    if items.is_empty() || bill_amount <= 0.0 {
+        return final_amount * default_rate;
+    }
+    let paid_share = final_amount / bill_amount;
+    items
+        .iter()
+        .map(|item| {
+            let rate = category_rates
+                .get(&normalize_category(&item.category))
+                .copied()
+                .unwrap_or(default_rate);
+            item.amount * paid_share * rate
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn item(category: &str, amount: f64) -> LineItem {
+        LineItem {
+            description: None,
+            category: category.to_string(),
+            amount,
+        }
+    }
+
+    #[test]
+    fn test_pool_contribution_by_category() {
+        let rates: HashMap<String, f64> = [("alcohol".to_string(), 0.0), ("staples".to_string(), 0.01)].into();
+        let items = vec![item("Alcohol", 40.0), item("staples", 100.0), item("snacks", 60.0)];
+        assert_eq!(validate_items(&items), Ok(200.0));
+
+        // Full price: 0 + 1 + 1.8.
+        let contribution = pool_contribution(&items, 200.0, 200.0, 0.03, &rates);
+        assert!((contribution - 2.8).abs() < 1e-9);
+        // A 50 discount is shared by every line, so each pays three quarters.
+        let contribution = pool_contribution(&items, 200.0, 150.0, 0.03, &rates);
+        assert!((contribution - 2.1).abs() < 1e-9);
+        // No items: the old flat rate.
+        assert_eq!(pool_contribution(&[], 200.0, 150.0, 0.03, &rates), 150.0 * 0.03);
+    }
+
+    #[test]
+    fn test_validate_items() {
+        assert!(validate_items(&[item(" ", 1.0)]).is_err());
+        assert!(validate_items(&[item("food", -1.0)]).is_err());
+        assert!(validate_items(&[item("food", f64::NAN)]).is_err());
+    }
+}
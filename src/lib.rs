@@ -1,11 +1,44 @@
 extern crate lazy_static;
-use chrono::{Datelike, Duration, NaiveDate, Utc, Weekday};
-use redis;
-use regex::Regex;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc, Weekday};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use std::collections::HashMap;
 use uuid::Uuid;
 
+pub mod audit;
+pub mod auth;
+pub mod business;
+pub mod campaigns;
+pub mod coalitions;
+pub mod customer;
+pub mod error;
+pub mod fraud;
+pub mod items;
+pub mod migrations;
+pub mod otp;
+pub mod outlets;
+pub mod phone;
+pub mod plugins;
+pub mod policy;
+pub mod pseudonym;
+pub mod ratelimit;
+pub mod referrals;
+pub mod reports;
+pub mod rules;
+pub mod secrets;
+pub mod signed_token;
+pub mod simulator;
+pub mod strategy;
+pub mod streaks;
+pub mod terminal;
+pub mod transactions;
+pub mod wallet;
+
+use auth::Role;
+use customer::CustomerId;
+use error::{ApiError, ErrorCode};
+pub use theloyalgame_core::{PoolSnapshot, Quote};
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CustomerDiscountDetails {
     pub total_pooled_amount: f64,
@@ -31,27 +64,71 @@ pub fn get_response(
     phone_number_amount: String,
     conn: &mut redis::Connection,
 ) -> String {
+    if let Err(error) = validate_token(&token, &business_name, conn) {
+        return error.message;
+    }
+    apply_discount(business_name, phone_number_amount, conn)
+}
+
+/// What a valid token grants: its role and the customer whose phone was
+/// verified when it was issued.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenGrant {
+    pub role: Role,
+    /// Stored key of the verified phone. Tokens stored before it was
+    /// recorded have none, so they can't act for any customer.
+    pub customer_key: Option<String>,
+}
+
+/// Checks that `token` was issued for `business_name` and has not expired,
+/// returning the role it carries. Tokens stored before roles existed carry
+/// no role part; they were all issued to the widget, so they are customer
+/// tokens.
+pub fn validate_token(
+    token: &str,
+    business_name: &str,
+    conn: &mut redis::Connection,
+) -> Result<Role, ApiError> {
+    authenticate_token(token, business_name, conn).map(|grant| grant.role)
+}
+
+/// `validate_token`, also returning the customer the token was issued to.
+pub fn authenticate_token(
+    token: &str,
+    business_name: &str,
+    conn: &mut redis::Connection,
+) -> Result<TokenGrant, ApiError> {
+    let token_expired = || ApiError::new(ErrorCode::TokenExpired, "Not authorized / Token expired.");
+    if signed_token::is_signed_token(token) {
+        let claims = signed_token::validate_signed_token(token, business_name, conn).map_err(|reason| {
+            eprintln!("Signed token validation failed: {}", reason);
+            token_expired()
+        })?;
+        business::require_registered(business_name, conn)?;
+        return Ok(TokenGrant {
+            role: claims.role,
+            customer_key: Some(claims.customer_key),
+        });
+    }
+
     let now = Utc::now();
-    let now_date = now.format("%d-%b-%Y").to_string();
     let token_key = format!("token:{}", token);
     let token_data = fetch_data_from_redis(&token_key, conn);
     let mut token_expiry_date: NaiveDate = now.date_naive() - Duration::days(7);
     let username_token_key = format!("{}_token_{}", business_name, token);
     let verified_token = fetch_data_from_redis(&username_token_key, conn);
-    println!(
-        "Token validation - Provided: {}, Verified: {}, Token Data: {}",
-        token, verified_token, token_data
-    );
 
-    let (stored_token, expiry_date_str) = if !token_data.is_empty() {
-        let parts: Vec<&str> = token_data.split("___").collect();
-        if parts.len() == 2 {
-            (parts[0].to_string(), parts[1].to_string())
-        } else {
-            (String::new(), String::new())
-        }
-    } else {
-        (String::new(), String::new())
+    let parts: Vec<&str> = token_data.split("___").collect();
+    let (stored_token, expiry_date_str, role_str, customer_key) = match parts.as_slice() {
+        [token, expiry] => (token.to_string(), expiry.to_string(), String::new(), None),
+        [token, expiry, role] => (token.to_string(), expiry.to_string(), role.to_string(), None),
+        [token, expiry, role, customer_key] => (
+            token.to_string(),
+            expiry.to_string(),
+            role.to_string(),
+            Some(customer_key.to_string()),
+        ),
+        _ => (String::new(), String::new(), String::new(), None),
     };
 
     if !expiry_date_str.is_empty() {
@@ -62,130 +139,518 @@ pub fn get_response(
                     "Failed to parse token expiry date '{}': {}",
                     expiry_date_str, e
                 );
-                return "Not authorized / Invalid token expiry date.".to_string();
+                return Err(ApiError::new(
+                    ErrorCode::Unauthenticated,
+                    "Not authorized / Invalid token expiry date.",
+                ));
             }
         }
     }
 
     if stored_token != token || verified_token != token || token_expiry_date < now.date_naive() {
         eprintln!(
-            "Token validation failed: stored_token='{}', verified_token='{}', token='{}', expiry_date='{}', now='{}'",
-            stored_token, verified_token, token, token_expiry_date, now.date_naive()
+            "Token validation failed: token={}, stored={}, issued_for_business={}, expiry_date={}, now={}",
+            auth::token_id(token),
+            stored_token == token,
+            verified_token == token,
+            token_expiry_date,
+            now.date_naive()
         );
-        return "Not authorized / Token expired.".to_string();
+        return Err(token_expired());
+    }
+
+    let role = if role_str.is_empty() {
+        Role::Customer
+    } else {
+        Role::parse(&role_str).ok_or_else(|| {
+            ApiError::new(ErrorCode::Unauthenticated, "Not authorized / Invalid token role.")
+        })?
+    };
+    business::require_registered(business_name, conn)?;
+    Ok(TokenGrant { role, customer_key })
+}
+
+/// A bill to run through the weekly pool of `business_name`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct DiscountRequest {
+    pub business_name: String,
+    /// Phone number, loyalty card number or email, as given at the counter.
+    pub customer_id: String,
+    pub amount: f64,
+    /// POS terminal that signed the request, if any.
+    pub terminal_id: Option<String>,
+    /// Cashier token or terminal that entered the bill, for fraud rules.
+    /// Never the token itself.
+    pub cashier_id: Option<String>,
+    /// Outlet of the business the bill was made at, if any.
+    pub outlet_id: Option<String>,
+    /// Referral code given by a new customer on their first bill.
+    pub referral_code: Option<String>,
+    /// Device id the client sent, used to spot referral abuse.
+    pub device_id: Option<String>,
+    /// Address the server saw a widget bill come from, used to spot
+    /// referral abuse whatever `device_id` says.
+    pub client_address: Option<String>,
+    /// How much of their wallet balance the customer wants off this bill.
+    pub redeem_from_wallet: Option<f64>,
+    /// Line items of an itemized bill; they must add up to `amount`.
+    pub items: Vec<items::LineItem>,
+}
+
+/// What the customer gets for one bill.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct DiscountOutcome {
+    /// The loyalty profile that was billed; see `customer::CustomerId::key`.
+    pub customer_id: String,
+    /// `customer_id` fit to show on a receipt; pseudonymized phones are masked.
+    pub customer_display: String,
+    pub bill_amount: f64,
+    pub discount: f64,
+    pub final_amount: f64,
+    pub discount_percentage: f64,
+    /// The customer was already billed today, so no discount was applied.
+    pub has_transaction: bool,
+    /// Consecutive weeks the customer has been billed in, this one included.
+    #[serde(default)]
+    pub streak_weeks: u32,
+    /// Multiplier the streak earned on the pool share; 1 without a bonus.
+    #[serde(default = "default_streak_multiplier")]
+    pub streak_multiplier: f64,
+    /// Part of `discount` paid from referral bonuses.
+    #[serde(default)]
+    pub referral_bonus: f64,
+    /// Pool share banked in the customer's wallet instead of being applied.
+    #[serde(default)]
+    pub wallet_credited: f64,
+    /// Part of `discount` paid from the wallet.
+    #[serde(default)]
+    pub wallet_redeemed: f64,
+    /// Balance after this bill, for customers with a wallet.
+    #[serde(default)]
+    pub wallet_balance: Option<f64>,
+    /// Campaigns that boosted this bill.
+    #[serde(default)]
+    pub campaign_ids: Vec<String>,
+    /// Eligibility rules the customer didn't meet, so no pool share was paid.
+    #[serde(default)]
+    pub failed_rules: Vec<String>,
+}
+
+fn default_streak_multiplier() -> f64 {
+    1.0
+}
+
+impl DiscountOutcome {
+    /// The plain-text format the chat widget parses.
+    pub fn to_legacy_string(&self) -> String {
+        format!(
+            "Phone number: {}\n ; Final bill amount: {:.2}\n ; Discount given: {:.2}%",
+            self.customer_display,
+            self.final_amount,
+            self.discount_percentage
+        )
+    }
+}
+
+/// Bills `phone_number_amount` ("phone,amount") against the weekly pool of
+/// `business_name`. Callers are expected to have authorized the request.
+pub fn apply_discount(
+    business_name: String,
+    phone_number_amount: String,
+    conn: &mut redis::Connection,
+) -> String {
+    match parse_phone_number_amount(business_name, &phone_number_amount)
+        .and_then(|request| process_discount(&request, conn))
+    {
+        Ok(outcome) => outcome.to_legacy_string(),
+        Err(error) => error.message,
     }
+}
 
-    static DISCOUNT_POOL_PERCENTAGE: f64 = 0.03;
-    static REDIS_KEY_SEPARATOR: &str = "___";
+/// Parses the legacy "phone,amount" path segment.
+pub fn parse_phone_number_amount(
+    business_name: String,
+    phone_number_amount: &str,
+) -> Result<DiscountRequest, ApiError> {
     let phone_amount_vec = phone_number_amount.split(",").collect::<Vec<&str>>();
     if phone_amount_vec.len() != 2 {
-        return "Invalid phone_number_amount format. Expected 'phone,amount'.".to_string();
+        return Err(ApiError::invalid_request(
+            "Invalid phone_number_amount format. Expected 'phone,amount'.",
+        ));
+    }
+    let amount = phone_amount_vec[1]
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|amount| amount.is_finite() && *amount >= 0.0)
+        .ok_or_else(|| {
+            ApiError::invalid_request(format!("Invalid bill amount: {}", phone_amount_vec[1].trim()))
+        })?;
+    Ok(DiscountRequest {
+        business_name,
+        customer_id: phone_amount_vec[0].trim().to_string(),
+        amount,
+        ..DiscountRequest::default()
+    })
+}
+
+fn pool_name(
+    business_name: &str,
+    coalition: Option<&coalitions::Coalition>,
+    policy: &policy::BusinessPolicy,
+    outlet_id: Option<&str>,
+) -> String {
+    match (coalition, policy.pool_scope, outlet_id) {
+        (Some(coalition), _, _) => coalitions::coalition_pool_name(&coalition.coalition_id),
+        (None, policy::PoolScope::Outlet, Some(outlet_id)) => outlets::outlet_pool_name(business_name, outlet_id),
+        _ => business_name.to_string(),
     }
-    let phone_number_str = phone_amount_vec[0].trim();
-    let amount_str = phone_amount_vec[1].trim();
-    let re = Regex::new(r"^[0-9]{10}$").unwrap();
-    if !re.is_match(phone_number_str) {
-        return format!(
-            "Invalid phone number: {}. Must be 10 digits.",
-            phone_number_str
+}
+
+// Customers billed before streaks were tracked start from last week's blob.
+fn previous_streak(
+    business_name: &str,
+    customer_key: &str,
+    previous_period: &CustomerDiscountDetails,
+    last_monday_date: NaiveDate,
+    conn: &mut redis::Connection,
+) -> Option<streaks::CustomerStreak> {
+    streaks::load_streak(business_name, customer_key, conn).or_else(|| {
+        previous_period
+            .customer_expense_map
+            .contains_key(customer_key)
+            .then_some(streaks::CustomerStreak { last_period: streaks::period_label(last_monday_date), weeks: 1 })
+    })
+}
+
+// What a bill is judged against before it is recorded: the pool's periods,
+// the customer's streak and the campaigns running at the till. Bills,
+// eligibility explanations and pool snapshots all load it this way.
+struct BillContext {
+    policy: policy::BusinessPolicy,
+    now: DateTime<Utc>,
+    coalition: Option<coalitions::Coalition>,
+    current_week_redis_key: String,
+    previous_period: CustomerDiscountDetails,
+    current_period: CustomerDiscountDetails,
+    previous_streak: Option<streaks::CustomerStreak>,
+    streak: streaks::CustomerStreak,
+    boost: campaigns::CampaignBoost,
+    billed_today: bool,
+}
+
+impl BillContext {
+    fn load(
+        business_name: &str,
+        customer_key: &str,
+        policy: policy::BusinessPolicy,
+        outlet_id: Option<&str>,
+        terminal_id: Option<&str>,
+        now: DateTime<Utc>,
+        conn: &mut redis::Connection,
+    ) -> BillContext {
+        // Coalitions and outlets with their own pool store it under their pool name.
+        let coalition = coalitions::coalition_of(business_name, conn);
+        let pool_name = pool_name(business_name, coalition.as_ref(), &policy, outlet_id);
+        let current_monday_date = get_latest_monday(now.iso_week().week());
+        let last_monday_date = current_monday_date - Duration::days(7);
+        let previous_week_redis_key = period_redis_key(&pool_name, last_monday_date);
+        let previous_period = parse_customer_discount_details(&fetch_data_from_redis(&previous_week_redis_key, conn));
+        let current_week_redis_key = period_redis_key(&pool_name, current_monday_date);
+        let current_period = parse_customer_discount_details(&fetch_data_from_redis(&current_week_redis_key, conn));
+        println!(
+            "Pool periods - Previous: {}, Current: {}",
+            previous_week_redis_key, current_week_redis_key
         );
+        let previous_streak = previous_streak(business_name, customer_key, &previous_period, last_monday_date, conn);
+        let streak = streaks::CustomerStreak::visit(previous_streak.as_ref(), current_monday_date);
+        let boost = campaigns::active_boost(&campaigns::load_campaigns(business_name, conn), now, terminal_id, outlet_id);
+        let billed_today = current_period
+            .customer_expense_map
+            .get(customer_key)
+            .is_some_and(|days| days.contains_key(&now.format("%d-%b-%Y").to_string()));
+        BillContext {
+            policy,
+            now,
+            coalition,
+            current_week_redis_key,
+            previous_period,
+            current_period,
+            previous_streak,
+            streak,
+            boost,
+            billed_today,
+        }
+    }
+
+    fn discount_context<'a>(
+        &'a self,
+        business_name: &'a str,
+        customer_key: &'a str,
+        bill_amount: f64,
+        items: &'a [items::LineItem],
+    ) -> strategy::DiscountContext<'a> {
+        strategy::DiscountContext {
+            business_name,
+            customer_key,
+            bill_amount,
+            items,
+            policy: &self.policy,
+            previous_period: &self.previous_period,
+            current_period: &self.current_period,
+            billed_today: self.billed_today,
+            streak_weeks: self.streak.weeks,
+            now: self.now,
+            campaign_pool_percentage: self.boost.pool_percentage,
+        }
+    }
+
+    // How the strategy and then the eligibility rules judge the bill.
+    fn judge(
+        &self,
+        discount_strategy: &dyn strategy::DiscountStrategy,
+        context: &strategy::DiscountContext,
+    ) -> rules::RuleTrace {
+        rules::evaluate_with_strategy(
+            &self.policy.strategy,
+            discount_strategy.is_eligible(context),
+            &self.policy.eligibility_rules,
+            rules::RuleContext::new(
+                context.customer_key,
+                context.bill_amount,
+                &self.previous_period,
+                &self.current_period,
+                self.streak.weeks,
+                self.now,
+                &self.policy.holidays,
+            ),
+        )
+    }
+}
+
+/// How the business's eligibility rules would judge a bill of `amount` by
+/// `customer_key` now, without recording anything.
+pub fn explain_eligibility(
+    business_name: &str,
+    customer_key: &str,
+    amount: f64,
+    outlet_id: Option<&str>,
+    terminal_id: Option<&str>,
+    conn: &mut redis::Connection,
+) -> rules::RuleTrace {
+    let policy = policy::load_policy(business_name, conn);
+    let bill = BillContext::load(business_name, customer_key, policy, outlet_id, terminal_id, Utc::now(), conn);
+    let discount_strategy = plugins::strategy_for(business_name, &bill.policy.strategy, conn);
+    bill.judge(
+        discount_strategy.as_ref(),
+        &bill.discount_context(business_name, customer_key, amount, &[]),
+    )
+}
+
+/// What `customer_key`'s next bill would be paid from, for previews with
+/// `theloyalgame_core::quote`. Reads only.
+pub fn pool_snapshot(
+    business_name: &str,
+    customer_key: &str,
+    outlet_id: Option<&str>,
+    terminal_id: Option<&str>,
+    conn: &mut redis::Connection,
+) -> PoolSnapshot {
+    let policy = policy::load_policy(business_name, conn);
+    let bill = BillContext::load(business_name, customer_key, policy, outlet_id, terminal_id, Utc::now(), conn);
+    let banks_to_wallet =
+        bill.policy.wallet.enabled && wallet::load_wallet(business_name, customer_key, conn).enabled;
+    PoolSnapshot {
+        pool_amount: bill.previous_period.total_pooled_amount,
+        eligible_customers: bill.previous_period.total_eligible_customers
+            + bill.current_period.total_eligible_customers,
+        in_previous_period: bill.previous_period.customer_expense_map.contains_key(customer_key),
+        billed_today: bill.billed_today,
+        streak_multiplier: streaks::streak_multiplier(&bill.policy.streak_bonuses, bill.streak.weeks),
+        campaign_multiplier: bill.boost.share_multiplier,
+        banks_to_wallet,
+        estimate: bill.policy.strategy != strategy::DEFAULT_STRATEGY || !bill.policy.eligibility_rules.is_empty(),
+    }
+}
+
+pub fn process_discount(
+    request: &DiscountRequest,
+    conn: &mut redis::Connection,
+) -> Result<DiscountOutcome, ApiError> {
+    if !request.amount.is_finite() || request.amount < 0.0 {
+        return Err(ApiError::invalid_request(format!(
+            "Invalid bill amount: {}",
+            request.amount
+        )));
+    }
+    let now = Utc::now();
+    let now_date = now.format("%d-%b-%Y").to_string();
+    let business_name = &request.business_name;
+    business::require_registered(business_name, conn)?;
+    let policy = policy::load_policy(business_name, conn);
+    let customer_id = CustomerId::parse(&request.customer_id, &policy.default_region)?;
+    let customer_key = customer::resolve_customer(business_name, &customer_id, conn);
+    let customer_key = customer_key.as_str();
+    let amount_float = request.amount;
+
+    if let Some(outlet_id) = &request.outlet_id {
+        if !outlets::outlet_exists(business_name, outlet_id, conn) {
+            return Err(ApiError::invalid_request(format!("Unknown outlet: {}", outlet_id)));
+        }
+    }
+    let items_total = items::validate_items(&request.items).map_err(ApiError::invalid_request)?;
+    if !request.items.is_empty() && (items_total - request.amount).abs() > 0.005 {
+        return Err(ApiError::invalid_request(format!(
+            "Line items add up to {:.2}, not the bill amount {:.2}",
+            items_total, request.amount
+        )));
+    }
+
+    let stored_wallet = wallet::load_wallet(business_name, customer_key, conn);
+    let banks_to_wallet = policy.wallet.enabled && stored_wallet.enabled;
+    // A balance stays redeemable after the business turns wallets off or the
+    // customer opts out; only new shares stop being banked.
+    let mut customer_wallet = (banks_to_wallet || stored_wallet.balance() > 0.0).then_some(stored_wallet);
+    if let Some(requested) = request.redeem_from_wallet {
+        if customer_wallet.is_none() {
+            return Err(ApiError::invalid_request("This customer has no wallet to redeem from"));
+        }
+        if !requested.is_finite() || requested < 0.0 {
+            return Err(ApiError::invalid_request("redeem_from_wallet must not be negative"));
+        }
     }
-    let amount_float = amount_str.parse::<f64>().unwrap_or(0.0);
-    let phone_number = phone_number_str.parse::<i64>().unwrap_or(0);
 
-    let current_monday_date = get_latest_monday(now.iso_week().week());
-    let current_week_redis_key = format!(
-        "{}{}{}",
+    let bill = BillContext::load(
         business_name,
-        REDIS_KEY_SEPARATOR,
-        current_monday_date.format("%d-%b-%Y").to_string().as_str()
+        customer_key,
+        policy,
+        request.outlet_id.as_deref(),
+        request.terminal_id.as_deref(),
+        now,
+        conn,
     );
-    let current_week_customer_discount_details_str =
-        fetch_data_from_redis(&current_week_redis_key, conn);
-    let mut current_week_customer_discount_details: CustomerDiscountDetails =
-        if current_week_customer_discount_details_str.is_empty() {
-            CustomerDiscountDetails::default()
-        } else {
-            serde_json::from_str(&current_week_customer_discount_details_str).unwrap_or_default()
-        };
+    let mut streak_multiplier = 1.0;
+    let mut discount = 0.0;
+    let has_current_week_transaction = bill.billed_today;
     println!(
-        "Current week data - Key: {}, Data: {}, Parsed: {:?}",
-        current_week_redis_key,
-        current_week_customer_discount_details_str,
-        current_week_customer_discount_details
+        "Discount check - Customer: {}, Has transaction: {}",
+        customer_key, has_current_week_transaction
     );
 
-    let last_monday_date = current_monday_date - Duration::days(7);
-    let redis_key = format!(
-        "{}{}{}",
+    let discount_strategy = plugins::strategy_for(business_name, &bill.policy.strategy, conn);
+    let discount_context = bill.discount_context(business_name, customer_key, amount_float, &request.items);
+    let share_value = discount_strategy.share_value(&discount_context);
+    let trace = bill.judge(discount_strategy.as_ref(), &discount_context);
+    println!("Eligibility - Customer: {}, Trace: {:?}", customer_key, trace.rules);
+    let failed_rules = trace.failed_rules();
+    let eligible = trace.passed;
+    if eligible {
+        println!(
+            "Calculating discount - Strategy: {}, Share: {}",
+            bill.policy.strategy, share_value
+        );
+        streak_multiplier = streaks::streak_multiplier(&bill.policy.streak_bonuses, bill.streak.weeks);
+        // Multipliers can push a share past the bill; it never pays out more.
+        discount = theloyalgame_core::pool_share(share_value, streak_multiplier, bill.boost.share_multiplier)
+            .min(amount_float);
+        println!(
+            "Discount applied: {}, Streak: {} weeks x{}, Campaigns: {:?} x{}",
+            discount, bill.streak.weeks, streak_multiplier, bill.boost.campaign_ids, bill.boost.share_multiplier
+        );
+    }
+
+    let pool_share = discount;
+
+    let mut fraud_verdict = fraud::check_velocity(
+        &bill.policy.fraud,
         business_name,
-        REDIS_KEY_SEPARATOR,
-        last_monday_date.format("%d-%b-%Y").to_string().as_str()
+        customer_key,
+        request.cashier_id.as_deref(),
+        bill.previous_streak.is_none(),
+        conn,
     );
-    let customer_discount_details_str = fetch_data_from_redis(&redis_key, conn);
-    let customer_discount_details: CustomerDiscountDetails =
-        if customer_discount_details_str.is_empty() {
-            CustomerDiscountDetails::default()
-        } else {
-            serde_json::from_str(&customer_discount_details_str).unwrap_or_default()
+    if amount_float > 0.0 {
+        fraud_verdict.check(bill.policy.fraud.discount_ratio.as_ref(), pool_share / amount_float, || {
+            format!("pool share is {:.0}% of the bill", pool_share / amount_float * 100.0)
+        });
+    }
+    let fraud_flags = fraud_verdict.into_result().inspect_err(|error| {
+        println!("Bill blocked - Business: {}, Customer: {}, {}", business_name, customer_key, error.message);
+    })?;
+
+    // Redeemed only once nothing else can refuse the bill.
+    if let Some(code) = &request.referral_code {
+        let bonus = bill.policy.referral_bonus.as_ref().ok_or_else(|| {
+            ApiError::new(ErrorCode::Forbidden, "Referrals are not enabled for this business")
+        })?;
+        // Widget bills are counted by address, terminal and cashier bills by who entered them.
+        let source = match &request.client_address {
+            Some(address) => Some(format!("address:{}", address)),
+            None => request.cashier_id.clone(),
         };
-    println!(
-        "Previous week data - Key: {}, Data: {}, Parsed: {:?}",
-        redis_key,
-        customer_discount_details_str,
-        customer_discount_details
-    );
+        referrals::redeem_referral(
+            business_name,
+            code,
+            customer_key,
+            source.as_deref(),
+            request.device_id.as_deref(),
+            bonus,
+            conn,
+        )?;
+    }
 
-    let mut discount = 0.0;
-    let has_current_week_transaction = current_week_customer_discount_details
-        .customer_expense_map
-        .get(phone_number_str)
-        .map_or(false, |map| map.contains_key(&now_date));
-    println!(
-        "Discount check - Phone: {}, Has transaction: {}",
-        phone_number_str, has_current_week_transaction
-    );
+    // Customers with a wallet bank their share and spend it when they choose.
+    let mut wallet_credited = 0.0;
+    if let Some(customer_wallet) = customer_wallet.as_mut() {
+        let expired = customer_wallet.expire(now.date_naive());
+        if expired > 0.0 {
+            println!("Wallet credit expired - Customer: {}, Amount: {}", customer_key, expired);
+        }
+        if banks_to_wallet {
+            customer_wallet.credit(discount, now.date_naive(), bill.policy.wallet.expiry_days);
+            wallet_credited = discount;
+            discount = 0.0;
+        }
+    }
 
+    let mut referral_bonus = 0.0;
     if !has_current_week_transaction {
-        if customer_discount_details
-            .customer_expense_map
-            .contains_key(phone_number_str)
-        {
-            let total_pooled_amount = customer_discount_details.total_pooled_amount;
-            let total_eligible_discountees = customer_discount_details.total_eligible_customers
-                + current_week_customer_discount_details.total_eligible_customers;
-            println!(
-                "Calculating discount - Pooled: {}, Eligible: {}",
-                total_pooled_amount, total_eligible_discountees
-            );
-            if total_eligible_discountees > 0.0 {
-                discount = total_pooled_amount / total_eligible_discountees;
-                println!("Discount applied: {}", discount);
-            }
+        let mut reward = referrals::load_pending_reward(business_name, customer_key, conn);
+        if !reward.is_empty() {
+            referral_bonus = reward.redeem(share_value, (amount_float - discount).max(0.0));
+            discount += referral_bonus;
+            referrals::save_pending_reward(business_name, customer_key, &reward, conn);
+            println!("Referral bonus applied: {}", referral_bonus);
+        }
+    }
+
+    let mut wallet_redeemed = 0.0;
+    if let Some(customer_wallet) = customer_wallet.as_mut() {
+        if let Some(requested) = request.redeem_from_wallet {
+            wallet_redeemed = customer_wallet.redeem(requested.min((amount_float - discount).max(0.0)));
+            discount += wallet_redeemed;
         }
+        wallet::save_wallet(business_name, customer_key, customer_wallet, conn);
     }
 
     let final_amount = amount_float - discount;
-    let pooled_amount = final_amount * DISCOUNT_POOL_PERCENTAGE;
-    let discount_perc = if amount_float != 0.0 {
-        (discount / amount_float) * 100.0
-    } else {
-        0.0
-    };
+    let pooled_amount = discount_strategy.pool_contribution(&discount_context, final_amount);
+    let discount_perc = theloyalgame_core::discount_percentage(discount, amount_float);
 
+    let mut current_week_customer_discount_details = bill.current_period;
     let mut current_week_customer_expense_map =
         current_week_customer_discount_details.customer_expense_map;
 
     let mut current_week_total_eligible_customers =
         current_week_customer_discount_details.total_eligible_customers;
 
-    if current_week_customer_expense_map.contains_key(phone_number_str) {
+    if current_week_customer_expense_map.contains_key(customer_key) {
         let particular_customer_current_week_expense_map = current_week_customer_expense_map
-            .get_mut(phone_number_str)
+            .get_mut(customer_key)
             .unwrap();
         let existing_amounts = particular_customer_current_week_expense_map
             .entry(now_date.clone())
-            .or_insert(String::new());
+            .or_default();
         if existing_amounts.is_empty() {
             *existing_amounts = final_amount.to_string();
         } else {
@@ -197,7 +662,7 @@ pub fn get_response(
             HashMap::new();
         particular_customer_current_week_expense_map.insert(now_date.clone(), final_amount.to_string());
         current_week_customer_expense_map.insert(
-            phone_number_str.to_string(),
+            customer_key.to_string(),
             particular_customer_current_week_expense_map,
         );
     }
@@ -211,17 +676,70 @@ pub fn get_response(
     current_week_customer_discount_details.total_eligible_customers =
         current_week_total_eligible_customers;
     current_week_customer_discount_details.total_pooled_amount = current_week_total_pooled_amount;
-    current_week_customer_discount_details.total_discount_given += discount;
+    // Wallet shares count as given when banked, not again when spent.
+    current_week_customer_discount_details.total_discount_given += discount - wallet_redeemed + wallet_credited;
     persist_data_to_redis(
-        &current_week_redis_key,
+        &bill.current_week_redis_key,
         serde_json::to_string(&current_week_customer_discount_details).unwrap(),
         conn,
     );
 
-    format!(
-        "Phone number: {}\n ; Final bill amount: {:.2}\n ; Discount given: {:.2}%",
-        phone_number, final_amount, discount_perc
-    )
+    if bill.previous_streak.as_ref() != Some(&bill.streak) {
+        streaks::save_streak(business_name, customer_key, &bill.streak, conn);
+    }
+
+    transactions::record_transaction(
+        &transactions::TransactionRecord {
+            timestamp: now.to_rfc3339(),
+            business_name: business_name.clone(),
+            customer_id: customer_key.to_string(),
+            bill_amount: amount_float,
+            discount,
+            final_amount,
+            terminal_id: request.terminal_id.clone(),
+            outlet_id: request.outlet_id.clone(),
+            campaign_ids: bill.boost.campaign_ids.clone(),
+            items: request.items.clone(),
+            pool_contribution: Some(pooled_amount),
+            pool_share: Some(pool_share),
+            coalition_id: bill.coalition.map(|coalition| coalition.coalition_id),
+            fraud_flags: fraud_flags.clone(),
+            failed_rules: failed_rules.clone(),
+        },
+        conn,
+    );
+    if !fraud_flags.is_empty() {
+        fraud::queue_review(
+            business_name,
+            &fraud::FraudReview::new(
+                customer_key,
+                request.cashier_id.as_deref(),
+                request.terminal_id.as_deref(),
+                amount_float,
+                discount,
+                fraud_flags,
+            ),
+            conn,
+        );
+    }
+
+    Ok(DiscountOutcome {
+        customer_id: customer_key.to_string(),
+        customer_display: pseudonym::display(customer_key),
+        bill_amount: amount_float,
+        discount,
+        final_amount,
+        discount_percentage: discount_perc,
+        has_transaction: has_current_week_transaction,
+        streak_weeks: bill.streak.weeks,
+        streak_multiplier,
+        referral_bonus,
+        wallet_credited,
+        wallet_redeemed,
+        wallet_balance: customer_wallet.as_ref().map(wallet::Wallet::balance),
+        campaign_ids: bill.boost.campaign_ids,
+        failed_rules,
+    })
 }
 
 pub fn generate_and_store_token(
@@ -229,27 +747,74 @@ pub fn generate_and_store_token(
     business_name: &str,
     conn: &mut redis::Connection,
 ) -> String {
+    generate_and_store_token_with_role(phone_number, business_name, Role::Customer, conn)
+}
+
+pub fn generate_and_store_token_with_role(
+    phone_number: &str,
+    business_name: &str,
+    role: Role,
+    conn: &mut redis::Connection,
+) -> String {
+    if let Some(signer) = signed_token::installed_signer() {
+        return signed_token::issue_signed_token(&signer, phone_number, business_name, role);
+    }
+
     let token = Uuid::new_v4().to_string();
     let expiry_date = (Utc::now() + Duration::days(7))
         .format("%d-%b-%Y")
         .to_string();
     let token_key = format!("token:{}", token);
-    let phone_token_key = format!("phone:{}:token", phone_number);
+    let phone_token_key = format!("phone:{}:token", pseudonym::phone_key(phone_number));
     let business_token_key = format!("{}_token_{}", business_name, token);
 
-    let token_data = format!("{}___{}", token, expiry_date);
+    let token_data = format!(
+        "{}___{}___{}___{}",
+        token,
+        expiry_date,
+        role.as_str(),
+        pseudonym::phone_key(phone_number)
+    );
     persist_data_to_redis(&token_key, token_data, conn);
     persist_data_to_redis(&phone_token_key, token.clone(), conn);
     persist_data_to_redis(&business_token_key, token.clone(), conn);
 
     println!(
-        "Generated token - Token: {}, Expiry: {}, Token Key: {}, Business Key: {}",
-        token, expiry_date, token_key, business_token_key
+        "Generated token - Id: {}, Business: {}, Expiry: {}",
+        auth::token_id(&token),
+        business_name,
+        expiry_date
     );
 
     token
 }
 
+/// Invalidates `token` before its expiry. Signed tokens go on the revocation
+/// list; stored tokens are deleted.
+pub fn revoke_token(
+    token: &str,
+    business_name: &str,
+    conn: &mut redis::Connection,
+) -> Result<(), ApiError> {
+    if signed_token::is_signed_token(token) {
+        let signer = signed_token::installed_signer()
+            .ok_or_else(|| ApiError::invalid_request("Signed tokens are not enabled"))?;
+        let claims = signer
+            .verify(token, Utc::now().timestamp())
+            .map_err(ApiError::invalid_request)?;
+        if claims.business_name != business_name {
+            return Err(ApiError::invalid_request("Token issued for another business"));
+        }
+        signed_token::revoke_signed_token(&claims, conn);
+        return Ok(());
+    }
+
+    validate_token(token, business_name, conn)?;
+    delete_data_from_redis(&format!("token:{}", token), conn);
+    delete_data_from_redis(&format!("{}_token_{}", business_name, token), conn);
+    Ok(())
+}
+
 pub fn fetch_data_from_redis(redis_key: &str, conn: &mut redis::Connection) -> String {
     let exists: bool = redis::cmd("EXISTS")
         .arg(redis_key)
@@ -272,11 +837,102 @@ pub fn persist_data_to_redis(redis_key: &str, value: String, conn: &mut redis::C
         .unwrap_or(());
 }
 
-fn get_latest_monday(week: u32) -> NaiveDate {
+pub fn persist_data_to_redis_with_expiry(
+    redis_key: &str,
+    value: String,
+    expiry_secs: u64,
+    conn: &mut redis::Connection,
+) {
+    let _: () = redis::cmd("SET")
+        .arg(redis_key)
+        .arg(value)
+        .arg("EX")
+        .arg(expiry_secs)
+        .query(conn)
+        .unwrap_or(());
+}
+
+/// Stores `value` only if `redis_key` does not exist yet. Returns whether it
+/// was stored.
+pub fn persist_data_to_redis_if_absent(
+    redis_key: &str,
+    value: String,
+    expiry_secs: u64,
+    conn: &mut redis::Connection,
+) -> bool {
+    let stored: Option<String> = redis::cmd("SET")
+        .arg(redis_key)
+        .arg(value)
+        .arg("NX")
+        .arg("EX")
+        .arg(expiry_secs)
+        .query(conn)
+        .unwrap_or(None);
+    stored.is_some()
+}
+
+/// Keys matching the glob `pattern`. Only meant for maintenance jobs; it
+/// walks the whole keyspace.
+pub fn list_redis_keys(pattern: &str, conn: &mut redis::Connection) -> Vec<String> {
+    redis::cmd("KEYS").arg(pattern).query(conn).unwrap_or_default()
+}
+
+/// `text` with the glob characters of `KEYS` patterns escaped, so it only
+/// matches itself.
+pub fn escape_redis_glob(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+pub fn delete_data_from_redis(redis_key: &str, conn: &mut redis::Connection) {
+    let _: () = redis::cmd("DEL").arg(redis_key).query(conn).unwrap_or(());
+}
+
+/// Increments the counter at `redis_key`, opening a window of `window_secs`
+/// on first use. Returns the new count and the seconds left in the window.
+pub fn increment_counter(
+    redis_key: &str,
+    window_secs: u64,
+    conn: &mut redis::Connection,
+) -> (i64, i64) {
+    let count: i64 = redis::cmd("INCR").arg(redis_key).query(conn).unwrap_or(0);
+    if count == 1 {
+        let _: () = redis::cmd("EXPIRE")
+            .arg(redis_key)
+            .arg(window_secs)
+            .query(conn)
+            .unwrap_or(());
+    }
+    let ttl: i64 = redis::cmd("TTL").arg(redis_key).query(conn).unwrap_or(-1);
+    (count, if ttl < 0 { window_secs as i64 } else { ttl })
+}
+
+/// Redis key of the weekly blob for `business_name` starting on `monday`.
+pub fn period_redis_key(business_name: &str, monday: NaiveDate) -> String {
+    format!("{}___{}", business_name, monday.format("%d-%b-%Y"))
+}
+
+pub fn parse_customer_discount_details(data: &str) -> CustomerDiscountDetails {
+    if data.is_empty() {
+        CustomerDiscountDetails::default()
+    } else {
+        serde_json::from_str(data).unwrap_or_default()
+    }
+}
+
+pub(crate) fn get_latest_monday(week: u32) -> NaiveDate {
     let current_year = chrono::offset::Local::now().year();
     NaiveDate::from_isoywd_opt(current_year, week, Weekday::Mon).unwrap()
 }
 #[cfg(test)]
+// The original tests pass `&mut *conn` for the locked connection.
+#[allow(clippy::explicit_auto_deref)]
 mod test {
     use super::*;
     use chrono::Duration;
@@ -294,6 +950,17 @@ mod test {
         };
     }
 
+    // Weekly blobs are keyed by E.164; fixtures that aren't phone numbers stay as-is.
+    fn stored_phone(phone: &str) -> String {
+        phone::normalize_phone_number(phone, phone::DEFAULT_REGION).unwrap_or_else(|_| phone.to_string())
+    }
+
+    // Tokens and bills are refused for businesses that aren't registered.
+    fn register_test_businesses(conn: &mut redis::Connection) {
+        business::register_business("test102", conn);
+        business::register_business("test103", conn);
+    }
+
     fn setup_previous_week_data(
         conn: &mut redis::Connection,
         business_name: &str,
@@ -306,7 +973,7 @@ mod test {
         let mut customer_discount_details = CustomerDiscountDetails::default();
         let mut expense_map: HashMap<String, String> = HashMap::new();
         expense_map.insert("10-Mar-2025".to_string(), "1000.00".to_string());
-        customer_discount_details.customer_expense_map.insert(phone.to_string(), expense_map);
+        customer_discount_details.customer_expense_map.insert(stored_phone(phone), expense_map);
         customer_discount_details.total_pooled_amount = total_pooled_amount;
         customer_discount_details.total_eligible_customers = total_eligible_customers;
         let serialized_data = serde_json::to_string(&customer_discount_details).unwrap();
@@ -331,7 +998,7 @@ mod test {
             let mut expense_map: HashMap<String, String> = HashMap::new();
             let today = Utc::now().format("%d-%b-%Y").to_string();
             expense_map.insert(today, "500.00".to_string());
-            customer_discount_details.customer_expense_map.insert(phone.to_string(), expense_map);
+            customer_discount_details.customer_expense_map.insert(stored_phone(phone), expense_map);
         }
         customer_discount_details.total_eligible_customers = total_eligible_customers;
         let serialized_data = serde_json::to_string(&customer_discount_details).unwrap();
@@ -347,6 +1014,7 @@ mod test {
         let mut conn = REDIS_CONNECTION.lock().unwrap();
         // Clear Redis before the test
         let _: () = redis::cmd("FLUSHALL").query(&mut *conn).unwrap();
+        register_test_businesses(&mut *conn);
         let keys_after_flush: Vec<String> = redis::cmd("KEYS").arg("*").query(&mut *conn).unwrap();
         println!("Keys after FLUSHALL: {:?}", keys_after_flush);
 
@@ -379,6 +1047,7 @@ mod test {
         let mut conn = REDIS_CONNECTION.lock().unwrap();
         // Clear Redis before the test
         let _: () = redis::cmd("FLUSHALL").query(&mut *conn).unwrap();
+        register_test_businesses(&mut *conn);
         let keys_after_flush: Vec<String> = redis::cmd("KEYS").arg("*").query(&mut *conn).unwrap();
         println!("Keys after FLUSHALL: {:?}", keys_after_flush);
 
@@ -411,6 +1080,7 @@ mod test {
         let mut conn = REDIS_CONNECTION.lock().unwrap();
         // Clear Redis before the test
         let _: () = redis::cmd("FLUSHALL").query(&mut *conn).unwrap();
+        register_test_businesses(&mut *conn);
         let keys_after_flush: Vec<String> = redis::cmd("KEYS").arg("*").query(&mut *conn).unwrap();
         println!("Keys after FLUSHALL: {:?}", keys_after_flush);
 
@@ -439,6 +1109,7 @@ mod test {
         let mut conn = REDIS_CONNECTION.lock().unwrap();
         // Clear Redis before the test
         let _: () = redis::cmd("FLUSHALL").query(&mut *conn).unwrap();
+        register_test_businesses(&mut *conn);
         let keys_after_flush: Vec<String> = redis::cmd("KEYS").arg("*").query(&mut *conn).unwrap();
         println!("Keys after FLUSHALL: {:?}", keys_after_flush);
 
@@ -471,6 +1142,7 @@ mod test {
         let mut conn = REDIS_CONNECTION.lock().unwrap();
         // Clear Redis before the test
         let _: () = redis::cmd("FLUSHALL").query(&mut *conn).unwrap();
+        register_test_businesses(&mut *conn);
         let keys_after_flush: Vec<String> = redis::cmd("KEYS").arg("*").query(&mut *conn).unwrap();
         println!("Keys after FLUSHALL: {:?}", keys_after_flush);
 
@@ -503,6 +1175,7 @@ mod test {
         let mut conn = REDIS_CONNECTION.lock().unwrap();
         // Clear Redis before the test
         let _: () = redis::cmd("FLUSHALL").query(&mut *conn).unwrap();
+        register_test_businesses(&mut *conn);
         let keys_after_flush: Vec<String> = redis::cmd("KEYS").arg("*").query(&mut *conn).unwrap();
         println!("Keys after FLUSHALL: {:?}", keys_after_flush);
 
@@ -521,6 +1194,7 @@ mod test {
         let mut conn = REDIS_CONNECTION.lock().unwrap();
         // Clear Redis before the test
         let _: () = redis::cmd("FLUSHALL").query(&mut *conn).unwrap();
+        register_test_businesses(&mut *conn);
         let keys_after_flush: Vec<String> = redis::cmd("KEYS").arg("*").query(&mut *conn).unwrap();
         println!("Keys after FLUSHALL: {:?}", keys_after_flush);
 
@@ -535,4 +1209,1499 @@ mod test {
         println!("Test get_response_wrong_user: {}", result);
         assert_eq!(result, "Not authorized / Token expired.");
     }
+
+    #[test]
+    fn test_validate_token_returns_role() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        let phone = "9876543210";
+        let customer_token = generate_and_store_token(phone, "test102", &mut conn);
+        assert_eq!(validate_token(&customer_token, "test102", &mut conn), Ok(Role::Customer));
+
+        let owner_token = generate_and_store_token_with_role(phone, "test102", Role::Owner, &mut conn);
+        assert_eq!(validate_token(&owner_token, "test102", &mut conn), Ok(Role::Owner));
+
+        // Tokens stored before roles were introduced have no role part. They
+        // all came from the widget.
+        let expiry_date = (Utc::now() + Duration::days(7)).format("%d-%b-%Y");
+        persist_data_to_redis("token:legacy", format!("legacy___{}", expiry_date), &mut conn);
+        persist_data_to_redis("test102_token_legacy", "legacy".to_string(), &mut conn);
+        assert_eq!(validate_token("legacy", "test102", &mut conn), Ok(Role::Customer));
+    }
+
+    struct CapturingSender(Mutex<Vec<String>>);
+
+    impl otp::MessageSender for CapturingSender {
+        fn send(&self, _phone_number: &str, message: &str) -> Result<(), String> {
+            self.0.lock().unwrap().push(message.to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_otp_flow() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        let phone = "9876543210";
+        let sender = CapturingSender(Mutex::new(Vec::new()));
+        otp::request_otp(phone, "test102", &sender, &mut conn).unwrap();
+        let message = sender.0.lock().unwrap().pop().unwrap();
+        let code = message
+            .split_whitespace()
+            .map(|word| word.trim_end_matches('.'))
+            .find(|word| word.len() == 6 && word.chars().all(|c| c.is_ascii_digit()))
+            .unwrap()
+            .to_string();
+        let wrong = if code == "000000" { "111111" } else { "000000" };
+
+        assert_eq!(
+            otp::verify_otp(phone, "test102", wrong, &mut conn),
+            Err(otp::OtpError::InvalidCode)
+        );
+        assert_eq!(otp::verify_otp(phone, "test101", &code, &mut conn), Err(otp::OtpError::NoPendingCode));
+        assert_eq!(otp::verify_otp(phone, "test102", &code, &mut conn), Ok(()));
+        // Codes are single use.
+        assert_eq!(otp::verify_otp(phone, "test102", &code, &mut conn), Err(otp::OtpError::NoPendingCode));
+    }
+
+    #[test]
+    fn test_otp_requests_are_rate_limited() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        let sender = CapturingSender(Mutex::new(Vec::new()));
+        for _ in 0..otp::OTP_REQUEST_LIMIT {
+            otp::request_otp("9876543210", "test102", &sender, &mut conn).unwrap();
+        }
+        assert!(matches!(
+            otp::request_otp("9876543210", "test102", &sender, &mut conn),
+            Err(otp::OtpError::RateLimited { .. })
+        ));
+        // Other phones are unaffected.
+        assert!(otp::request_otp("9876543211", "test102", &sender, &mut conn).is_ok());
+    }
+
+    #[test]
+    fn test_otp_burned_after_max_attempts() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        let sender = CapturingSender(Mutex::new(Vec::new()));
+        otp::request_otp("9876543210", "test102", &sender, &mut conn).unwrap();
+        for _ in 1..otp::OTP_MAX_ATTEMPTS {
+            assert_eq!(
+                otp::verify_otp("9876543210", "test102", "abcdef", &mut conn),
+                Err(otp::OtpError::InvalidCode)
+            );
+        }
+        assert_eq!(
+            otp::verify_otp("9876543210", "test102", "abcdef", &mut conn),
+            Err(otp::OtpError::TooManyAttempts)
+        );
+    }
+
+    #[test]
+    fn test_revoke_stored_token() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        let token = generate_and_store_token("9876543210", "test102", &mut conn);
+        assert_eq!(
+            revoke_token(&token, "test101", &mut conn).map_err(|error| error.code),
+            Err(ErrorCode::TokenExpired)
+        );
+        assert_eq!(revoke_token(&token, "test102", &mut conn), Ok(()));
+        assert!(validate_token(&token, "test102", &mut conn).is_err());
+    }
+
+    #[test]
+    fn test_signed_token_revocations_are_kept_apart() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        let now = Utc::now().timestamp();
+        let claims = |jti: &str, exp: i64| signed_token::TokenClaims {
+            business_name: "test102".to_string(),
+            customer_key: "+919876543210".to_string(),
+            role: Role::Customer,
+            exp,
+            jti: jti.to_string(),
+        };
+        signed_token::revoke_signed_token(&claims("first", now + 600), &mut conn);
+        signed_token::revoke_signed_token(&claims("second", now + 600), &mut conn);
+        signed_token::revoke_signed_token(&claims("expired", now - 1), &mut conn);
+        assert!(signed_token::is_revoked("first", now, &mut conn));
+        assert!(signed_token::is_revoked("second", now, &mut conn));
+        let remaining: Vec<String> =
+            redis::cmd("ZRANGE").arg("revoked_token_ids").arg(0).arg(-1).query(&mut conn).unwrap();
+        assert_eq!(remaining, vec!["first", "second"]);
+    }
+
+    #[test]
+    fn test_invalid_amounts_are_refused() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        for amount in ["abc", "NaN", "inf", "-5", ""] {
+            let parsed = parse_phone_number_amount("test102".to_string(), &format!("9876543210,{}", amount));
+            assert_eq!(parsed.map_err(|error| error.code), Err(ErrorCode::InvalidRequest), "{}", amount);
+        }
+        assert_eq!(
+            parse_phone_number_amount("test102".to_string(), "9876543210, 600.50").unwrap().amount,
+            600.5
+        );
+
+        for amount in [f64::NAN, f64::INFINITY, -1.0] {
+            let request = DiscountRequest {
+                business_name: "test102".to_string(),
+                customer_id: "9876543210".to_string(),
+                amount,
+                ..DiscountRequest::default()
+            };
+            assert_eq!(
+                process_discount(&request, &mut conn).map_err(|error| error.code),
+                Err(ErrorCode::InvalidRequest)
+            );
+        }
+        assert_eq!(list_redis_keys("*", &mut conn), vec!["businesses".to_string()]);
+    }
+
+    #[test]
+    fn test_terminal_signed_request() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        let terminal = terminal::register_terminal("test102", "till-1", None, &mut conn);
+        let now = Utc::now().timestamp();
+        let body = br#"{"phone_number":"9876543210","amount":100.0}"#;
+        let signature = terminal::sign_request(&terminal.secret, "POST", "/terminal/discounts", now, body);
+        let timestamp = now.to_string();
+        let request = terminal::SignedRequest {
+            terminal_id: &terminal.terminal_id,
+            timestamp: &timestamp,
+            signature: &signature,
+            method: "POST",
+            path: "/terminal/discounts",
+            body,
+        };
+
+        let verified = terminal::verify_signed_request(&request, now, &mut conn).unwrap();
+        assert_eq!(verified.business_name, "test102");
+        // The same signature can't be used twice.
+        assert!(terminal::verify_signed_request(&request, now, &mut conn).is_err());
+        // Nor can a request far outside the window.
+        assert!(terminal::verify_signed_request(
+            &request,
+            now + 2 * terminal::SIGNATURE_WINDOW_SECS,
+            &mut conn
+        )
+        .is_err());
+
+        let tampered = terminal::SignedRequest {
+            body: br#"{"phone_number":"9876543210","amount":1.0}"#,
+            ..request
+        };
+        assert!(terminal::verify_signed_request(&tampered, now, &mut conn).is_err());
+
+        // Once deleted, the terminal's fresh signatures are refused.
+        assert!(terminal::delete_terminal("test101", &terminal.terminal_id, "owner", &mut conn).is_err());
+        terminal::delete_terminal("test102", &terminal.terminal_id, "owner", &mut conn).unwrap();
+        let signature = terminal::sign_request(&terminal.secret, "POST", "/terminal/discounts", now + 1, body);
+        let timestamp = (now + 1).to_string();
+        let request = terminal::SignedRequest {
+            timestamp: &timestamp,
+            signature: &signature,
+            ..request
+        };
+        assert!(terminal::verify_signed_request(&request, now + 1, &mut conn).is_err());
+        let audit = audit::load_audit("test102", &mut conn);
+        assert_eq!(audit.last().unwrap().action, "terminal_deleted");
+    }
+
+    #[test]
+    fn test_transaction_records_terminal() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        let request = DiscountRequest {
+            business_name: "test102".to_string(),
+            customer_id: "9876543210".to_string(),
+            amount: 250.0,
+            terminal_id: Some("term_1".to_string()),
+            ..DiscountRequest::default()
+        };
+        let outcome = process_discount(&request, &mut conn).unwrap();
+        assert_eq!(outcome.final_amount, 250.0);
+
+        let recorded = transactions::load_transactions("test102", Utc::now().date_naive(), &mut conn);
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0].terminal_id.as_deref(), Some("term_1"));
+        assert_eq!(recorded[0].bill_amount, 250.0);
+    }
+
+    #[test]
+    fn test_phone_spellings_share_one_customer() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        // Data written before normalization, under two spellings of one number.
+        let current_monday = get_latest_monday(Utc::now().iso_week().week());
+        let key = period_redis_key("test102", current_monday);
+        let mut details = CustomerDiscountDetails {
+            total_eligible_customers: 2.0,
+            ..CustomerDiscountDetails::default()
+        };
+        for raw in ["9876543210", "09876543210"] {
+            let mut expense_map = HashMap::new();
+            expense_map.insert("13-Oct-2026".to_string(), "100".to_string());
+            details.customer_expense_map.insert(raw.to_string(), expense_map);
+        }
+        persist_data_to_redis(&key, serde_json::to_string(&details).unwrap(), &mut conn);
+        persist_data_to_redis("phone:9876543210:token", "legacy".to_string(), &mut conn);
+
+        let report = migrations::normalize_stored_phone_numbers(&mut conn);
+        assert_eq!(report.customers_merged, 1);
+        assert_eq!(report.keys_renamed, 1);
+        assert_eq!(fetch_data_from_redis("phone:+919876543210:token", &mut conn), "legacy");
+
+        let details = parse_customer_discount_details(&fetch_data_from_redis(&key, &mut conn));
+        assert_eq!(details.total_eligible_customers, 1.0);
+        assert_eq!(
+            details.customer_expense_map.keys().collect::<Vec<_>>(),
+            vec!["+919876543210"]
+        );
+
+        // Billing under yet another spelling lands on the same customer.
+        let request = DiscountRequest {
+            business_name: "test102".to_string(),
+            customer_id: "+91 98765 43210".to_string(),
+            amount: 100.0,
+            ..DiscountRequest::default()
+        };
+        let outcome = process_discount(&request, &mut conn).unwrap();
+        assert_eq!(outcome.customer_id, "+919876543210");
+        let details = parse_customer_discount_details(&fetch_data_from_redis(&key, &mut conn));
+        assert_eq!(details.customer_expense_map.len(), 1);
+        assert_eq!(details.total_eligible_customers, 1.0);
+    }
+
+    #[test]
+    fn test_phone_migration_moves_customer_records() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        for prefix in ["wallet", "streak", "referral_code_of", "referral_reward", "fraud_blocked"] {
+            persist_data_to_redis(&format!("{}:test102:9876543210", prefix), prefix.to_string(), &mut conn);
+        }
+        persist_data_to_redis("referral_code:test102:CODE1234", "9876543210".to_string(), &mut conn);
+        persist_data_to_redis(
+            "referred_by:test102:09812345678",
+            serde_json::json!({ "code": "CODE1234", "referrer": "9876543210", "referee": "09812345678" }).to_string(),
+            &mut conn,
+        );
+
+        let report = migrations::normalize_stored_phone_numbers(&mut conn);
+        assert_eq!(report.keys_renamed, 6);
+        for prefix in ["wallet", "streak", "referral_code_of", "referral_reward", "fraud_blocked"] {
+            assert_eq!(fetch_data_from_redis(&format!("{}:test102:+919876543210", prefix), &mut conn), prefix);
+            assert_eq!(fetch_data_from_redis(&format!("{}:test102:9876543210", prefix), &mut conn), "");
+        }
+        assert_eq!(fetch_data_from_redis("referral_code:test102:CODE1234", &mut conn), "+919876543210");
+        let referral: serde_json::Value =
+            serde_json::from_str(&fetch_data_from_redis("referred_by:test102:+919812345678", &mut conn)).unwrap();
+        assert_eq!(referral["referrer"], "+919876543210");
+        assert_eq!(referral["referee"], "+919812345678");
+        assert!(fraud::is_blocked("test102", "+919876543210", &mut conn));
+    }
+
+    #[test]
+    fn test_linked_card_shares_discount_history() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        setup_previous_week_data(&mut conn, "test102", "9876543210", 30.0, 1.0);
+        let phone = CustomerId::parse("9876543210", "IN").unwrap();
+        let card = CustomerId::parse("LC 1234 5678", "IN").unwrap();
+        let email = CustomerId::parse("asha@example.com", "IN").unwrap();
+        customer::link_customer_id("test102", &phone, &card, &mut conn).unwrap();
+        let profile = customer::link_customer_id("test102", &card, &email, &mut conn).unwrap();
+        assert_eq!(profile.profile_id, "+919876543210");
+        assert_eq!(
+            profile.identifiers,
+            vec!["+919876543210", "card:LC12345678", "email:asha@example.com"]
+        );
+
+        // Billing by card earns the discount owed to the phone's history.
+        let request = DiscountRequest {
+            business_name: "test102".to_string(),
+            customer_id: "LC12345678".to_string(),
+            amount: 100.0,
+            ..DiscountRequest::default()
+        };
+        let outcome = process_discount(&request, &mut conn).unwrap();
+        assert_eq!(outcome.customer_id, "+919876543210");
+        assert_eq!(outcome.discount, 30.0);
+
+        // A second visit the same day, by email, counts as the same customer.
+        let request = DiscountRequest {
+            customer_id: "Asha@Example.com".to_string(),
+            ..request
+        };
+        assert!(process_discount(&request, &mut conn).unwrap().has_transaction);
+
+        // An identifier with its own history can't be silently absorbed.
+        let other = CustomerId::parse("9876543211", "IN").unwrap();
+        setup_current_week_data(&mut conn, "test102", "9876543211", true, 1.0);
+        assert_eq!(
+            customer::link_customer_id("test102", &phone, &other, &mut conn).map_err(|e| e.code),
+            Err(ErrorCode::Conflict)
+        );
+    }
+
+    #[test]
+    fn test_streak_bonus_multiplies_pool_share() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        let mut business_policy = policy::load_policy("test102", &mut conn);
+        business_policy.streak_bonuses = vec![policy::StreakBonus { min_weeks: 3, multiplier: 1.5 }];
+        policy::save_policy("test102", &business_policy, &mut conn).unwrap();
+        setup_previous_week_data(&mut conn, "test102", "9876543210", 60.0, 2.0);
+        let current_monday = get_latest_monday(Utc::now().iso_week().week());
+        let redis_key = period_redis_key("test102", current_monday - Duration::days(7));
+        let mut details = parse_customer_discount_details(&fetch_data_from_redis(&redis_key, &mut conn));
+        details
+            .customer_expense_map
+            .insert("+919876543211".to_string(), [("10-Mar-2025".to_string(), "800.00".to_string())].into());
+        persist_data_to_redis(&redis_key, serde_json::to_string(&details).unwrap(), &mut conn);
+
+        // Billed two weeks ago and last week: this visit makes three.
+        streaks::save_streak(
+            "test102",
+            "+919876543210",
+            &streaks::CustomerStreak {
+                last_period: streaks::period_label(current_monday - Duration::days(7)),
+                weeks: 2,
+            },
+            &mut conn,
+        );
+        let request = DiscountRequest {
+            business_name: "test102".to_string(),
+            customer_id: "9876543210".to_string(),
+            amount: 100.0,
+            ..DiscountRequest::default()
+        };
+        let outcome = process_discount(&request, &mut conn).unwrap();
+        assert_eq!(outcome.streak_weeks, 3);
+        assert_eq!(outcome.streak_multiplier, 1.5);
+        assert_eq!(outcome.discount, 45.0);
+
+        // A customer seen only last week starts at two weeks, below the bonus.
+        let outcome = process_discount(
+            &DiscountRequest {
+                customer_id: "9876543211".to_string(),
+                ..request
+            },
+            &mut conn,
+        )
+        .unwrap();
+        assert_eq!(outcome.streak_weeks, 2);
+        assert_eq!(outcome.streak_multiplier, 1.0);
+        assert_eq!(
+            streaks::load_streak("test102", "+919876543211", &mut conn).map(|streak| streak.weeks),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn test_referral_credits_both_customers() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        let mut business_policy = policy::load_policy("test102", &mut conn);
+        business_policy.referral_bonus = Some(policy::ReferralBonus {
+            kind: policy::ReferralBonusKind::FlatCredit,
+            amount: 50.0,
+        });
+        policy::save_policy("test102", &business_policy, &mut conn).unwrap();
+        setup_previous_week_data(&mut conn, "test102", "9876543210", 30.0, 1.0);
+
+        // Only customers with history can refer.
+        assert_eq!(
+            referrals::referral_code("test102", "+919812345678", &mut conn).map_err(|e| e.code),
+            Err(ErrorCode::Forbidden)
+        );
+        let code = referrals::referral_code("test102", "+919876543210", &mut conn).unwrap();
+        assert_eq!(referrals::referral_code("test102", "+919876543210", &mut conn).unwrap(), code);
+
+        let request = DiscountRequest {
+            business_name: "test102".to_string(),
+            customer_id: "9876543210".to_string(),
+            amount: 100.0,
+            referral_code: Some(code.to_lowercase()),
+            device_id: Some("device-1".to_string()),
+            ..DiscountRequest::default()
+        };
+        assert_eq!(
+            process_discount(&request, &mut conn).map_err(|e| e.code),
+            Err(ErrorCode::Forbidden)
+        );
+
+        let referee = DiscountRequest {
+            customer_id: "98123 45678".to_string(),
+            ..request.clone()
+        };
+        let outcome = process_discount(&referee, &mut conn).unwrap();
+        assert_eq!(outcome.referral_bonus, 50.0);
+        assert_eq!(outcome.final_amount, 50.0);
+        // The referee has been billed now, so the code can't be used again.
+        assert_eq!(
+            process_discount(&referee, &mut conn).map_err(|e| e.code),
+            Err(ErrorCode::Conflict)
+        );
+
+        // The referrer gets their pool share and the credit on the next bill.
+        let outcome = process_discount(
+            &DiscountRequest {
+                referral_code: None,
+                ..request.clone()
+            },
+            &mut conn,
+        )
+        .unwrap();
+        assert_eq!(outcome.discount, 30.0 / 2.0 + 50.0);
+        assert_eq!(outcome.referral_bonus, 50.0);
+
+        // One device can't keep redeeming codes for fresh numbers.
+        for phone in ["9812345671", "9812345672"] {
+            let _ = process_discount(
+                &DiscountRequest {
+                    customer_id: phone.to_string(),
+                    ..request.clone()
+                },
+                &mut conn,
+            );
+        }
+        assert_eq!(
+            process_discount(
+                &DiscountRequest {
+                    customer_id: "9812345673".to_string(),
+                    ..request.clone()
+                },
+                &mut conn,
+            )
+            .map_err(|e| e.code),
+            Err(ErrorCode::RateLimited)
+        );
+
+        // Sending a fresh device id each time doesn't get around the address.
+        for (index, phone) in ["9812345674", "9812345675", "9812345676"].iter().enumerate() {
+            let result = process_discount(
+                &DiscountRequest {
+                    customer_id: phone.to_string(),
+                    device_id: Some(format!("fresh-{}", index)),
+                    client_address: Some("203.0.113.7".to_string()),
+                    ..request.clone()
+                },
+                &mut conn,
+            );
+            assert_eq!(result.is_ok(), index < 2);
+        }
+    }
+
+    #[test]
+    fn test_wallet_banks_shares_until_redeemed() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        let mut business_policy = policy::load_policy("test102", &mut conn);
+        business_policy.wallet = policy::WalletPolicy { enabled: true, expiry_days: Some(90) };
+        policy::save_policy("test102", &business_policy, &mut conn).unwrap();
+        setup_previous_week_data(&mut conn, "test102", "9876543210", 30.0, 1.0);
+
+        let request = DiscountRequest {
+            business_name: "test102".to_string(),
+            customer_id: "9876543210".to_string(),
+            amount: 100.0,
+            redeem_from_wallet: Some(10.0),
+            ..DiscountRequest::default()
+        };
+        // Without opting in, there is nothing to redeem from.
+        assert_eq!(
+            process_discount(&request, &mut conn).map_err(|e| e.code),
+            Err(ErrorCode::InvalidRequest)
+        );
+
+        let mut customer_wallet = wallet::load_wallet("test102", "+919876543210", &mut conn);
+        customer_wallet.enabled = true;
+        wallet::save_wallet("test102", "+919876543210", &customer_wallet, &mut conn);
+
+        // The share is banked and 10 of it spent straight away.
+        let outcome = process_discount(&request, &mut conn).unwrap();
+        assert_eq!(outcome.wallet_credited, 30.0);
+        assert_eq!(outcome.wallet_redeemed, 10.0);
+        assert_eq!(outcome.discount, 10.0);
+        assert_eq!(outcome.wallet_balance, Some(20.0));
+
+        // Later bills the same day earn nothing but can still spend the balance.
+        let outcome = process_discount(
+            &DiscountRequest {
+                amount: 15.0,
+                redeem_from_wallet: Some(50.0),
+                ..request.clone()
+            },
+            &mut conn,
+        )
+        .unwrap();
+        assert_eq!(outcome.wallet_credited, 0.0);
+        assert_eq!(outcome.wallet_redeemed, 15.0);
+        assert_eq!(outcome.final_amount, 0.0);
+        assert_eq!(outcome.wallet_balance, Some(5.0));
+
+        // Turning wallets off leaves what is left redeemable.
+        business_policy.wallet.enabled = false;
+        policy::save_policy("test102", &business_policy, &mut conn).unwrap();
+        let outcome = process_discount(
+            &DiscountRequest {
+                amount: 20.0,
+                redeem_from_wallet: Some(50.0),
+                ..request
+            },
+            &mut conn,
+        )
+        .unwrap();
+        assert_eq!(outcome.wallet_redeemed, 5.0);
+        assert_eq!(outcome.final_amount, 15.0);
+        assert_eq!(outcome.wallet_balance, Some(0.0));
+
+        let current_monday = get_latest_monday(Utc::now().iso_week().week());
+        let details = parse_customer_discount_details(&fetch_data_from_redis(
+            &period_redis_key("test102", current_monday),
+            &mut conn,
+        ));
+        assert_eq!(details.total_discount_given, 30.0);
+    }
+
+    #[test]
+    fn test_active_campaign_boosts_bill() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        setup_previous_week_data(&mut conn, "test102", "9876543210", 30.0, 1.0);
+        let now = Utc::now();
+        let schedule = campaigns::CampaignSchedule {
+            starts_at: (now - Duration::hours(1)).to_rfc3339(),
+            ends_at: (now + Duration::hours(1)).to_rfc3339(),
+            days: Vec::new(),
+            daily_from: None,
+            daily_until: None,
+            utc_offset_minutes: 0,
+        };
+        let double_rewards = campaigns::create_campaign(
+            "test102",
+            campaigns::Campaign {
+                campaign_id: String::new(),
+                name: "Double rewards".to_string(),
+                schedule: schedule.clone(),
+                outlet_ids: Vec::new(),
+                terminal_ids: Vec::new(),
+                pool_percentage: Some(0.05),
+                share_multiplier: Some(2.0),
+            },
+            &mut conn,
+        )
+        .unwrap();
+        // Limited to a till this bill doesn't come from.
+        campaigns::create_campaign(
+            "test102",
+            campaigns::Campaign {
+                campaign_id: String::new(),
+                name: "Till 2 only".to_string(),
+                schedule,
+                outlet_ids: Vec::new(),
+                terminal_ids: vec!["term_2".to_string()],
+                pool_percentage: Some(0.5),
+                share_multiplier: None,
+            },
+            &mut conn,
+        )
+        .unwrap();
+
+        let request = DiscountRequest {
+            business_name: "test102".to_string(),
+            customer_id: "9876543210".to_string(),
+            amount: 100.0,
+            terminal_id: Some("term_1".to_string()),
+            ..DiscountRequest::default()
+        };
+        let outcome = process_discount(&request, &mut conn).unwrap();
+        assert_eq!(outcome.discount, 60.0);
+        assert_eq!(outcome.campaign_ids, vec![double_rewards.campaign_id.clone()]);
+
+        let current_monday = get_latest_monday(now.iso_week().week());
+        let details = parse_customer_discount_details(&fetch_data_from_redis(
+            &period_redis_key("test102", current_monday),
+            &mut conn,
+        ));
+        assert!((details.total_pooled_amount - 40.0 * 0.05).abs() < 1e-9);
+        let records = transactions::load_transactions("test102", now.date_naive(), &mut conn);
+        assert_eq!(records[0].campaign_ids, vec![double_rewards.campaign_id]);
+    }
+
+    #[test]
+    fn test_itemized_bill_uses_category_rates() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        let mut business_policy = policy::load_policy("test102", &mut conn);
+        business_policy.category_rates = [("alcohol".to_string(), 0.0)].into();
+        policy::save_policy("test102", &business_policy, &mut conn).unwrap();
+
+        let line = |category: &str, amount: f64| items::LineItem {
+            description: None,
+            category: category.to_string(),
+            amount,
+        };
+        let request = DiscountRequest {
+            business_name: "test102".to_string(),
+            customer_id: "9876543210".to_string(),
+            amount: 100.0,
+            items: vec![line("Alcohol", 60.0), line("food", 30.0)],
+            ..DiscountRequest::default()
+        };
+        assert_eq!(
+            process_discount(&request, &mut conn).map_err(|e| e.code),
+            Err(ErrorCode::InvalidRequest)
+        );
+
+        let request = DiscountRequest {
+            items: vec![line("Alcohol", 60.0), line("food", 40.0)],
+            ..request
+        };
+        process_discount(&request, &mut conn).unwrap();
+
+        let current_monday = get_latest_monday(Utc::now().iso_week().week());
+        let details = parse_customer_discount_details(&fetch_data_from_redis(
+            &period_redis_key("test102", current_monday),
+            &mut conn,
+        ));
+        assert!((details.total_pooled_amount - 40.0 * 0.03).abs() < 1e-9);
+        let records = transactions::load_transactions("test102", Utc::now().date_naive(), &mut conn);
+        assert_eq!(records[0].items, request.items);
+        assert!((records[0].pool_contribution.unwrap() - 1.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_outlet_pools_and_report_breakdown() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        let downtown = outlets::create_outlet("test102", "Downtown", &mut conn).unwrap();
+        let airport = outlets::create_outlet("test102", "Airport", &mut conn).unwrap();
+        assert!(outlets::create_outlet("test102", "downtown", &mut conn).is_err());
+        let mut business_policy = policy::load_policy("test102", &mut conn);
+        business_policy.pool_scope = policy::PoolScope::Outlet;
+        policy::save_policy("test102", &business_policy, &mut conn).unwrap();
+
+        // Only the downtown pool has anything to share.
+        let downtown_pool = outlets::outlet_pool_name("test102", &downtown.outlet_id);
+        setup_previous_week_data(&mut conn, &downtown_pool, "9876543210", 30.0, 1.0);
+        setup_previous_week_data(&mut conn, &outlets::outlet_pool_name("test102", &airport.outlet_id), "9876543210", 0.0, 1.0);
+
+        let request = DiscountRequest {
+            business_name: "test102".to_string(),
+            customer_id: "9876543210".to_string(),
+            amount: 100.0,
+            outlet_id: Some("out_missing".to_string()),
+            ..DiscountRequest::default()
+        };
+        assert_eq!(
+            process_discount(&request, &mut conn).map_err(|e| e.code),
+            Err(ErrorCode::InvalidRequest)
+        );
+        let at_airport = process_discount(
+            &DiscountRequest { outlet_id: Some(airport.outlet_id.clone()), ..request.clone() },
+            &mut conn,
+        )
+        .unwrap();
+        assert_eq!(at_airport.discount, 0.0);
+        let at_downtown = process_discount(
+            &DiscountRequest { outlet_id: Some(downtown.outlet_id.clone()), ..request },
+            &mut conn,
+        )
+        .unwrap();
+        assert_eq!(at_downtown.discount, 30.0);
+
+        let current_monday = get_latest_monday(Utc::now().iso_week().week());
+        let details = parse_customer_discount_details(&fetch_data_from_redis(
+            &period_redis_key(&downtown_pool, current_monday),
+            &mut conn,
+        ));
+        assert!((details.total_pooled_amount - 70.0 * 0.03).abs() < 1e-9);
+
+        let summary = reports::period_summary("test102", current_monday, &mut conn);
+        assert_eq!(summary.transactions, 2);
+        assert!((summary.total_pooled_amount - 170.0 * 0.03).abs() < 1e-9);
+        let breakdown: HashMap<_, _> = summary
+            .outlets
+            .iter()
+            .map(|activity| (activity.name.clone().unwrap(), activity))
+            .collect();
+        assert_eq!(breakdown["Downtown"].discount_given, 30.0);
+        assert_eq!(breakdown["Airport"].discount_given, 0.0);
+        assert!((breakdown["Airport"].pool_contribution - 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_coalition_shares_one_pool() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        business::register_business("test102", &mut conn);
+        business::register_business("test103", &mut conn);
+        let members = vec!["test102".to_string(), "test103".to_string()];
+        let coalition = coalitions::create_coalition("Market street", &members, &mut conn).unwrap();
+        assert_eq!(
+            coalitions::create_coalition("Again", &members, &mut conn).map_err(|e| e.code),
+            Err(ErrorCode::Conflict)
+        );
+
+        // Shopped at test102 last week, redeems at test103.
+        let pool_name = coalitions::coalition_pool_name(&coalition.coalition_id);
+        setup_previous_week_data(&mut conn, &pool_name, "9876543210", 30.0, 1.0);
+        let outcome = process_discount(
+            &DiscountRequest {
+                business_name: "test103".to_string(),
+                customer_id: "9876543210".to_string(),
+                amount: 100.0,
+                ..DiscountRequest::default()
+            },
+            &mut conn,
+        )
+        .unwrap();
+        assert_eq!(outcome.discount, 30.0);
+        process_discount(
+            &DiscountRequest {
+                business_name: "test102".to_string(),
+                customer_id: "9812345678".to_string(),
+                amount: 100.0,
+                ..DiscountRequest::default()
+            },
+            &mut conn,
+        )
+        .unwrap();
+
+        let current_monday = get_latest_monday(Utc::now().iso_week().week());
+        let details = parse_customer_discount_details(&fetch_data_from_redis(
+            &period_redis_key(&pool_name, current_monday),
+            &mut conn,
+        ));
+        assert_eq!(details.customer_expense_map.len(), 2);
+        assert!((details.total_pooled_amount - 170.0 * 0.03).abs() < 1e-9);
+
+        let settlement = coalitions::settlement(&coalition, current_monday, &mut conn);
+        assert_eq!(settlement.total_funded, 30.0);
+        assert_eq!(settlement.members[1].business_name, "test103");
+        // Nothing was contributed last week, so both carry half the payouts.
+        assert_eq!(settlement.members[1].balance, 15.0);
+        assert_eq!(settlement.members[0].balance, -15.0);
+        // Next week's payouts come out of this week's contributions.
+        let settlement = coalitions::settlement(&coalition, current_monday + Duration::days(7), &mut conn);
+        assert!((settlement.members[0].contributed - 3.0).abs() < 1e-9);
+        assert!((settlement.members[1].contributed - 2.1).abs() < 1e-9);
+
+        // A dissolved coalition stops pooling but can still be settled.
+        assert!(coalitions::delete_coalition(&coalition.coalition_id, &mut conn).is_some());
+        assert!(coalitions::delete_coalition(&coalition.coalition_id, &mut conn).is_none());
+        assert!(coalitions::coalition_of("test102", &mut conn).is_none());
+        let dissolved = coalitions::load_coalition(&coalition.coalition_id, &mut conn).unwrap();
+        assert!(!dissolved.active);
+        assert_eq!(coalitions::settlement(&dissolved, current_monday, &mut conn).total_funded, 30.0);
+        assert!(coalitions::create_coalition("Again", &members, &mut conn).is_ok());
+    }
+
+    #[test]
+    fn test_fraud_rules_flag_and_block_bills() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        let mut business_policy = policy::load_policy("test102", &mut conn);
+        business_policy.fraud = policy::FraudPolicy {
+            customer_bills_per_hour: Some(policy::FraudRule { limit: 1.0, action: policy::FraudAction::Flag }),
+            new_customers_per_cashier_per_hour: Some(policy::FraudRule { limit: 1.0, action: policy::FraudAction::Block }),
+            discount_ratio: Some(policy::FraudRule { limit: 0.5, action: policy::FraudAction::Flag }),
+            ..policy::FraudPolicy::default()
+        };
+        policy::save_policy("test102", &business_policy, &mut conn).unwrap();
+
+        let request = DiscountRequest {
+            business_name: "test102".to_string(),
+            customer_id: "9876543210".to_string(),
+            amount: 100.0,
+            cashier_id: Some(auth::token_id("cashier-token")),
+            ..DiscountRequest::default()
+        };
+        process_discount(&request, &mut conn).unwrap();
+        assert!(fraud::load_reviews("test102", &mut conn).is_empty());
+        // A second bill within the hour is queued for review.
+        process_discount(&request, &mut conn).unwrap();
+        let reviews = fraud::load_reviews("test102", &mut conn);
+        assert_eq!(reviews.len(), 1);
+        assert_eq!(reviews[0].customer_id, "+919876543210");
+        assert_eq!(reviews[0].cashier_id, request.cashier_id);
+
+        // A second new customer from the same token is refused outright.
+        let other_customer = DiscountRequest { customer_id: "9812345678".to_string(), ..request.clone() };
+        assert_eq!(
+            process_discount(&other_customer, &mut conn).map_err(|e| e.code),
+            Err(ErrorCode::FraudSuspected)
+        );
+        assert_eq!(transactions::load_transactions("test102", Utc::now().date_naive(), &mut conn).len(), 2);
+
+        let review = fraud::decide_review(
+            "test102",
+            &reviews[0].review_id,
+            fraud::ReviewStatus::Rejected,
+            true,
+            "owner",
+            &mut conn,
+        )
+        .unwrap();
+        assert_eq!(review.status, fraud::ReviewStatus::Rejected);
+        assert_eq!(
+            fraud::decide_review("test102", &review.review_id, fraud::ReviewStatus::Approved, false, "owner", &mut conn)
+                .map_err(|e| e.code),
+            Err(ErrorCode::Conflict)
+        );
+        assert_eq!(
+            process_discount(&DiscountRequest { cashier_id: None, ..request.clone() }, &mut conn).map_err(|e| e.code),
+            Err(ErrorCode::FraudSuspected)
+        );
+        assert!(fraud::unblock_customer("test102", "+919876543210", "owner", &mut conn));
+
+        // A share worth most of a small bill: 30 split with this week's customer.
+        setup_previous_week_data(&mut conn, "test102", "9123456789", 30.0, 1.0);
+        let outcome = process_discount(
+            &DiscountRequest { customer_id: "9123456789".to_string(), amount: 20.0, cashier_id: None, ..request },
+            &mut conn,
+        )
+        .unwrap();
+        assert_eq!(outcome.discount, 15.0);
+        let reviews = fraud::load_reviews("test102", &mut conn);
+        assert_eq!(reviews.len(), 2);
+        assert_eq!(reviews[1].reasons, vec!["pool share is 75% of the bill"]);
+    }
+
+    #[test]
+    fn test_rate_limits_are_counted_in_redis() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        let limits = ratelimit::RateLimits {
+            per_ip: None,
+            per_token: Some(ratelimit::RateLimit { requests: 2, window_secs: 60 }),
+            per_business: None,
+        };
+        let token_id = auth::token_id("some-token");
+        let subject = ratelimit::RateLimitSubject {
+            ip: Some("203.0.113.7"),
+            token_id: Some(&token_id),
+            business_name: Some("test102"),
+        };
+        limits.check("get_discount", &subject, &mut conn).unwrap();
+        limits.check("get_discount", &subject, &mut conn).unwrap();
+        let error = limits.check("get_discount", &subject, &mut conn).unwrap_err();
+        assert_eq!(error.code, ErrorCode::RateLimited);
+        assert!(error.retry_after_secs.is_some_and(|secs| (1..=120).contains(&secs)));
+        // Other endpoints and tokens have their own allowance.
+        limits.check("submit_feedback", &subject, &mut conn).unwrap();
+        let other_token = auth::token_id("other-token");
+        let other = ratelimit::RateLimitSubject { token_id: Some(&other_token), ..subject };
+        limits.check("get_discount", &other, &mut conn).unwrap();
+    }
+
+    #[test]
+    fn test_simulator_replays_stored_history_read_only() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        // Last week predates the transaction log; this week has one.
+        let current_monday = get_latest_monday(Utc::now().iso_week().week());
+        let last_monday = current_monday - Duration::days(7);
+        let mut details = CustomerDiscountDetails::default();
+        details.customer_expense_map.insert(
+            "+919876543210".to_string(),
+            [(last_monday.format("%d-%b-%Y").to_string(), "400,600".to_string())].into(),
+        );
+        persist_data_to_redis(
+            &period_redis_key("test102", last_monday),
+            serde_json::to_string(&details).unwrap(),
+            &mut conn,
+        );
+        let request = DiscountRequest {
+            business_name: "test102".to_string(),
+            customer_id: "9876543210".to_string(),
+            amount: 500.0,
+            ..DiscountRequest::default()
+        };
+        process_discount(&request, &mut conn).unwrap();
+
+        let keys_before = list_redis_keys("*", &mut conn).len();
+        let today = Utc::now().date_naive();
+        let bills = simulator::load_history("test102", last_monday, today, &mut conn);
+        assert_eq!(bills.iter().map(|bill| bill.amount).collect::<Vec<_>>(), vec![400.0, 600.0, 500.0]);
+
+        let policy = simulator::SimulationPolicy { pool_percentage: 0.1, ..Default::default() };
+        let report = simulator::simulate(&bills, last_monday, &policy);
+        assert_eq!(report.periods.len(), 2);
+        assert_eq!(report.customers_eligible, 1);
+        assert!((report.total_discount - 100.0).abs() < 1e-9);
+        let keys_after = list_redis_keys("*", &mut conn).len();
+        assert_eq!(keys_before, keys_after);
+    }
+
+    #[test]
+    fn test_eligibility_rules_gate_pool_shares() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        let current_monday = get_latest_monday(Utc::now().iso_week().week());
+        let last_monday = current_monday - Duration::days(7);
+        let mut details = CustomerDiscountDetails {
+            total_pooled_amount: 60.0,
+            total_eligible_customers: 2.0,
+            ..CustomerDiscountDetails::default()
+        };
+        let day = |offset: i64| (last_monday + Duration::days(offset)).format("%d-%b-%Y").to_string();
+        details
+            .customer_expense_map
+            .insert("+919876543210".to_string(), [(day(0), "500".to_string())].into());
+        details.customer_expense_map.insert(
+            "+919812345678".to_string(),
+            [(day(0), "200".to_string()), (day(2), "300".to_string())].into(),
+        );
+        persist_data_to_redis(
+            &period_redis_key("test102", last_monday),
+            serde_json::to_string(&details).unwrap(),
+            &mut conn,
+        );
+
+        let mut business_policy = policy::load_policy("test102", &mut conn);
+        business_policy.eligibility_rules = vec![rules::EligibilityRule {
+            name: "regulars".to_string(),
+            condition: "visits_last_week >= 2 and bill_amount >= 300".to_string(),
+        }];
+        business_policy.holidays = vec!["Christmas".to_string()];
+        assert!(policy::save_policy("test102", &business_policy, &mut conn).is_err());
+        business_policy.holidays = vec!["25-Dec-2026".to_string()];
+        policy::save_policy("test102", &business_policy, &mut conn).unwrap();
+
+        let trace = explain_eligibility("test102", "+919876543210", 400.0, None, None, &mut conn);
+        assert!(!trace.passed);
+        assert!(trace.rules[0].passed);
+        assert_eq!(trace.rules[1].steps, vec!["visits_last_week >= 2: 1 >= 2 is false"]);
+        assert_eq!(trace.context.spend_last_week, 500.0);
+        assert!(list_redis_keys("transactions:*", &mut conn).is_empty());
+
+        let request = DiscountRequest {
+            business_name: "test102".to_string(),
+            customer_id: "9876543210".to_string(),
+            amount: 400.0,
+            ..DiscountRequest::default()
+        };
+        let outcome = process_discount(&request, &mut conn).unwrap();
+        assert_eq!(outcome.discount, 0.0);
+        assert_eq!(outcome.failed_rules, vec!["regulars"]);
+
+        let regular = DiscountRequest { customer_id: "9812345678".to_string(), ..request };
+        let outcome = process_discount(&regular, &mut conn).unwrap();
+        assert_eq!(outcome.discount, 20.0);
+        assert!(outcome.failed_rules.is_empty());
+        // A second bill today gets no share from the strategy, whatever the rules say.
+        let outcome = process_discount(&regular, &mut conn).unwrap();
+        assert_eq!(outcome.failed_rules, vec![rules::STRATEGY_STEP]);
+        let trace = explain_eligibility("test102", "+919812345678", 400.0, None, None, &mut conn);
+        assert_eq!(trace.failed_rules(), vec![rules::STRATEGY_STEP]);
+
+        let records = transactions::load_transactions("test102", Utc::now().date_naive(), &mut conn);
+        assert_eq!(records[0].failed_rules, vec!["regulars"]);
+        assert!(records[1].failed_rules.is_empty());
+    }
+
+    #[test]
+    fn test_wasm_plugin_strategy_with_fallback() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        let current_monday = get_latest_monday(Utc::now().iso_week().week());
+        let last_monday = current_monday - Duration::days(7);
+        let day = |offset: i64| (last_monday + Duration::days(offset)).format("%d-%b-%Y").to_string();
+        let mut details = CustomerDiscountDetails {
+            total_pooled_amount: 60.0,
+            total_eligible_customers: 2.0,
+            ..CustomerDiscountDetails::default()
+        };
+        details
+            .customer_expense_map
+            .insert("+919876543210".to_string(), [(day(0), "500".to_string())].into());
+        details.customer_expense_map.insert(
+            "+919812345678".to_string(),
+            [(day(0), "200".to_string()), (day(2), "300".to_string())].into(),
+        );
+        details
+            .customer_expense_map
+            .insert("+919800000000".to_string(), [(day(1), "100".to_string())].into());
+        persist_data_to_redis(
+            &period_redis_key("test102", last_monday),
+            serde_json::to_string(&details).unwrap(),
+            &mut conn,
+        );
+        let mut business_policy = policy::load_policy("test102", &mut conn);
+        business_policy.strategy = plugins::PLUGIN_STRATEGY.to_string();
+        policy::save_policy("test102", &business_policy, &mut conn).unwrap();
+
+        // 10% of the bill for customers with two visits last week.
+        let loyal_tenth = wat::parse_str(
+            r#"(module
+                 (memory (export "memory") 1)
+                 (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+                 (func (export "discount_share") (param $input i32) (result f64)
+                   (if (result f64) (f64.ge (f64.load offset=8 (local.get $input)) (f64.const 2))
+                     (then (f64.mul (f64.load (local.get $input)) (f64.const 0.1)))
+                     (else (f64.const -1)))))"#,
+        )
+        .unwrap();
+        let info = plugins::upload_plugin("test102", &loyal_tenth, "owner", &mut conn).unwrap();
+        assert_eq!(plugins::load_plugin_info("test102", &mut conn), Some(info));
+        // Bills run the cached module without reading it back from the store.
+        let stored_module = fetch_data_from_redis("plugin:test102", &mut conn);
+        delete_data_from_redis("plugin:test102", &mut conn);
+
+        let request = DiscountRequest {
+            business_name: "test102".to_string(),
+            customer_id: "9812345678".to_string(),
+            amount: 400.0,
+            ..DiscountRequest::default()
+        };
+        assert_eq!(process_discount(&request, &mut conn).unwrap().discount, 40.0);
+        let once = DiscountRequest { customer_id: "9876543210".to_string(), ..request.clone() };
+        assert_eq!(process_discount(&once, &mut conn).unwrap().discount, 0.0);
+        persist_data_to_redis("plugin:test102", stored_module, &mut conn);
+
+        // A plugin that traps falls back to the equal split of the pool.
+        let traps = wat::parse_str(
+            r#"(module
+                 (memory (export "memory") 1)
+                 (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                 (func (export "discount_share") (param i32) (result f64) unreachable))"#,
+        )
+        .unwrap();
+        plugins::upload_plugin("test102", &traps, "owner", &mut conn).unwrap();
+        // Two customers billed this week so far share the pool with last week's two.
+        let fallback = DiscountRequest { customer_id: "9800000000".to_string(), ..request };
+        assert_eq!(process_discount(&fallback, &mut conn).unwrap().discount, 15.0);
+
+        plugins::delete_plugin("test102", "owner", &mut conn).unwrap();
+        assert!(plugins::delete_plugin("test102", "owner", &mut conn).is_err());
+        assert!(list_redis_keys("plugin*", &mut conn).is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_quote_matches_the_bill() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        let current_monday = get_latest_monday(Utc::now().iso_week().week());
+        let last_monday = current_monday - Duration::days(7);
+        let mut details = CustomerDiscountDetails {
+            total_pooled_amount: 90.0,
+            total_eligible_customers: 2.0,
+            ..CustomerDiscountDetails::default()
+        };
+        details.customer_expense_map.insert(
+            "+919876543210".to_string(),
+            [(last_monday.format("%d-%b-%Y").to_string(), "500".to_string())].into(),
+        );
+        persist_data_to_redis(
+            &period_redis_key("test102", last_monday),
+            serde_json::to_string(&details).unwrap(),
+            &mut conn,
+        );
+        let mut business_policy = policy::load_policy("test102", &mut conn);
+        business_policy.streak_bonuses = vec![policy::StreakBonus { min_weeks: 2, multiplier: 1.5 }];
+        policy::save_policy("test102", &business_policy, &mut conn).unwrap();
+
+        let snapshot = pool_snapshot("test102", "+919876543210", None, None, &mut conn);
+        assert_eq!(snapshot.eligible_customers, 2.0);
+        assert_eq!(snapshot.streak_multiplier, 1.5);
+        assert!(!snapshot.estimate);
+        let quote = theloyalgame_core::quote(&snapshot, 300.0);
+        assert!(list_redis_keys("transactions:*", &mut conn).is_empty());
+
+        let request = DiscountRequest {
+            business_name: "test102".to_string(),
+            customer_id: "9876543210".to_string(),
+            amount: 300.0,
+            ..DiscountRequest::default()
+        };
+        let outcome = process_discount(&request, &mut conn).unwrap();
+        assert_eq!(outcome.discount, quote.discount);
+        assert_eq!(outcome.final_amount, quote.final_amount);
+        assert_eq!(outcome.discount, 67.5);
+
+        let snapshot = pool_snapshot("test102", "+919876543210", None, None, &mut conn);
+        assert!(snapshot.billed_today);
+        assert_eq!(theloyalgame_core::quote(&snapshot, 300.0).discount, 0.0);
+    }
+
+    // Pays everyone a flat 10 on their first bill of the day, from nothing.
+    struct FlatWelcome;
+
+    impl strategy::DiscountStrategy for FlatWelcome {
+        fn is_eligible(&self, context: &strategy::DiscountContext) -> bool {
+            !context.billed_today
+        }
+
+        fn share_value(&self, _context: &strategy::DiscountContext) -> f64 {
+            10.0
+        }
+
+        fn pool_contribution(&self, _context: &strategy::DiscountContext, _final_amount: f64) -> f64 {
+            0.0
+        }
+    }
+
+    #[test]
+    fn test_business_picks_a_custom_strategy() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        let mut business_policy = policy::load_policy("test102", &mut conn);
+        business_policy.strategy = "flat_welcome".to_string();
+        assert!(policy::save_policy("test102", &business_policy, &mut conn).is_err());
+        strategy::register_strategy("flat_welcome", std::sync::Arc::new(FlatWelcome));
+        policy::save_policy("test102", &business_policy, &mut conn).unwrap();
+
+        let request = DiscountRequest {
+            business_name: "test102".to_string(),
+            customer_id: "9876543210".to_string(),
+            amount: 100.0,
+            ..DiscountRequest::default()
+        };
+        let outcome = process_discount(&request, &mut conn).unwrap();
+        assert_eq!(outcome.discount, 10.0);
+        assert_eq!(outcome.final_amount, 90.0);
+        let outcome = process_discount(&request, &mut conn).unwrap();
+        assert_eq!(outcome.discount, 0.0);
+
+        let current_monday = get_latest_monday(Utc::now().iso_week().week());
+        let details = parse_customer_discount_details(&fetch_data_from_redis(
+            &period_redis_key("test102", current_monday),
+            &mut conn,
+        ));
+        assert_eq!(details.total_pooled_amount, 0.0);
+
+        // Other businesses keep the equal split.
+        let other_business = DiscountRequest { business_name: "test103".to_string(), ..request };
+        assert_eq!(process_discount(&other_business, &mut conn).unwrap().discount, 0.0);
+    }
+
+    #[test]
+    fn test_customer_token_only_acts_for_its_customer() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        let token = generate_and_store_token("+919876543210", "test102", &mut conn);
+        let principal = auth::principal_from_token(&token, "test102", &mut conn).unwrap();
+        assert_eq!(principal.role, Role::Customer);
+        assert_eq!(principal.label, auth::token_id(&token));
+        assert!(auth::authorize_customer(&principal, "test102", "+919876543210", &mut conn).is_ok());
+        assert!(matches!(
+            auth::authorize_customer(&principal, "test102", "+919812345678", &mut conn),
+            Err(auth::AuthError::Forbidden(_))
+        ));
+
+        // Once the phone joins a card's profile, the token acts for that profile.
+        let card = CustomerId::parse("LC 1234 5678", "IN").unwrap();
+        let phone = CustomerId::parse("9876543210", "IN").unwrap();
+        customer::link_customer_id("test102", &card, &phone, &mut conn).unwrap();
+        assert!(auth::authorize_customer(&principal, "test102", "card:LC12345678", &mut conn).is_ok());
+
+        // Cashiers act for anyone.
+        let cashier = generate_and_store_token_with_role("+919812345678", "test102", Role::Cashier, &mut conn);
+        let cashier = auth::principal_from_token(&cashier, "test102", &mut conn).unwrap();
+        assert!(auth::authorize_customer(&cashier, "test102", "+919876543210", &mut conn).is_ok());
+    }
+
+    #[test]
+    fn test_merge_moves_referral_codes_and_this_business_feedback() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        let from = CustomerId::parse("a*b@example.com", "IN").unwrap();
+        let into = CustomerId::parse("asha@example.com", "IN").unwrap();
+        let feedback = |business_name: Option<&str>| {
+            let mut feedback = serde_json::json!({"customer_id": "email:a*b@example.com", "rating": 5});
+            if let Some(business_name) = business_name {
+                feedback["business_name"] = business_name.into();
+            }
+            feedback.to_string()
+        };
+        persist_data_to_redis("feedback:email:a*b@example.com:1", feedback(Some("test102")), &mut conn);
+        persist_data_to_redis("feedback:email:a*b@example.com:2", feedback(Some("test101")), &mut conn);
+        persist_data_to_redis("feedback:email:a*b@example.com:3", feedback(None), &mut conn);
+        // Would match the unescaped pattern.
+        persist_data_to_redis("feedback:email:axxb@example.com:4", feedback(Some("test102")), &mut conn);
+        persist_data_to_redis("referral_code:test102:CODE1234", "email:a*b@example.com".to_string(), &mut conn);
+        persist_data_to_redis("referral_code_of:test102:email:a*b@example.com", "CODE1234".to_string(), &mut conn);
+
+        let report = customer::merge_customers("test102", &from, &into, "support", &mut conn).unwrap();
+        assert_eq!(report.feedback_moved, 2);
+        assert_eq!(report.referral_codes_moved, 1);
+        let mut moved = list_redis_keys("feedback:email:asha@example.com:*", &mut conn);
+        moved.sort();
+        assert_eq!(moved, vec!["feedback:email:asha@example.com:1", "feedback:email:asha@example.com:3"]);
+        // Another business's feedback and the look-alike customer stay put.
+        assert!(!fetch_data_from_redis("feedback:email:a*b@example.com:2", &mut conn).is_empty());
+        assert!(!fetch_data_from_redis("feedback:email:axxb@example.com:4", &mut conn).is_empty());
+
+        assert_eq!(fetch_data_from_redis("referral_code:test102:CODE1234", &mut conn), "email:asha@example.com");
+        assert_eq!(
+            fetch_data_from_redis("referral_code_of:test102:email:asha@example.com", &mut conn),
+            "CODE1234"
+        );
+        assert_eq!(fetch_data_from_redis("referral_code_of:test102:email:a*b@example.com", &mut conn), "");
+    }
+
+    #[test]
+    fn test_multiplied_share_is_capped_at_the_bill() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        let mut business_policy = policy::load_policy("test102", &mut conn);
+        business_policy.streak_bonuses = vec![policy::StreakBonus { min_weeks: 3, multiplier: 1.5 }];
+        policy::save_policy("test102", &business_policy, &mut conn).unwrap();
+        setup_previous_week_data(&mut conn, "test102", "9876543210", 60.0, 2.0);
+        let current_monday = get_latest_monday(Utc::now().iso_week().week());
+        streaks::save_streak(
+            "test102",
+            "+919876543210",
+            &streaks::CustomerStreak {
+                last_period: streaks::period_label(current_monday - Duration::days(7)),
+                weeks: 2,
+            },
+            &mut conn,
+        );
+
+        // A 30 share times 1.5 is 45, more than the 40 bill.
+        let request = DiscountRequest {
+            business_name: "test102".to_string(),
+            customer_id: "9876543210".to_string(),
+            amount: 40.0,
+            ..DiscountRequest::default()
+        };
+        let outcome = process_discount(&request, &mut conn).unwrap();
+        assert_eq!(outcome.discount, 40.0);
+        assert_eq!(outcome.final_amount, 0.0);
+    }
+
+    #[test]
+    fn test_simulator_replay_matches_live_discounts() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        let pool_percentage = policy::load_policy("test102", &mut conn).pool_percentage;
+        let current_monday = get_latest_monday(Utc::now().iso_week().week());
+        let last_monday = current_monday - Duration::days(7);
+        let last_day = last_monday.format("%d-%b-%Y").to_string();
+        let mut details = CustomerDiscountDetails {
+            total_eligible_customers: 2.0,
+            total_pooled_amount: 1500.0 * pool_percentage,
+            ..CustomerDiscountDetails::default()
+        };
+        for (customer_id, amount) in [("+919876543210", "1000"), ("+919876543211", "500")] {
+            details
+                .customer_expense_map
+                .insert(customer_id.to_string(), [(last_day.clone(), amount.to_string())].into());
+        }
+        persist_data_to_redis(
+            &period_redis_key("test102", last_monday),
+            serde_json::to_string(&details).unwrap(),
+            &mut conn,
+        );
+
+        // A newcomer billed before a regular counts toward the regular's share.
+        let bills = [("9876543210", 200.0), ("9812345678", 100.0), ("9876543211", 100.0), ("9876543210", 50.0)];
+        for (customer_id, amount) in bills {
+            let request = DiscountRequest {
+                business_name: "test102".to_string(),
+                customer_id: customer_id.to_string(),
+                amount,
+                ..DiscountRequest::default()
+            };
+            process_discount(&request, &mut conn).unwrap();
+        }
+        let stored = parse_customer_discount_details(&fetch_data_from_redis(
+            &period_redis_key("test102", current_monday),
+            &mut conn,
+        ));
+
+        let bills = simulator::load_history("test102", last_monday, Utc::now().date_naive(), &mut conn);
+        let policy = simulator::SimulationPolicy { pool_percentage, ..Default::default() };
+        let report = simulator::simulate(&bills, last_monday, &policy);
+        assert!((stored.total_discount_given - 1500.0 * pool_percentage * (1.0 / 2.0 + 1.0 / 4.0)).abs() < 1e-9);
+        assert!((report.periods[1].discount - stored.total_discount_given).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_removed_business_is_refused() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        let token = generate_and_store_token("9876543210", "test102", &mut conn);
+        let owner = auth::ApiKey {
+            business_name: Some("test102".to_string()),
+            role: Role::Owner,
+            label: "owner".to_string(),
+        };
+        let api_key = auth::issue_api_key(&owner, &mut conn);
+        let request = DiscountRequest {
+            business_name: "test102".to_string(),
+            customer_id: "9876543210".to_string(),
+            amount: 100.0,
+            ..DiscountRequest::default()
+        };
+        assert!(validate_token(&token, "test102", &mut conn).is_ok());
+        assert!(auth::principal_from_api_key(&api_key, &mut conn).is_ok());
+
+        assert!(business::remove_business("test102", &mut conn));
+        let refused = validate_token(&token, "test102", &mut conn).unwrap_err();
+        assert_eq!(refused.code, ErrorCode::Forbidden);
+        assert!(matches!(
+            auth::principal_from_api_key(&api_key, &mut conn),
+            Err(auth::AuthError::Forbidden(_))
+        ));
+        assert_eq!(process_discount(&request, &mut conn).unwrap_err().code, ErrorCode::Forbidden);
+
+        // Registering it again brings its credentials back.
+        business::register_business("test102", &mut conn);
+        assert!(validate_token(&token, "test102", &mut conn).is_ok());
+    }
+
+    #[test]
+    fn test_merge_after_phone_number_change() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        // Both numbers shopped last week, so both were counted as eligible.
+        setup_previous_week_data(&mut conn, "test102", "9876543210", 30.0, 2.0);
+        let last_monday = get_latest_monday(Utc::now().iso_week().week()) - Duration::days(7);
+        let redis_key = period_redis_key("test102", last_monday);
+        let mut details = parse_customer_discount_details(&fetch_data_from_redis(&redis_key, &mut conn));
+        details.customer_expense_map.insert(
+            "+919812345678".to_string(),
+            [("11-Mar-2025".to_string(), "200.00".to_string())].into(),
+        );
+        persist_data_to_redis(&redis_key, serde_json::to_string(&details).unwrap(), &mut conn);
+        let token = generate_and_store_token("+919876543210", "test102", &mut conn);
+
+        let old_phone = CustomerId::parse("9876543210", "IN").unwrap();
+        let new_phone = CustomerId::parse("98123 45678", "IN").unwrap();
+        let report = customer::merge_customers("test102", &old_phone, &new_phone, "support", &mut conn).unwrap();
+        assert_eq!(report.periods_updated, 1);
+        assert_eq!(report.periods_combined, 1);
+        assert!(!report.block_moved);
+        assert_eq!(
+            report.profile.unwrap().identifiers,
+            vec!["+919812345678", "+919876543210"]
+        );
+
+        let details = parse_customer_discount_details(&fetch_data_from_redis(&redis_key, &mut conn));
+        assert_eq!(details.total_eligible_customers, 1.0);
+        assert_eq!(details.customer_expense_map.len(), 1);
+        assert_eq!(details.customer_expense_map["+919812345678"].len(), 2);
+        assert_eq!(customer::resolve_customer("test102", &old_phone, &mut conn), "+919812345678");
+        // Widget tokens aren't tied to one business, so the merge leaves them be.
+        assert_eq!(fetch_data_from_redis("phone:+919876543210:token", &mut conn), token);
+        assert_eq!(fetch_data_from_redis("phone:+919812345678:token", &mut conn), "");
+
+        // The whole pool now goes to the one remaining customer.
+        let request = DiscountRequest {
+            business_name: "test102".to_string(),
+            customer_id: "9876543210".to_string(),
+            amount: 100.0,
+            ..DiscountRequest::default()
+        };
+        assert_eq!(process_discount(&request, &mut conn).unwrap().discount, 30.0);
+
+        let entries = audit::load_audit("test102", &mut conn);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].actor, "support");
+        assert_eq!(entries[0].action, "customer_merge");
+        assert_eq!(entries[0].details["from"], "+919876543210");
+
+        assert_eq!(
+            customer::merge_customers("test102", &old_phone, &new_phone, "support", &mut conn).map_err(|e| e.code),
+            Err(ErrorCode::Conflict)
+        );
+    }
+
+    #[test]
+    fn test_merge_carries_fraud_block() {
+        let mut conn = REDIS_CONNECTION.lock().unwrap();
+        let _: () = redis::cmd("FLUSHALL").query(&mut conn).unwrap();
+        register_test_businesses(&mut conn);
+
+        setup_previous_week_data(&mut conn, "test102", "9876543210", 30.0, 1.0);
+        persist_data_to_redis("fraud_blocked:test102:+919876543210", "review-1".to_string(), &mut conn);
+        let old_phone = CustomerId::parse("9876543210", "IN").unwrap();
+        let new_phone = CustomerId::parse("9812345678", "IN").unwrap();
+        let report = customer::merge_customers("test102", &old_phone, &new_phone, "support", &mut conn).unwrap();
+
+        assert!(report.block_moved);
+        assert!(fraud::is_blocked("test102", "+919812345678", &mut conn));
+        assert!(!fraud::is_blocked("test102", "+919876543210", &mut conn));
+        assert_eq!(audit::load_audit("test102", &mut conn)[0].details["block_moved"], true);
+        let request = DiscountRequest {
+            business_name: "test102".to_string(),
+            customer_id: "9812345678".to_string(),
+            amount: 100.0,
+            ..DiscountRequest::default()
+        };
+        assert_eq!(process_discount(&request, &mut conn).unwrap_err().code, ErrorCode::FraudSuspected);
+    }
 }
@@ -1,15 +1,48 @@
 use actix_cors::Cors;
 use actix_web::{
+    body::EitherBody,
     dev::{Service, ServiceRequest, ServiceResponse, Transform},
-    web, App, Error, HttpResponse, HttpServer, Responder, Either, middleware::Logger,
+    http::header::{HeaderName, HeaderValue},
+    web, App, Error, HttpRequest, HttpResponse, HttpServer, Responder, Either, middleware::Logger,
 };
+use chatbot_rust_wasm::auth::{self, ApiKey, Principal, Role};
+use chatbot_rust_wasm::error::{ApiError, ErrorBody, ErrorCode};
+use chatbot_rust_wasm::otp::{self, FileMessageSender, LogMessageSender, MessageSender, NoMessageSender};
+use chatbot_rust_wasm::pseudonym::{self, Pseudonymizer};
+use chatbot_rust_wasm::ratelimit::{RateLimit, RateLimitSubject, RateLimits};
+use chatbot_rust_wasm::signed_token::{self, TokenSigner};
+use chatbot_rust_wasm::terminal::{self, SignedRequest};
+use chatbot_rust_wasm::policy::BusinessPolicy;
+use chatbot_rust_wasm::reports::BusinessReport;
+use chatbot_rust_wasm::transactions::TransactionRecord;
+use chatbot_rust_wasm::{DiscountOutcome, PoolSnapshot};
+use chatbot_rust_wasm::audit::{self, AuditEntry};
+use chatbot_rust_wasm::customer::{self, CustomerId, CustomerMergeReport, CustomerProfile};
+use chatbot_rust_wasm::migrations::PhoneMigrationReport;
+use chatbot_rust_wasm::campaigns::{self, Campaign};
+use chatbot_rust_wasm::coalitions::{self, Coalition, CoalitionSettlement};
+use chatbot_rust_wasm::fraud::{FraudReview, ReviewStatus};
+use chatbot_rust_wasm::items::LineItem;
+use chatbot_rust_wasm::outlets::{self, Outlet};
+use chatbot_rust_wasm::plugins::{self, PluginInfo};
+use chatbot_rust_wasm::rules::RuleTrace;
+use chatbot_rust_wasm::wallet::{self, Wallet, WalletLot};
+use chatbot_rust_wasm::{business, fraud, migrations, phone, policy, referrals, reports, transactions};
 use actix_multipart::Multipart;
+use chrono::Datelike;
+use futures_util::future::LocalBoxFuture;
 use futures_util::stream::StreamExt as _;
 use redis::Commands;
 use serde::{Deserialize, Serialize};
 use std::future::{ready, Ready};
+use std::sync::Arc;
+use utoipa::openapi::security::{ApiKey as ApiKeyScheme, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{IntoParams, Modify, OpenApi, ToSchema};
+use utoipa_scalar::{Scalar, Servable};
+use uuid::Uuid;
 
-// Custom middleware to log incoming requests
+// Custom middleware to log incoming requests. It also tags every request with
+// an id (the caller's X-Request-Id, or a fresh one) and renders errors with it.
 pub struct RequestLogger;
 
 impl<S: 'static, B> Transform<S, ServiceRequest> for RequestLogger
@@ -18,7 +51,7 @@ where
     S::Future: 'static,
     B: 'static,
 {
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<EitherBody<B>>;
     type Error = Error;
     type InitError = ();
     type Transform = RequestLoggerMiddleware<S>;
@@ -39,153 +72,1636 @@ where
     S::Future: 'static,
     B: 'static,
 {
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<EitherBody<B>>;
     type Error = Error;
-    type Future = S::Future;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
 
     fn poll_ready(&self, cx: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
         self.service.poll_ready(cx)
     }
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
+        let request_id = req
+            .headers()
+            .get("X-Request-Id")
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.is_empty() && value.len() <= 64)
+            .map(str::to_string)
+            .unwrap_or_else(|| Uuid::new_v4().simple().to_string());
         println!(
-            "Incoming request: {} {} Content-Type: {:?}",
+            "Incoming request: {} {} Content-Type: {:?} Request-Id: {}",
             req.method(),
-            req.path(),
-            req.headers().get("Content-Type")
+            redacted_path(req.path()),
+            req.headers().get("Content-Type"),
+            request_id
         );
-        self.service.call(req)
+        let plain_text = wants_plain_text(req.request());
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let res = fut.await?;
+            let mut res = match res.response().error() {
+                Some(error) => {
+                    let error = ApiError::from_actix(error);
+                    println!("Request {} failed: {}", request_id, error);
+                    let (req, _) = res.into_parts();
+                    ServiceResponse::new(req, error.render(&request_id, plain_text)).map_into_right_body()
+                }
+                None => res.map_into_left_body(),
+            };
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                res.headers_mut().insert(HeaderName::from_static("x-request-id"), value);
+            }
+            Ok(res)
+        })
+    }
+}
+
+// Errors are JSON unless the client asks for text/plain and not for JSON.
+fn wants_plain_text(req: &HttpRequest) -> bool {
+    let accept = header_str(req, "Accept").unwrap_or_default();
+    accept.contains("text/plain") && !accept.contains("application/json")
+}
+
+// The legacy discount route carries the phone, amount and token in its path,
+// and customer lookups carry the identifier; keep them out of the logs.
+fn redacted_path(path: &str) -> String {
+    if let Some(rest) = path.strip_prefix("/get_discount/") {
+        let business_name = rest.split('/').next().unwrap_or_default();
+        return format!("/get_discount/{}/phone_number_amount/***/token/***", business_name);
+    }
+    if let Some(rest) = path.strip_prefix("/v1/customers/") {
+        if let Some((business_name, customer_id)) = rest.split_once('/') {
+            if customer_id != "links" {
+                return format!("/v1/customers/{}/***", business_name);
+            }
+        }
     }
+    path.to_string()
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
 struct Feedback {
-    phone_number: String,
+    business_name: String,
+    /// Phone number, loyalty card number or email. `phone_number` is accepted as well.
+    #[serde(alias = "phone_number")]
+    customer_id: String,
+    #[schema(minimum = 1, maximum = 5)]
     rating: u8, // 1 to 5 stars
     comment: String,
     photo: Option<String>, // Add photo field as an optional base64 string
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 struct TokenQuery {
+    business_name: String,
+    phone: String,
+    otp: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct OtpRequest {
+    business_name: String,
     phone: String,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 struct TokenResponse {
     token: String,
 }
 
+#[derive(Deserialize, ToSchema)]
+struct NewBusiness {
+    business_name: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct NewCoalition {
+    name: String,
+    /// Registered businesses; at least two, none already in a coalition.
+    members: Vec<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct NewApiKey {
+    business_name: Option<String>,
+    role: Role,
+    label: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct RevokeTokenRequest {
+    token: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct NewTerminal {
+    label: String,
+    /// Outlet the terminal is installed at.
+    outlet_id: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct NewOutlet {
+    name: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct TerminalResponse {
+    terminal_id: String,
+    secret: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct DiscountBody {
+    // Required with a bearer token; terminals bill for the business they were registered to.
+    business_name: Option<String>,
+    /// Phone number, loyalty card number or email. `phone_number` is accepted as well.
+    #[serde(alias = "phone_number")]
+    customer_id: String,
+    /// Bill total. May be left out of an itemized bill.
+    amount: Option<f64>,
+    /// Line items with categories; their pool rates come from the business policy.
+    #[serde(default)]
+    items: Vec<LineItem>,
+    /// Outlet the bill was made at. Terminals bill for the outlet they are installed at.
+    outlet_id: Option<String>,
+    /// Referral code, accepted on a customer's first bill only.
+    referral_code: Option<String>,
+    /// Stable id of the customer's device. Referral limits also count the
+    /// client address or terminal, whatever this says.
+    device_id: Option<String>,
+    /// Amount of the customer's wallet balance to take off this bill.
+    redeem_from_wallet: Option<f64>,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct NewReferralCode {
+    /// The referring customer's phone number, loyalty card number or email.
+    customer_id: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ReferralCodeResponse {
+    code: String,
+}
+
+/// Names a customer in the body, so the identifier stays out of URLs and logs.
+#[derive(Deserialize, ToSchema)]
+struct CustomerLookup {
+    /// Phone number, loyalty card number or email.
+    customer_id: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct WalletSettings {
+    /// Phone number, loyalty card number or email.
+    customer_id: String,
+    /// Bank pool shares instead of taking them off each bill.
+    enabled: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+struct WalletResponse {
+    enabled: bool,
+    balance: f64,
+    lots: Vec<WalletLot>,
+}
+
+impl From<Wallet> for WalletResponse {
+    fn from(wallet: Wallet) -> WalletResponse {
+        WalletResponse {
+            enabled: wallet.enabled,
+            balance: wallet.balance(),
+            lots: wallet.lots,
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+struct NewCustomerLink {
+    /// An identifier the customer already uses.
+    customer_id: String,
+    /// The identifier to add to their profile.
+    link_id: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct CustomerMerge {
+    /// The identifier whose history moves; for a number change, the old number.
+    from: String,
+    /// The identifier that keeps the merged history.
+    into: String,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct TransactionsQuery {
+    date: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ReviewsQuery {
+    /// Only reviews in this state, e.g. `pending`.
+    status: Option<ReviewStatus>,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct ReviewDecision {
+    /// `approved` or `rejected`.
+    status: ReviewStatus,
+    /// Refuse the customer's future bills. Only with `rejected`.
+    #[serde(default)]
+    block_customer: bool,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct ExplainRequest {
+    /// Phone number, loyalty card number or email.
+    customer_id: String,
+    /// Bill amount to judge.
+    amount: f64,
+    outlet_id: Option<String>,
+    /// Terminal the bill would be entered at, for terminal campaigns.
+    terminal_id: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct SnapshotRequest {
+    /// Phone number, loyalty card number or email.
+    customer_id: String,
+    outlet_id: Option<String>,
+    /// Terminal the bill would be entered at, for terminal campaigns.
+    terminal_id: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SettlementQuery {
+    /// Any day of the week to settle, like `19-Oct-2026`. Defaults to this week.
+    week: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ApiKeyResponse {
+    api_key: String,
+}
+
+fn redis_connection(redis_conn: &redis::Client) -> Result<redis::Connection, ApiError> {
+    Ok(redis_conn.get_connection()?)
+}
+
+// Resolves the `X-Api-Key` header and checks it against the role the route needs.
+fn require_api_key(
+    req: &HttpRequest,
+    business_name: Option<&str>,
+    required: Role,
+    conn: &mut redis::Connection,
+) -> Result<Principal, ApiError> {
+    let key = header_str(req, "X-Api-Key")
+        .ok_or_else(|| ApiError::new(ErrorCode::Unauthenticated, "Missing X-Api-Key header"))?;
+    let principal = auth::principal_from_api_key(key, conn)?;
+    auth::authorize(&principal, business_name, required)?;
+    Ok(principal)
+}
+
+// Reads a phone number the way `business_name`'s customers dial it and returns it in E.164.
+fn normalize_phone(
+    phone_number: &str,
+    business_name: &str,
+    conn: &mut redis::Connection,
+) -> Result<String, ApiError> {
+    let region = policy::load_policy(business_name, conn).default_region;
+    phone::normalize_phone_number(phone_number, &region)
+        .map_err(|message| ApiError::new(ErrorCode::InvalidPhoneNumber, message))
+}
+
+// Resolves a phone, card or email to the loyalty profile it belongs to at `business_name`.
+fn resolve_customer(
+    customer_id: &str,
+    business_name: &str,
+    conn: &mut redis::Connection,
+) -> Result<String, ApiError> {
+    let region = policy::load_policy(business_name, conn).default_region;
+    let customer_id = CustomerId::parse(customer_id, &region)?;
+    Ok(customer::resolve_customer(business_name, &customer_id, conn))
+}
+
+// `resolve_customer`, refused unless `principal` may act for that customer:
+// customer tokens only act for the phone they were issued to.
+fn resolve_customer_for(
+    principal: &Principal,
+    customer_id: &str,
+    business_name: &str,
+    conn: &mut redis::Connection,
+) -> Result<String, ApiError> {
+    let customer_key = resolve_customer(customer_id, business_name, conn)?;
+    auth::authorize_customer(principal, business_name, &customer_key, conn)?;
+    Ok(customer_key)
+}
+
+// Accepts either a bearer token (the chat widget, at least the customer role)
+// or a cashier API key for `business_name`. Routes about one customer then
+// check the caller with `resolve_customer_for`.
+fn require_cashier(
+    req: &HttpRequest,
+    business_name: &str,
+    conn: &mut redis::Connection,
+) -> Result<Principal, ApiError> {
+    let principal = match bearer_token(req) {
+        Some(token) => auth::principal_from_token(token, business_name, conn)?,
+        None => require_api_key(req, Some(business_name), Role::Cashier, conn)?,
+    };
+    auth::authorize(&principal, Some(business_name), Role::Customer)?;
+    Ok(principal)
+}
+
+// Cashiers and terminals bill; customer tokens only where the business lets
+// the widget bill itself.
+fn billing_role(business_name: &str, conn: &mut redis::Connection) -> Role {
+    if policy::load_policy(business_name, conn).customer_self_billing {
+        Role::Customer
+    } else {
+        Role::Cashier
+    }
+}
+
+fn deprecated(mut builder: actix_web::HttpResponseBuilder) -> actix_web::HttpResponseBuilder {
+    builder
+        .insert_header(("Deprecation", "true"))
+        .insert_header(("Link", "</v1/discounts>; rel=\"successor-version\""));
+    builder
+}
+
+#[utoipa::path(
+    get,
+    path = "/get_discount/{business_name}/phone_number_amount/{phone_number_amount}/token/{token}",
+    tag = "discounts",
+    params(
+        ("business_name" = String, Path, description = "Business to bill"),
+        ("phone_number_amount" = String, Path, description = "Phone and amount as 'phone,amount'"),
+        ("token" = String, Path, description = "Token from /generate_token"),
+    ),
+    responses(
+        (status = 200, description = "Plain-text outcome", body = String, content_type = "text/plain"),
+        (status = 400, body = ErrorBody),
+        (status = 401, description = "Token unknown or expired", body = ErrorBody),
+        (status = 403, description = "Token may not bill for this business", body = ErrorBody),
+        (status = 429, description = "Rate limited; see Retry-After", body = ErrorBody),
+    )
+)]
+/// Deprecated: use `POST /v1/discounts`. Kept so existing widgets keep working.
 async fn get_discount(
+    req: HttpRequest,
     path: web::Path<(String, String, String)>,
     redis_conn: web::Data<redis::Client>,
-) -> impl Responder {
+    rate_limiting: web::Data<RateLimiting>,
+) -> Result<HttpResponse, ApiError> {
     let (business_name, phone_number_amount, token) = path.into_inner();
-    let mut conn = redis_conn
-        .get_connection()
-        .expect("Failed to get Redis connection");
-    let response = chatbot_rust_wasm::get_response(token, business_name, phone_number_amount, &mut conn);
-    HttpResponse::Ok().body(response)
+    let mut conn = redis_connection(&redis_conn)?;
+    rate_limiting.check_client(&req, "get_discount", &mut conn)?;
+    let principal = auth::principal_from_token(&token, &business_name, &mut conn)?;
+    let required = billing_role(&business_name, &mut conn);
+    auth::authorize(&principal, Some(&business_name), required)?;
+    rate_limiting.check_caller("get_discount", Some(&token), &business_name, &mut conn)?;
+    let request = chatbot_rust_wasm::parse_phone_number_amount(business_name, &phone_number_amount)?;
+    resolve_customer_for(&principal, &request.customer_id, &request.business_name, &mut conn)?;
+    let outcome = chatbot_rust_wasm::process_discount(&request, &mut conn)?;
+    Ok(deprecated(HttpResponse::Ok()).body(outcome.to_legacy_string()))
+}
+
+// Rate limits on the public endpoints. Client addresses come from the
+// connection unless the server sits behind a proxy that sets Forwarded or
+// X-Forwarded-For; clients could spoof those headers otherwise.
+struct RateLimiting {
+    limits: RateLimits,
+    trust_proxy_headers: bool,
+}
+
+impl RateLimiting {
+    // The client's address: the forwarded one behind a trusted proxy, the
+    // connection's otherwise.
+    fn client_ip(&self, req: &HttpRequest) -> Option<String> {
+        if self.trust_proxy_headers {
+            req.connection_info().realip_remote_addr().map(str::to_string)
+        } else {
+            req.peer_addr().map(|addr| addr.ip().to_string())
+        }
+    }
+
+    // Counted before authentication, so floods are refused cheaply.
+    fn check_client(&self, req: &HttpRequest, endpoint: &str, conn: &mut redis::Connection) -> Result<(), ApiError> {
+        let ip = self.client_ip(req);
+        let subject = RateLimitSubject {
+            ip: ip.as_deref(),
+            ..RateLimitSubject::default()
+        };
+        self.limits.check(endpoint, &subject, conn)
+    }
+
+    // Counted once the caller is authenticated, so nobody can use up another
+    // business's or token's allowance.
+    fn check_caller(
+        &self,
+        endpoint: &str,
+        token: Option<&str>,
+        business_name: &str,
+        conn: &mut redis::Connection,
+    ) -> Result<(), ApiError> {
+        let token_id = token.map(auth::token_id);
+        let subject = RateLimitSubject {
+            token_id: token_id.as_deref(),
+            business_name: Some(business_name),
+            ..RateLimitSubject::default()
+        };
+        self.limits.check(endpoint, &subject, conn)
+    }
+}
+
+fn header_str<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    req.headers().get(name).and_then(|value| value.to_str().ok())
+}
+
+// Verifies the X-Terminal-Id / X-Timestamp / X-Signature headers over the raw request.
+fn require_terminal(
+    req: &HttpRequest,
+    body: &[u8],
+    conn: &mut redis::Connection,
+) -> Result<terminal::Terminal, ApiError> {
+    let (Some(terminal_id), Some(timestamp), Some(signature)) = (
+        header_str(req, "X-Terminal-Id"),
+        header_str(req, "X-Timestamp"),
+        header_str(req, "X-Signature"),
+    ) else {
+        return Err(ApiError::new(
+            ErrorCode::Unauthenticated,
+            "Missing X-Terminal-Id, X-Timestamp or X-Signature header",
+        ));
+    };
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or_else(|| req.path());
+    let signed = SignedRequest {
+        terminal_id,
+        timestamp,
+        signature,
+        method: req.method().as_str(),
+        path,
+        body,
+    };
+    Ok(terminal::verify_signed_request(&signed, chrono::Utc::now().timestamp(), conn)?)
+}
+
+fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    header_str(req, "Authorization")?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/discounts",
+    tag = "discounts",
+    request_body = DiscountBody,
+    responses(
+        (status = 200, body = DiscountOutcome),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 429, description = "Rate limited; see Retry-After", body = ErrorBody),
+    ),
+    security(("bearer_token" = []), ("terminal_signature" = []))
+)]
+// POST /v1/discounts. Authenticated either by a bearer token for the business
+// in the body or by a terminal signature (also served at /terminal/discounts).
+async fn create_discount(
+    req: HttpRequest,
+    body: web::Bytes,
+    redis_conn: web::Data<redis::Client>,
+    rate_limiting: web::Data<RateLimiting>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = redis_connection(&redis_conn)?;
+    let bill: Result<DiscountBody, _> = serde_json::from_slice(&body);
+    rate_limiting.check_client(&req, "discounts", &mut conn)?;
+
+    let (principal, business_name, terminal_id, terminal_outlet, cashier_id) = if header_str(&req, "X-Terminal-Id").is_some() {
+        let terminal = require_terminal(&req, &body, &mut conn)?;
+        (
+            terminal.principal(),
+            terminal.business_name.clone(),
+            Some(terminal.terminal_id.clone()),
+            terminal.outlet_id,
+            format!("terminal:{}", terminal.terminal_id),
+        )
+    } else {
+        let token = bearer_token(&req).ok_or_else(|| {
+            ApiError::new(ErrorCode::Unauthenticated, "Missing Authorization: Bearer <token> header")
+        })?;
+        let business_name = bill
+            .as_ref()
+            .ok()
+            .and_then(|bill| bill.business_name.clone())
+            .ok_or_else(|| ApiError::invalid_request("business_name is required"))?;
+        let principal = auth::principal_from_token(token, &business_name, &mut conn)?;
+        (principal, business_name, None, None, auth::token_id(token))
+    };
+
+    let bill = bill.map_err(|e| ApiError::invalid_request(format!("Invalid JSON body: {}", e)))?;
+    if bill.business_name.as_deref().is_some_and(|name| name != business_name) {
+        return Err(ApiError::new(
+            ErrorCode::Forbidden,
+            "Terminal is not registered to this business",
+        ));
+    }
+    let required = billing_role(&business_name, &mut conn);
+    auth::authorize(&principal, Some(&business_name), required)?;
+    // Terminals are counted by their id, widgets by their bearer token.
+    let caller = terminal_id.as_deref().or_else(|| bearer_token(&req));
+    rate_limiting.check_caller("discounts", caller, &business_name, &mut conn)?;
+    if terminal_outlet.is_some() && bill.outlet_id.is_some() && terminal_outlet != bill.outlet_id {
+        return Err(ApiError::new(ErrorCode::Forbidden, "Terminal is installed at another outlet"));
+    }
+
+    // Terminals bill many customers, so they are counted by their id instead.
+    let client_address = terminal_id
+        .is_none()
+        .then(|| rate_limiting.client_ip(&req))
+        .flatten();
+    resolve_customer_for(&principal, &bill.customer_id, &business_name, &mut conn)?;
+    let request = chatbot_rust_wasm::DiscountRequest {
+        business_name,
+        customer_id: bill.customer_id,
+        amount: bill
+            .amount
+            .or_else(|| (!bill.items.is_empty()).then(|| bill.items.iter().map(|item| item.amount).sum()))
+            .ok_or_else(|| ApiError::invalid_request("amount or items is required"))?,
+        terminal_id,
+        cashier_id: Some(cashier_id),
+        outlet_id: terminal_outlet.or(bill.outlet_id),
+        referral_code: bill.referral_code,
+        device_id: bill.device_id,
+        client_address,
+        redeem_from_wallet: bill.redeem_from_wallet,
+        items: bill.items,
+    };
+    let outcome = chatbot_rust_wasm::process_discount(&request, &mut conn)?;
+    Ok(HttpResponse::Ok().json(outcome))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/customers/{business_name}/links",
+    tag = "customers",
+    params(("business_name" = String, Path)),
+    request_body = NewCustomerLink,
+    responses(
+        (status = 200, body = CustomerProfile),
+        (status = 400, body = ErrorBody),
+        (status = 409, description = "The new identifier already has its own history", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+async fn link_customer(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<NewCustomerLink>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let business_name = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    require_api_key(&req, Some(&business_name), Role::Cashier, &mut conn)?;
+    let region = policy::load_policy(&business_name, &mut conn).default_region;
+    let existing_id = CustomerId::parse(&body.customer_id, &region)?;
+    let new_id = CustomerId::parse(&body.link_id, &region)?;
+    let profile = customer::link_customer_id(&business_name, &existing_id, &new_id, &mut conn)?;
+    Ok(HttpResponse::Ok().json(profile))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/referrals/{business_name}",
+    tag = "customers",
+    params(("business_name" = String, Path)),
+    request_body = NewReferralCode,
+    responses(
+        (status = 200, description = "The customer's code; the same one on every call", body = ReferralCodeResponse),
+        (status = 403, description = "Referrals are off, the customer was never billed, or the token was issued to another customer", body = ErrorBody),
+    ),
+    security(("bearer_token" = []), ("api_key" = []))
+)]
+async fn create_referral_code(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<NewReferralCode>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let business_name = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    let principal = require_cashier(&req, &business_name, &mut conn)?;
+    if policy::load_policy(&business_name, &mut conn).referral_bonus.is_none() {
+        return Err(ApiError::new(ErrorCode::Forbidden, "Referrals are not enabled for this business"));
+    }
+    let customer_key = resolve_customer_for(&principal, &body.customer_id, &business_name, &mut conn)?;
+    let code = referrals::referral_code(&business_name, &customer_key, &mut conn)?;
+    Ok(HttpResponse::Ok().json(ReferralCodeResponse { code }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/wallets/{business_name}/lookup",
+    tag = "customers",
+    params(("business_name" = String, Path)),
+    request_body = CustomerLookup,
+    responses(
+        (status = 200, body = WalletResponse),
+        (status = 400, body = ErrorBody),
+        (status = 403, description = "The token was issued to another customer", body = ErrorBody),
+    ),
+    security(("bearer_token" = []), ("api_key" = []))
+)]
+// Reads a wallet. A POST so the customer's identifier isn't in the URL.
+async fn get_wallet(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<CustomerLookup>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let business_name = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    let principal = require_cashier(&req, &business_name, &mut conn)?;
+    let customer_key = resolve_customer_for(&principal, &body.customer_id, &business_name, &mut conn)?;
+    let mut customer_wallet = wallet::load_wallet(&business_name, &customer_key, &mut conn);
+    customer_wallet.expire(chrono::Utc::now().date_naive());
+    Ok(HttpResponse::Ok().json(WalletResponse::from(customer_wallet)))
+}
+
+#[utoipa::path(
+    put,
+    path = "/v1/wallets/{business_name}",
+    tag = "customers",
+    params(("business_name" = String, Path)),
+    request_body = WalletSettings,
+    responses(
+        (status = 200, body = WalletResponse),
+        (status = 403, description = "The business doesn't offer wallets, or the token was issued to another customer", body = ErrorBody),
+        (status = 409, description = "The wallet still has a balance", body = ErrorBody),
+    ),
+    security(("bearer_token" = []), ("api_key" = []))
+)]
+// Opts a customer in or out of banking their pool shares.
+async fn update_wallet(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<WalletSettings>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let business_name = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    let principal = require_cashier(&req, &business_name, &mut conn)?;
+    if body.enabled && !policy::load_policy(&business_name, &mut conn).wallet.enabled {
+        return Err(ApiError::new(ErrorCode::Forbidden, "Wallets are not enabled for this business"));
+    }
+    let customer_key = resolve_customer_for(&principal, &body.customer_id, &business_name, &mut conn)?;
+    let mut customer_wallet = wallet::load_wallet(&business_name, &customer_key, &mut conn);
+    customer_wallet.expire(chrono::Utc::now().date_naive());
+    if !body.enabled && customer_wallet.balance() > 0.0 {
+        return Err(ApiError::new(
+            ErrorCode::Conflict,
+            "Redeem the remaining wallet balance before turning the wallet off",
+        ));
+    }
+    customer_wallet.enabled = body.enabled;
+    wallet::save_wallet(&business_name, &customer_key, &customer_wallet, &mut conn);
+    Ok(HttpResponse::Ok().json(WalletResponse::from(customer_wallet)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/customers/{business_name}/{customer_id}",
+    tag = "customers",
+    params(
+        ("business_name" = String, Path),
+        ("customer_id" = String, Path, description = "Phone number, loyalty card number or email"),
+    ),
+    responses((status = 200, body = CustomerProfile), (status = 400, body = ErrorBody)),
+    security(("api_key" = []))
+)]
+async fn get_customer(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let (business_name, customer_id) = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    require_api_key(&req, Some(&business_name), Role::Cashier, &mut conn)?;
+    let profile_id = resolve_customer(&customer_id, &business_name, &mut conn)?;
+    Ok(HttpResponse::Ok().json(customer::load_profile(&business_name, &profile_id, &mut conn)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/pool_snapshot/{business_name}",
+    tag = "customers",
+    params(("business_name" = String, Path)),
+    request_body = SnapshotRequest,
+    responses(
+        (status = 200, body = PoolSnapshot),
+        (status = 400, body = ErrorBody),
+        (status = 403, description = "The token was issued to another customer", body = ErrorBody),
+    ),
+    security(("bearer_token" = []), ("api_key" = []))
+)]
+// Reads only. A POST so the customer's identifier isn't in the URL.
+async fn get_pool_snapshot(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<SnapshotRequest>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let business_name = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    let principal = require_cashier(&req, &business_name, &mut conn)?;
+    if let Some(outlet_id) = &body.outlet_id {
+        if !outlets::outlet_exists(&business_name, outlet_id, &mut conn) {
+            return Err(ApiError::invalid_request(format!("Unknown outlet: {}", outlet_id)));
+        }
+    }
+    let customer_key = resolve_customer_for(&principal, &body.customer_id, &business_name, &mut conn)?;
+    Ok(HttpResponse::Ok().json(chatbot_rust_wasm::pool_snapshot(
+        &business_name,
+        &customer_key,
+        body.outlet_id.as_deref(),
+        body.terminal_id.as_deref(),
+        &mut conn,
+    )))
+}
+
+#[utoipa::path(
+    post,
+    path = "/terminals/{business_name}",
+    tag = "owner",
+    params(("business_name" = String, Path)),
+    request_body = NewTerminal,
+    responses((status = 201, description = "The secret is only shown once", body = TerminalResponse)),
+    security(("api_key" = []))
+)]
+async fn create_terminal(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<NewTerminal>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let business_name = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    require_api_key(&req, Some(&business_name), Role::Owner, &mut conn)?;
+    if let Some(outlet_id) = &body.outlet_id {
+        if !outlets::outlet_exists(&business_name, outlet_id, &mut conn) {
+            return Err(ApiError::invalid_request(format!("Unknown outlet: {}", outlet_id)));
+        }
+    }
+    let terminal = terminal::register_terminal(&business_name, &body.label, body.outlet_id.as_deref(), &mut conn);
+    Ok(HttpResponse::Created().json(TerminalResponse {
+        terminal_id: terminal.terminal_id,
+        secret: terminal.secret,
+    }))
 }
 
+#[utoipa::path(
+    delete,
+    path = "/terminals/{business_name}/{terminal_id}",
+    tag = "owner",
+    params(("business_name" = String, Path), ("terminal_id" = String, Path)),
+    responses((status = 204), (status = 404, body = ErrorBody)),
+    security(("api_key" = []))
+)]
+// Retires a terminal, e.g. a lost or replaced till.
+async fn delete_terminal(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let (business_name, terminal_id) = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    let principal = require_api_key(&req, Some(&business_name), Role::Owner, &mut conn)?;
+    terminal::delete_terminal(&business_name, &terminal_id, &principal.label, &mut conn)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    get,
+    path = "/transactions/{business_name}",
+    tag = "owner",
+    params(("business_name" = String, Path), TransactionsQuery),
+    responses((status = 200, body = Vec<TransactionRecord>), (status = 400, body = ErrorBody)),
+    security(("api_key" = []))
+)]
+async fn get_transactions(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<TransactionsQuery>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let business_name = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    require_api_key(&req, Some(&business_name), Role::Owner, &mut conn)?;
+    let date = match query.date.as_deref() {
+        Some(date) => chrono::NaiveDate::parse_from_str(date, "%d-%b-%Y")
+            .map_err(|_| ApiError::invalid_request("date must look like 19-Oct-2026"))?,
+        None => chrono::Utc::now().date_naive(),
+    };
+    Ok(HttpResponse::Ok().json(transactions::load_transactions(&business_name, date, &mut conn)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/revoke_token/{business_name}",
+    tag = "owner",
+    params(("business_name" = String, Path)),
+    request_body = RevokeTokenRequest,
+    responses((status = 204), (status = 400, body = ErrorBody)),
+    security(("api_key" = []))
+)]
+async fn revoke_token(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<RevokeTokenRequest>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let business_name = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    require_api_key(&req, Some(&business_name), Role::Owner, &mut conn)?;
+    chatbot_rust_wasm::revoke_token(&body.token, &business_name, &mut conn)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    get,
+    path = "/reports/{business_name}",
+    tag = "owner",
+    params(("business_name" = String, Path)),
+    responses((status = 200, body = BusinessReport)),
+    security(("api_key" = []))
+)]
+async fn get_report(
+    req: HttpRequest,
+    path: web::Path<String>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let business_name = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    require_api_key(&req, Some(&business_name), Role::Owner, &mut conn)?;
+    Ok(HttpResponse::Ok().json(reports::business_report(&business_name, &mut conn)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/policy/{business_name}",
+    tag = "owner",
+    params(("business_name" = String, Path)),
+    responses((status = 200, body = BusinessPolicy)),
+    security(("api_key" = []))
+)]
+async fn get_policy(
+    req: HttpRequest,
+    path: web::Path<String>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let business_name = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    require_api_key(&req, Some(&business_name), Role::Owner, &mut conn)?;
+    Ok(HttpResponse::Ok().json(policy::load_policy(&business_name, &mut conn)))
+}
+
+#[utoipa::path(
+    put,
+    path = "/policy/{business_name}",
+    tag = "owner",
+    params(("business_name" = String, Path)),
+    request_body = BusinessPolicy,
+    responses((status = 200, body = BusinessPolicy), (status = 400, body = ErrorBody)),
+    security(("api_key" = []))
+)]
+async fn update_policy(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<policy::BusinessPolicy>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let business_name = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    let principal = require_api_key(&req, Some(&business_name), Role::Owner, &mut conn)?;
+    let new_policy = body.into_inner();
+    policy::save_policy(&business_name, &new_policy, &mut conn).map_err(ApiError::invalid_request)?;
+    println!(
+        "Policy updated - Business: {}, By: {}, Policy: {:?}",
+        business_name, principal.label, new_policy
+    );
+    Ok(HttpResponse::Ok().json(new_policy))
+}
+
+#[utoipa::path(
+    post,
+    path = "/rules/{business_name}/explain",
+    tag = "owner",
+    params(("business_name" = String, Path)),
+    request_body = ExplainRequest,
+    responses((status = 200, body = RuleTrace), (status = 400, body = ErrorBody)),
+    security(("api_key" = []))
+)]
+async fn explain_eligibility(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<ExplainRequest>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let business_name = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    require_api_key(&req, Some(&business_name), Role::Owner, &mut conn)?;
+    if !(body.amount.is_finite() && body.amount >= 0.0) {
+        return Err(ApiError::invalid_request("amount must not be negative"));
+    }
+    let customer_key = resolve_customer(&body.customer_id, &business_name, &mut conn)?;
+    Ok(HttpResponse::Ok().json(chatbot_rust_wasm::explain_eligibility(
+        &business_name,
+        &customer_key,
+        body.amount,
+        body.outlet_id.as_deref(),
+        body.terminal_id.as_deref(),
+        &mut conn,
+    )))
+}
+
+#[utoipa::path(
+    get,
+    path = "/plugins/{business_name}",
+    tag = "owner",
+    params(("business_name" = String, Path)),
+    responses((status = 200, body = PluginInfo), (status = 404, body = ErrorBody)),
+    security(("api_key" = []))
+)]
+async fn get_plugin(
+    req: HttpRequest,
+    path: web::Path<String>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let business_name = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    require_api_key(&req, Some(&business_name), Role::Owner, &mut conn)?;
+    let info = plugins::load_plugin_info(&business_name, &mut conn)
+        .ok_or_else(|| ApiError::not_found("No plugin uploaded"))?;
+    Ok(HttpResponse::Ok().json(info))
+}
+
+#[utoipa::path(
+    put,
+    path = "/plugins/{business_name}",
+    tag = "owner",
+    params(("business_name" = String, Path)),
+    request_body(content = Vec<u8>, content_type = "application/wasm", description = "A WebAssembly module implementing the discount ABI"),
+    responses((status = 200, body = PluginInfo), (status = 400, body = ErrorBody)),
+    security(("api_key" = []))
+)]
+async fn upload_plugin(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Bytes,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let business_name = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    let principal = require_api_key(&req, Some(&business_name), Role::Owner, &mut conn)?;
+    let info = plugins::upload_plugin(&business_name, &body, &principal.label, &mut conn)?;
+    Ok(HttpResponse::Ok().json(info))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/plugins/{business_name}",
+    tag = "owner",
+    params(("business_name" = String, Path)),
+    responses((status = 204), (status = 404, body = ErrorBody)),
+    security(("api_key" = []))
+)]
+async fn delete_plugin(
+    req: HttpRequest,
+    path: web::Path<String>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let business_name = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    let principal = require_api_key(&req, Some(&business_name), Role::Owner, &mut conn)?;
+    plugins::delete_plugin(&business_name, &principal.label, &mut conn)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    get,
+    path = "/outlets/{business_name}",
+    tag = "owner",
+    params(("business_name" = String, Path)),
+    responses((status = 200, body = Vec<Outlet>)),
+    security(("api_key" = []))
+)]
+async fn list_outlets(
+    req: HttpRequest,
+    path: web::Path<String>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let business_name = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    require_api_key(&req, Some(&business_name), Role::Owner, &mut conn)?;
+    Ok(HttpResponse::Ok().json(outlets::load_outlets(&business_name, &mut conn)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/outlets/{business_name}",
+    tag = "owner",
+    params(("business_name" = String, Path)),
+    request_body = NewOutlet,
+    responses((status = 201, body = Outlet), (status = 400, body = ErrorBody)),
+    security(("api_key" = []))
+)]
+async fn create_outlet(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<NewOutlet>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let business_name = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    require_api_key(&req, Some(&business_name), Role::Owner, &mut conn)?;
+    let outlet = outlets::create_outlet(&business_name, &body.name, &mut conn).map_err(ApiError::invalid_request)?;
+    Ok(HttpResponse::Created().json(outlet))
+}
+
+#[utoipa::path(
+    get,
+    path = "/campaigns/{business_name}",
+    tag = "owner",
+    params(("business_name" = String, Path)),
+    responses((status = 200, body = Vec<Campaign>)),
+    security(("api_key" = []))
+)]
+async fn list_campaigns(
+    req: HttpRequest,
+    path: web::Path<String>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let business_name = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    require_api_key(&req, Some(&business_name), Role::Owner, &mut conn)?;
+    Ok(HttpResponse::Ok().json(campaigns::load_campaigns(&business_name, &mut conn)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/campaigns/{business_name}",
+    tag = "owner",
+    params(("business_name" = String, Path)),
+    request_body = Campaign,
+    responses((status = 201, body = Campaign), (status = 400, body = ErrorBody)),
+    security(("api_key" = []))
+)]
+async fn create_campaign(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<Campaign>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let business_name = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    require_api_key(&req, Some(&business_name), Role::Owner, &mut conn)?;
+    let campaign = campaigns::create_campaign(&business_name, body.into_inner(), &mut conn)
+        .map_err(ApiError::invalid_request)?;
+    Ok(HttpResponse::Created().json(campaign))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/campaigns/{business_name}/{campaign_id}",
+    tag = "owner",
+    params(("business_name" = String, Path), ("campaign_id" = String, Path)),
+    responses((status = 204), (status = 404, body = ErrorBody)),
+    security(("api_key" = []))
+)]
+async fn delete_campaign(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let (business_name, campaign_id) = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    require_api_key(&req, Some(&business_name), Role::Owner, &mut conn)?;
+    if !campaigns::delete_campaign(&business_name, &campaign_id, &mut conn) {
+        return Err(ApiError::not_found("Campaign not found"));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    get,
+    path = "/fraud/{business_name}/reviews",
+    tag = "owner",
+    params(("business_name" = String, Path), ReviewsQuery),
+    responses((status = 200, body = Vec<FraudReview>)),
+    security(("api_key" = []))
+)]
+async fn list_fraud_reviews(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<ReviewsQuery>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let business_name = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    require_api_key(&req, Some(&business_name), Role::Owner, &mut conn)?;
+    let mut reviews = fraud::load_reviews(&business_name, &mut conn);
+    if let Some(status) = query.status {
+        reviews.retain(|review| review.status == status);
+    }
+    Ok(HttpResponse::Ok().json(reviews))
+}
+
+#[utoipa::path(
+    post,
+    path = "/fraud/{business_name}/reviews/{review_id}",
+    tag = "owner",
+    params(("business_name" = String, Path), ("review_id" = String, Path)),
+    request_body = ReviewDecision,
+    responses(
+        (status = 200, body = FraudReview),
+        (status = 400, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+        (status = 409, description = "The review was already decided", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+async fn decide_fraud_review(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    body: web::Json<ReviewDecision>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let (business_name, review_id) = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    let principal = require_api_key(&req, Some(&business_name), Role::Owner, &mut conn)?;
+    let review = fraud::decide_review(
+        &business_name,
+        &review_id,
+        body.status,
+        body.block_customer,
+        &principal.label,
+        &mut conn,
+    )?;
+    Ok(HttpResponse::Ok().json(review))
+}
+
+#[utoipa::path(
+    post,
+    path = "/fraud/{business_name}/unblock",
+    tag = "owner",
+    params(("business_name" = String, Path)),
+    request_body = CustomerLookup,
+    responses((status = 204), (status = 404, body = ErrorBody)),
+    security(("api_key" = []))
+)]
+async fn unblock_customer(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<CustomerLookup>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let business_name = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    let principal = require_api_key(&req, Some(&business_name), Role::Owner, &mut conn)?;
+    let customer_key = resolve_customer(&body.customer_id, &business_name, &mut conn)?;
+    if !fraud::unblock_customer(&business_name, &customer_key, &principal.label, &mut conn) {
+        return Err(ApiError::not_found("Customer is not blocked"));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/businesses",
+    tag = "admin",
+    responses((status = 200, body = Vec<String>)),
+    security(("api_key" = []))
+)]
+async fn list_businesses(
+    req: HttpRequest,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = redis_connection(&redis_conn)?;
+    require_api_key(&req, None, Role::Admin, &mut conn)?;
+    Ok(HttpResponse::Ok().json(business::list_businesses(&mut conn)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/businesses",
+    tag = "admin",
+    request_body = NewBusiness,
+    responses((status = 201, body = Vec<String>), (status = 400, body = ErrorBody), (status = 409, body = ErrorBody)),
+    security(("api_key" = []))
+)]
+async fn register_business(
+    req: HttpRequest,
+    body: web::Json<NewBusiness>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = redis_connection(&redis_conn)?;
+    require_api_key(&req, None, Role::Admin, &mut conn)?;
+    business::validate_business_name(&body.business_name).map_err(ApiError::invalid_request)?;
+    if !business::register_business(&body.business_name, &mut conn) {
+        return Err(ApiError::new(ErrorCode::Conflict, "Business already registered"));
+    }
+    Ok(HttpResponse::Created().json(business::list_businesses(&mut conn)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/businesses/{business_name}",
+    tag = "admin",
+    params(("business_name" = String, Path)),
+    responses((status = 204), (status = 404, body = ErrorBody)),
+    security(("api_key" = []))
+)]
+async fn remove_business(
+    req: HttpRequest,
+    path: web::Path<String>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let business_name = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    require_api_key(&req, None, Role::Admin, &mut conn)?;
+    if !business::remove_business(&business_name, &mut conn) {
+        return Err(ApiError::not_found("Business not registered"));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/coalitions",
+    tag = "admin",
+    responses((status = 200, body = Vec<Coalition>)),
+    security(("api_key" = []))
+)]
+async fn list_coalitions(
+    req: HttpRequest,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = redis_connection(&redis_conn)?;
+    require_api_key(&req, None, Role::Admin, &mut conn)?;
+    Ok(HttpResponse::Ok().json(coalitions::list_coalitions(&mut conn)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/coalitions",
+    tag = "admin",
+    request_body = NewCoalition,
+    responses((status = 201, body = Coalition), (status = 400, body = ErrorBody), (status = 409, body = ErrorBody)),
+    security(("api_key" = []))
+)]
+async fn create_coalition(
+    req: HttpRequest,
+    body: web::Json<NewCoalition>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = redis_connection(&redis_conn)?;
+    let principal = require_api_key(&req, None, Role::Admin, &mut conn)?;
+    let coalition = coalitions::create_coalition(&body.name, &body.members, &mut conn)?;
+    for business_name in &coalition.members {
+        audit::record_audit(
+            business_name,
+            &principal.label,
+            "coalition_joined",
+            serde_json::json!({ "coalition_id": coalition.coalition_id, "members": coalition.members }),
+            &mut conn,
+        );
+    }
+    Ok(HttpResponse::Created().json(coalition))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/coalitions/{coalition_id}",
+    tag = "admin",
+    params(("coalition_id" = String, Path)),
+    responses((status = 204), (status = 404, body = ErrorBody)),
+    security(("api_key" = []))
+)]
+async fn delete_coalition(
+    req: HttpRequest,
+    path: web::Path<String>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let coalition_id = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    let principal = require_api_key(&req, None, Role::Admin, &mut conn)?;
+    let coalition = coalitions::delete_coalition(&coalition_id, &mut conn)
+        .ok_or_else(|| ApiError::not_found("Coalition not found"))?;
+    for business_name in &coalition.members {
+        audit::record_audit(
+            business_name,
+            &principal.label,
+            "coalition_left",
+            serde_json::json!({ "coalition_id": coalition.coalition_id }),
+            &mut conn,
+        );
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    get,
+    path = "/coalitions/{coalition_id}/settlement",
+    tag = "owner",
+    params(("coalition_id" = String, Path), SettlementQuery),
+    responses((status = 200, body = CoalitionSettlement), (status = 403, body = ErrorBody), (status = 404, body = ErrorBody)),
+    security(("api_key" = []))
+)]
+async fn get_coalition_settlement(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<SettlementQuery>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let coalition_id = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    let principal = require_api_key(&req, None, Role::Owner, &mut conn)?;
+    let coalition = coalitions::load_coalition(&coalition_id, &mut conn)
+        .ok_or_else(|| ApiError::not_found("Coalition not found"))?;
+    // Owners of any member may see how the members stand with each other.
+    let is_member = principal
+        .business_name
+        .as_ref()
+        .is_some_and(|business_name| coalition.members.contains(business_name));
+    if principal.role != Role::Admin && !is_member {
+        return Err(ApiError::new(ErrorCode::Forbidden, "Not a member of this coalition"));
+    }
+    let day = match query.week.as_deref() {
+        Some(day) => chrono::NaiveDate::parse_from_str(day, "%d-%b-%Y")
+            .map_err(|_| ApiError::invalid_request("week must look like 19-Oct-2026"))?,
+        None => chrono::Utc::now().date_naive(),
+    };
+    let monday = day - chrono::Duration::days(day.weekday().num_days_from_monday() as i64);
+    Ok(HttpResponse::Ok().json(coalitions::settlement(&coalition, monday, &mut conn)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/api_keys",
+    tag = "admin",
+    request_body = NewApiKey,
+    responses((status = 201, description = "The key is only shown once", body = ApiKeyResponse), (status = 400, body = ErrorBody)),
+    security(("api_key" = []))
+)]
+async fn create_api_key(
+    req: HttpRequest,
+    body: web::Json<NewApiKey>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = redis_connection(&redis_conn)?;
+    require_api_key(&req, None, Role::Admin, &mut conn)?;
+    let body = body.into_inner();
+    if body.role == Role::Customer {
+        return Err(ApiError::invalid_request(
+            "Customer tokens come from /generate_token, not API keys",
+        ));
+    }
+    if body.role != Role::Admin && body.business_name.is_none() {
+        return Err(ApiError::invalid_request(
+            "business_name is required for cashier and owner keys",
+        ));
+    }
+    let api_key = auth::issue_api_key(
+        &ApiKey {
+            business_name: body.business_name,
+            role: body.role,
+            label: body.label,
+        },
+        &mut conn,
+    );
+    Ok(HttpResponse::Created().json(ApiKeyResponse { api_key }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/migrations/phone_numbers",
+    tag = "admin",
+    responses((status = 200, body = PhoneMigrationReport)),
+    security(("api_key" = []))
+)]
+// Rewrites phone numbers stored before E.164 normalization and merges duplicates.
+async fn migrate_phone_numbers(
+    req: HttpRequest,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = redis_connection(&redis_conn)?;
+    let principal = require_api_key(&req, None, Role::Admin, &mut conn)?;
+    let report = migrations::normalize_stored_phone_numbers(&mut conn);
+    println!("Phone number migration - By: {}, Report: {:?}", principal.label, report);
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/customers/{business_name}/merge",
+    tag = "admin",
+    params(("business_name" = String, Path)),
+    request_body = CustomerMerge,
+    responses(
+        (status = 200, body = CustomerMergeReport),
+        (status = 400, body = ErrorBody),
+        (status = 409, description = "Both identifiers already belong to one customer", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+// Merges two customers, or moves a customer to a new phone number.
+async fn merge_customers(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<CustomerMerge>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let business_name = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    let principal = require_api_key(&req, None, Role::Admin, &mut conn)?;
+    let region = policy::load_policy(&business_name, &mut conn).default_region;
+    let from = CustomerId::parse(&body.from, &region)?;
+    let into = CustomerId::parse(&body.into, &region)?;
+    let report = customer::merge_customers(&business_name, &from, &into, &principal.label, &mut conn)?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[utoipa::path(
+    get,
+    path = "/audit/{business_name}",
+    tag = "owner",
+    params(("business_name" = String, Path)),
+    responses((status = 200, body = Vec<AuditEntry>)),
+    security(("api_key" = []))
+)]
+async fn get_audit_log(
+    req: HttpRequest,
+    path: web::Path<String>,
+    redis_conn: web::Data<redis::Client>,
+) -> Result<HttpResponse, ApiError> {
+    let business_name = path.into_inner();
+    let mut conn = redis_connection(&redis_conn)?;
+    require_api_key(&req, Some(&business_name), Role::Owner, &mut conn)?;
+    Ok(HttpResponse::Ok().json(audit::load_audit(&business_name, &mut conn)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/request_otp",
+    tag = "tokens",
+    request_body = OtpRequest,
+    responses(
+        (status = 202, description = "Code sent"),
+        (status = 400, body = ErrorBody),
+        (status = 429, description = "Too many requests; see Retry-After", body = ErrorBody),
+    )
+)]
+async fn request_otp(
+    req: HttpRequest,
+    body: web::Json<OtpRequest>,
+    redis_conn: web::Data<redis::Client>,
+    sender: web::Data<dyn MessageSender>,
+    rate_limiting: web::Data<RateLimiting>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = redis_connection(&redis_conn)?;
+
+    rate_limiting.check_client(&req, "request_otp", &mut conn)?;
+    let business_name = &body.business_name;
+    business::validate_business_name(business_name).map_err(ApiError::invalid_request)?;
+    business::require_registered(business_name, &mut conn)?;
+    let phone_number = normalize_phone(&body.phone, business_name, &mut conn)?;
+    otp::request_otp(&phone_number, business_name, sender.get_ref(), &mut conn)?;
+    Ok(HttpResponse::Accepted().body("OTP sent"))
+}
+
+#[utoipa::path(
+    get,
+    path = "/generate_token",
+    tag = "tokens",
+    params(TokenQuery),
+    responses((status = 200, body = TokenResponse), (status = 401, body = ErrorBody), (status = 429, description = "Rate limited; see Retry-After", body = ErrorBody))
+)]
 async fn generate_token(
+    req: HttpRequest,
     query: web::Query<TokenQuery>,
     redis_conn: web::Data<redis::Client>,
-) -> impl Responder {
-    let mut conn = redis_conn
-        .get_connection()
-        .expect("Failed to get Redis connection");
+    rate_limiting: web::Data<RateLimiting>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = redis_connection(&redis_conn)?;
 
-    let business_name = "test102";
-    let new_token = chatbot_rust_wasm::generate_and_store_token(&query.phone, business_name, &mut conn);
+    let business_name = &query.business_name;
+    // Nobody is authenticated yet, so only the client address is counted.
+    rate_limiting.check_client(&req, "generate_token", &mut conn)?;
+    business::require_registered(business_name, &mut conn)?;
+    let code = query.otp.as_deref().ok_or_else(|| {
+        ApiError::new(ErrorCode::InvalidOtp, "OTP required. Request one via /request_otp.")
+    })?;
+    let phone_number = normalize_phone(&query.phone, business_name, &mut conn)?;
+    otp::verify_otp(&phone_number, business_name, code, &mut conn)?;
+    let new_token = chatbot_rust_wasm::generate_and_store_token(&phone_number, business_name, &mut conn);
 
     let response = TokenResponse {
         token: new_token,
     };
-    HttpResponse::Ok().json(response)
+    Ok(HttpResponse::Ok().json(response))
+}
+
+// Reads one multipart field to the end.
+async fn read_field(field: &mut actix_multipart::Field, field_name: &str) -> Result<Vec<u8>, ApiError> {
+    let mut data = Vec::new();
+    while let Some(chunk) = field.next().await {
+        let chunk = chunk.map_err(|e| {
+            println!("Error reading {} chunk: {}", field_name, e);
+            ApiError::invalid_request(format!("Failed to read {}", field_name))
+        })?;
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
 }
 
+#[utoipa::path(
+    post,
+    path = "/submit_feedback",
+    tag = "feedback",
+    request_body(content(
+        (Feedback = "application/json"),
+        (Feedback = "multipart/form-data"),
+    )),
+    responses(
+        (status = 200, body = String),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 429, description = "Rate limited; see Retry-After", body = ErrorBody),
+    ),
+    security(("bearer_token" = []), ("api_key" = []))
+)]
 async fn submit_feedback(
+    req: HttpRequest,
     payload: Either<web::Json<Feedback>, Multipart>,
     redis_conn: web::Data<redis::Client>,
-) -> impl Responder {
+    rate_limiting: web::Data<RateLimiting>,
+) -> Result<HttpResponse, ApiError> {
     println!("Received feedback submission request");
+    let mut conn = redis_connection(&redis_conn)?;
+    rate_limiting.check_client(&req, "submit_feedback", &mut conn)?;
 
-    let feedback = match payload {
+    let mut feedback = match payload {
         Either::Left(json) => {
-            println!("Processing JSON payload: {:?}", json);
+            println!("Processing JSON payload - Rating: {}", json.rating);
             json.into_inner()
         }
         Either::Right(mut multipart) => {
             println!("Processing multipart/form-data payload");
-            let mut phone_number = String::new();
+            let mut business_name = String::new();
+            let mut customer_id = String::new();
             let mut rating = 0;
             let mut comment = String::new();
             let mut photo = None;
 
             // Parse multipart form data
             while let Some(item) = multipart.next().await {
-                let mut field = match item {
-                    Ok(field) => field,
-                    Err(e) => {
-                        println!("Failed to parse multipart field: {}", e);
-                        return HttpResponse::BadRequest().body(format!("Failed to parse multipart: {}", e));
-                    }
-                };
+                let mut field = item.map_err(|e| {
+                    println!("Failed to parse multipart field: {}", e);
+                    ApiError::invalid_request(format!("Failed to parse multipart: {}", e))
+                })?;
 
                 let field_name = field.name().to_string();
                 println!("Processing field: {}", field_name);
 
-                if field_name == "phone_number" {
-                    while let Some(chunk) = field.next().await {
-                        let data = match chunk {
-                            Ok(data) => data,
-                            Err(e) => {
-                                println!("Error reading phone_number chunk: {}", e);
-                                return HttpResponse::BadRequest().body("Failed to read phone_number");
-                            }
-                        };
-                        phone_number.push_str(&String::from_utf8_lossy(&data));
-                    }
-                    println!("Parsed phone_number: {}", phone_number);
+                if field_name == "business_name" {
+                    let data = read_field(&mut field, &field_name).await?;
+                    business_name.push_str(&String::from_utf8_lossy(&data));
+                } else if field_name == "customer_id" || field_name == "phone_number" {
+                    let data = read_field(&mut field, &field_name).await?;
+                    customer_id.push_str(&String::from_utf8_lossy(&data));
+                    println!("Parsed {}", field_name);
                 } else if field_name == "rating" {
-                    let mut rating_str = String::new();
-                    while let Some(chunk) = field.next().await {
-                        let data = match chunk {
-                            Ok(data) => data,
-                            Err(e) => {
-                                println!("Error reading rating chunk: {}", e);
-                                return HttpResponse::BadRequest().body("Failed to read rating");
-                            }
-                        };
-                        rating_str.push_str(&String::from_utf8_lossy(&data));
-                    }
-                    rating = rating_str.parse().unwrap_or(0);
+                    let data = read_field(&mut field, &field_name).await?;
+                    rating = String::from_utf8_lossy(&data).parse().unwrap_or(0);
                     println!("Parsed rating: {}", rating);
                 } else if field_name == "comment" {
-                    while let Some(chunk) = field.next().await {
-                        let data = match chunk {
-                            Ok(data) => data,
-                            Err(e) => {
-                                println!("Error reading comment chunk: {}", e);
-                                return HttpResponse::BadRequest().body("Failed to read comment");
-                            }
-                        };
-                        comment.push_str(&String::from_utf8_lossy(&data));
-                    }
+                    let data = read_field(&mut field, &field_name).await?;
+                    comment.push_str(&String::from_utf8_lossy(&data));
                     println!("Parsed comment: {}", comment);
                 } else if field_name == "photo" {
-                    let mut photo_data = Vec::new();
-                    while let Some(chunk) = field.next().await {
-                        let data = match chunk {
-                            Ok(data) => data,
-                            Err(e) => {
-                                println!("Error reading photo chunk: {}", e);
-                                return HttpResponse::BadRequest().body("Failed to read photo");
-                            }
-                        };
-                        photo_data.extend_from_slice(&data);
-                    }
+                    let photo_data = read_field(&mut field, &field_name).await?;
                     // Convert photo to base64
                     photo = Some(base64::encode(&photo_data));
                     println!("Parsed photo as base64 (length: {})", photo.as_ref().unwrap().len());
@@ -193,7 +1709,8 @@ async fn submit_feedback(
             }
 
             Feedback {
-                phone_number,
+                business_name,
+                customer_id,
                 rating,
                 comment,
                 photo,
@@ -201,58 +1718,253 @@ async fn submit_feedback(
         }
     };
 
+    let principal = require_cashier(&req, &feedback.business_name, &mut conn)?;
+    rate_limiting.check_caller("submit_feedback", bearer_token(&req), &feedback.business_name, &mut conn)?;
+
     // Validate rating (1 to 5)
     if feedback.rating < 1 || feedback.rating > 5 {
         println!("Invalid rating: {}", feedback.rating);
-        return HttpResponse::BadRequest().body("Rating must be between 1 and 5");
+        return Err(ApiError::new(ErrorCode::InvalidRating, "Rating must be between 1 and 5"));
     }
 
     // Store feedback in Redis
-    let mut conn = redis_conn
-        .get_connection()
-        .expect("Failed to get Redis connection");
+    feedback.customer_id =
+        resolve_customer_for(&principal, &feedback.customer_id, &feedback.business_name, &mut conn)?;
 
     let timestamp = chrono::Utc::now().timestamp();
-    let feedback_key = format!("feedback:{}:{}", feedback.phone_number, timestamp);
+    let feedback_key = format!("feedback:{}:{}", feedback.customer_id, timestamp);
 
     let feedback_data = serde_json::to_string(&feedback).unwrap();
-    let _: () = conn
-        .set(&feedback_key, &feedback_data)
-        .expect("Failed to store feedback in Redis");
+    let _: () = conn.set(&feedback_key, &feedback_data)?;
 
     println!(
         "Stored feedback - Key: {}, Data: {}",
         feedback_key, feedback_data
     );
 
-    HttpResponse::Ok().body("Feedback submitted successfully!")
+    Ok(HttpResponse::Ok().body("Feedback submitted successfully!"))
+}
+
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer_token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKeyScheme::Header(ApiKeyValue::new("X-Api-Key"))),
+        );
+        components.add_security_scheme(
+            "terminal_signature",
+            SecurityScheme::ApiKey(ApiKeyScheme::Header(ApiKeyValue::with_description(
+                "X-Signature",
+                "Hex HMAC-SHA256 of METHOD\\nPATH\\nTIMESTAMP\\nhex(SHA-256(body)), sent with X-Terminal-Id and X-Timestamp",
+            ))),
+        );
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "TheLoyalGame API", description = "Loyalty-pool discounts, tokens and feedback."),
+    paths(
+        create_discount,
+        get_discount,
+        link_customer,
+        get_customer,
+        get_pool_snapshot,
+        create_referral_code,
+        get_wallet,
+        update_wallet,
+        request_otp,
+        generate_token,
+        submit_feedback,
+        revoke_token,
+        create_terminal,
+        delete_terminal,
+        get_report,
+        get_transactions,
+        get_policy,
+        update_policy,
+        explain_eligibility,
+        get_plugin,
+        upload_plugin,
+        delete_plugin,
+        list_outlets,
+        create_outlet,
+        list_campaigns,
+        create_campaign,
+        delete_campaign,
+        list_fraud_reviews,
+        decide_fraud_review,
+        unblock_customer,
+        list_businesses,
+        register_business,
+        remove_business,
+        list_coalitions,
+        create_coalition,
+        delete_coalition,
+        get_coalition_settlement,
+        create_api_key,
+        migrate_phone_numbers,
+        merge_customers,
+        get_audit_log,
+    ),
+    modifiers(&SecurityAddon)
+)]
+struct ApiDoc;
+
+async fn openapi_json() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     let redis_client = redis::Client::open("redis://127.0.0.1:6379/").expect("Failed to connect to Redis");
 
+    // Bootstrap credential for the platform admin; everything else is issued through it.
+    if let Ok(admin_key) = std::env::var("ADMIN_API_KEY") {
+        let mut conn = redis_client
+            .get_connection()
+            .expect("Failed to get Redis connection");
+        auth::store_api_key(
+            &admin_key,
+            &ApiKey {
+                business_name: None,
+                role: Role::Admin,
+                label: "bootstrap-admin".to_string(),
+            },
+            &mut conn,
+        );
+    }
+
+    // TOKEN_MODE=signed issues HMAC-signed tokens that validate without Redis.
+    // TOKEN_SIGNING_KEYS is "kid:secret,..."; the first key signs, all of them verify.
+    if std::env::var("TOKEN_MODE").as_deref() == Ok("signed") {
+        let keys = std::env::var("TOKEN_SIGNING_KEYS")
+            .expect("TOKEN_SIGNING_KEYS is required when TOKEN_MODE=signed");
+        let signer = TokenSigner::from_config(&keys).expect("Invalid TOKEN_SIGNING_KEYS");
+        signed_token::install_signer(signer);
+        println!("Issuing signed tokens");
+    }
+
+    // PHONE_PEPPER stores a keyed hash of each phone number instead of the number.
+    // Run POST /admin/migrations/phone_numbers after enabling it to rewrite older data.
+    if let Ok(pepper) = std::env::var("PHONE_PEPPER") {
+        let pseudonymizer = Pseudonymizer::new(pepper.into_bytes()).expect("Invalid PHONE_PEPPER");
+        pseudonym::install_pseudonymizer(pseudonymizer);
+        println!("Pseudonymizing phone numbers");
+    }
+
+    // For development only: OTP_SENDER=file writes codes to OTP_OUTBOX (default
+    // otp_outbox.log), OTP_SENDER=log notes each message without its code.
+    // Without a sender, /request_otp fails with delivery_failed.
+    let otp_sender: Arc<dyn MessageSender> = match std::env::var("OTP_SENDER").as_deref() {
+        Ok("file") => Arc::new(FileMessageSender {
+            path: std::env::var("OTP_OUTBOX")
+                .unwrap_or_else(|_| "otp_outbox.log".to_string())
+                .into(),
+        }),
+        Ok("log") => Arc::new(LogMessageSender),
+        _ => {
+            println!("No OTP_SENDER set; one-time passwords can't be delivered");
+            Arc::new(NoMessageSender)
+        }
+    };
+    let otp_sender = web::Data::from(otp_sender);
+
+    // RATE_LIMIT_PER_IP, RATE_LIMIT_PER_TOKEN and RATE_LIMIT_PER_BUSINESS are
+    // "<requests>/<seconds>" or "off". Set RATE_LIMIT_TRUST_PROXY=1 behind a
+    // reverse proxy so clients are told apart by their forwarded address.
+    let mut limits = RateLimits::default();
+    for (var, limit) in [
+        ("RATE_LIMIT_PER_IP", &mut limits.per_ip),
+        ("RATE_LIMIT_PER_TOKEN", &mut limits.per_token),
+        ("RATE_LIMIT_PER_BUSINESS", &mut limits.per_business),
+    ] {
+        if let Ok(value) = std::env::var(var) {
+            *limit = RateLimit::parse(&value).unwrap_or_else(|e| panic!("Invalid {}: {}", var, e));
+        }
+    }
+    let rate_limiting = web::Data::new(RateLimiting {
+        limits,
+        trust_proxy_headers: std::env::var("RATE_LIMIT_TRUST_PROXY").as_deref() == Ok("1"),
+    });
+
     println!("Server starting on http://0.0.0.0:3030");
     HttpServer::new(move || {
         let cors = Cors::default()
             .allowed_origin("http://127.0.0.1:8000")
-            .allowed_methods(vec!["GET", "POST", "OPTIONS"])
+            .allowed_methods(vec!["GET", "POST", "PUT", "DELETE", "OPTIONS"])
             .allow_any_header()
             .max_age(3600);
 
         App::new()
             .wrap(cors)
-            .wrap(Logger::default()) // Add default Actix Web logger
+            // Default Actix Web format, with the request line redacted
+            .wrap(
+                Logger::new("%a \"%{request}xi\" %s %b \"%{Referer}i\" \"%{User-Agent}i\" %T")
+                    .custom_request_replace("request", |req| {
+                        format!("{} {}", req.method(), redacted_path(req.path()))
+                    }),
+            )
             .wrap(RequestLogger) // Add custom request logger
             .app_data(web::Data::new(redis_client.clone()))
+            .app_data(otp_sender.clone())
+            .app_data(rate_limiting.clone())
             // Configure payload size limit for the entire app (10 MB)
             .app_data(web::PayloadConfig::new(10 * 1024 * 1024)) // 10 MB limit
             .route(
                 "/get_discount/{business_name}/phone_number_amount/{phone_number_amount}/token/{token}",
                 web::get().to(get_discount),
             )
+            .route("/request_otp", web::post().to(request_otp))
             .route("/generate_token", web::get().to(generate_token))
             .route("/submit_feedback", web::post().to(submit_feedback))
+            .route("/revoke_token/{business_name}", web::post().to(revoke_token))
+            .route("/api-docs/openapi.json", web::get().to(openapi_json))
+            .service(Scalar::with_url("/docs", ApiDoc::openapi()))
+            .route("/v1/discounts", web::post().to(create_discount))
+            .route("/terminal/discounts", web::post().to(create_discount))
+            .route("/v1/customers/{business_name}/links", web::post().to(link_customer))
+            .route("/v1/customers/{business_name}/{customer_id}", web::get().to(get_customer))
+            .route("/v1/pool_snapshot/{business_name}", web::post().to(get_pool_snapshot))
+            .route("/v1/referrals/{business_name}", web::post().to(create_referral_code))
+            .route("/v1/wallets/{business_name}/lookup", web::post().to(get_wallet))
+            .route("/v1/wallets/{business_name}", web::put().to(update_wallet))
+            .route("/terminals/{business_name}", web::post().to(create_terminal))
+            .route("/terminals/{business_name}/{terminal_id}", web::delete().to(delete_terminal))
+            .route("/reports/{business_name}", web::get().to(get_report))
+            .route("/transactions/{business_name}", web::get().to(get_transactions))
+            .route("/policy/{business_name}", web::get().to(get_policy))
+            .route("/policy/{business_name}", web::put().to(update_policy))
+            .route("/rules/{business_name}/explain", web::post().to(explain_eligibility))
+            .route("/plugins/{business_name}", web::get().to(get_plugin))
+            .route("/plugins/{business_name}", web::put().to(upload_plugin))
+            .route("/plugins/{business_name}", web::delete().to(delete_plugin))
+            .route("/outlets/{business_name}", web::get().to(list_outlets))
+            .route("/outlets/{business_name}", web::post().to(create_outlet))
+            .route("/fraud/{business_name}/reviews", web::get().to(list_fraud_reviews))
+            .route("/fraud/{business_name}/reviews/{review_id}", web::post().to(decide_fraud_review))
+            .route("/fraud/{business_name}/unblock", web::post().to(unblock_customer))
+            .route("/campaigns/{business_name}", web::get().to(list_campaigns))
+            .route("/campaigns/{business_name}", web::post().to(create_campaign))
+            .route("/campaigns/{business_name}/{campaign_id}", web::delete().to(delete_campaign))
+            .route("/admin/businesses", web::get().to(list_businesses))
+            .route("/admin/businesses", web::post().to(register_business))
+            .route("/admin/businesses/{business_name}", web::delete().to(remove_business))
+            .route("/admin/coalitions", web::get().to(list_coalitions))
+            .route("/admin/coalitions", web::post().to(create_coalition))
+            .route("/admin/coalitions/{coalition_id}", web::delete().to(delete_coalition))
+            .route("/coalitions/{coalition_id}/settlement", web::get().to(get_coalition_settlement))
+            .route("/admin/api_keys", web::post().to(create_api_key))
+            .route("/admin/migrations/phone_numbers", web::post().to(migrate_phone_numbers))
+            .route("/admin/customers/{business_name}/merge", web::post().to(merge_customers))
+            .route("/audit/{business_name}", web::get().to(get_audit_log))
     })
     .bind("0.0.0.0:3030")?
     .run()
@@ -0,0 +1,316 @@
+use crate::customer::{CustomerId, CustomerProfile};
+use crate::phone::{self, DEFAULT_REGION};
+use crate::{
+    delete_data_from_redis, fetch_data_from_redis, list_redis_keys, parse_customer_discount_details,
+    outlets, persist_data_to_redis, policy, pseudonym, transactions, CustomerDiscountDetails,
+};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use utoipa::ToSchema;
+
+#[derive(Serialize, Debug, Default, PartialEq, ToSchema)]
+pub struct PhoneMigrationReport {
+    /// Weekly blobs that had at least one customer re-keyed.
+    pub periods_rewritten: usize,
+    /// Customer entries folded into another spelling of the same number.
+    pub customers_merged: usize,
+    /// Token, feedback, customer link, wallet, streak, referral and fraud
+    /// block keys moved to their current name.
+    pub keys_renamed: usize,
+    /// Daily transaction logs that had at least one entry re-keyed.
+    pub transaction_logs_rewritten: usize,
+    /// Numbers that could not be parsed; they are left as they were.
+    pub unparseable: Vec<String>,
+}
+
+/// Rewrites phone numbers stored before E.164 normalization, merging
+/// customers that appear under several spellings, and pseudonymizes them
+/// when a pepper is installed. Keys scoped to a business use its region;
+/// token and feedback keys, which carry no business, use `DEFAULT_REGION`.
+/// Safe to run more than once.
+pub fn normalize_stored_phone_numbers(conn: &mut redis::Connection) -> PhoneMigrationReport {
+    let mut migration = Migration::default();
+
+    for key in list_redis_keys("*___*", conn) {
+        let Some((pool_name, _)) = key.split_once("___") else {
+            continue;
+        };
+        let region = migration.region(outlets::pool_business_name(pool_name), conn);
+        let mut details = parse_customer_discount_details(&fetch_data_from_redis(&key, conn));
+        let (merged, changed) = merge_customers(&mut details, &region, &mut migration.unparseable);
+        if changed {
+            persist_data_to_redis(&key, serde_json::to_string(&details).unwrap(), conn);
+            migration.report.periods_rewritten += 1;
+            migration.report.customers_merged += merged;
+            println!("Normalized phone numbers - Key: {}, Merged: {}", key, merged);
+        }
+    }
+
+    for key in list_redis_keys("phone:*:token", conn) {
+        let raw = &key["phone:".len()..key.len() - ":token".len()];
+        if let Some(new_key) = migration.rekey(raw, DEFAULT_REGION) {
+            migration.move_key(&key, &format!("phone:{}:token", new_key), conn);
+        }
+    }
+
+    for key in list_redis_keys("feedback:*", conn) {
+        let Some((raw, timestamp)) = key["feedback:".len()..].rsplit_once(':') else {
+            continue;
+        };
+        if let Some(new_key) = migration.rekey(raw, DEFAULT_REGION) {
+            let mut feedback: serde_json::Value =
+                serde_json::from_str(&fetch_data_from_redis(&key, conn)).unwrap_or_default();
+            for field in ["customer_id", "phone_number"] {
+                if let Some(value) = feedback.get_mut(field) {
+                    *value = serde_json::Value::String(new_key.clone());
+                }
+            }
+            persist_data_to_redis(&key, feedback.to_string(), conn);
+            migration.move_key(&key, &format!("feedback:{}:{}", new_key, timestamp), conn);
+        }
+    }
+
+    for key in list_redis_keys("customer_link:*", conn) {
+        let Some((business_name, raw)) = key["customer_link:".len()..].split_once(':') else {
+            continue;
+        };
+        let region = migration.region(business_name, conn);
+        let profile_id = fetch_data_from_redis(&key, conn);
+        if let Some(new_profile_id) = migration.rekey(&profile_id, &region) {
+            persist_data_to_redis(&key, new_profile_id, conn);
+        }
+        if let Some(new_key) = migration.rekey(raw, &region) {
+            migration.move_key(&key, &format!("customer_link:{}:{}", business_name, new_key), conn);
+        }
+    }
+
+    for key in list_redis_keys("customer_profile:*", conn) {
+        let Some((business_name, raw)) = key["customer_profile:".len()..].split_once(':') else {
+            continue;
+        };
+        let region = migration.region(business_name, conn);
+        let Ok(mut profile) = serde_json::from_str::<CustomerProfile>(&fetch_data_from_redis(&key, conn)) else {
+            continue;
+        };
+        let mut changed = false;
+        for identifier in std::iter::once(&mut profile.profile_id).chain(profile.identifiers.iter_mut()) {
+            if let Some(new_key) = migration.rekey(identifier, &region) {
+                *identifier = new_key;
+                changed = true;
+            }
+        }
+        if changed {
+            persist_data_to_redis(&key, serde_json::to_string(&profile).unwrap(), conn);
+        }
+        if let Some(new_key) = migration.rekey(raw, &region) {
+            migration.move_key(&key, &format!("customer_profile:{}:{}", business_name, new_key), conn);
+        }
+    }
+
+    // Per-customer records kept under `<prefix>:<business>:<customer>`.
+    for prefix in ["wallet", "streak", "referral_code_of", "referral_reward", "referred_by", "fraud_blocked"] {
+        for key in list_redis_keys(&format!("{}:*", prefix), conn) {
+            let Some((business_name, raw)) = key[prefix.len() + 1..].split_once(':') else {
+                continue;
+            };
+            let region = migration.region(business_name, conn);
+            if prefix == "referred_by" {
+                let mut referral: serde_json::Value =
+                    serde_json::from_str(&fetch_data_from_redis(&key, conn)).unwrap_or_default();
+                let mut changed = false;
+                for field in ["referrer", "referee"] {
+                    let raw_referral = referral.get(field).and_then(|value| value.as_str()).map(str::to_string);
+                    if let Some(new_key) = raw_referral.and_then(|value| migration.rekey(&value, &region)) {
+                        referral[field] = serde_json::Value::String(new_key);
+                        changed = true;
+                    }
+                }
+                if changed {
+                    persist_data_to_redis(&key, referral.to_string(), conn);
+                }
+            }
+            if let Some(new_key) = migration.rekey(raw, &region) {
+                migration.move_key(&key, &format!("{}:{}:{}", prefix, business_name, new_key), conn);
+            }
+        }
+    }
+
+    // Referral codes point at the customer who owns them.
+    for key in list_redis_keys("referral_code:*", conn) {
+        let Some((business_name, _)) = key["referral_code:".len()..].split_once(':') else {
+            continue;
+        };
+        let region = migration.region(business_name, conn);
+        if let Some(new_key) = migration.rekey(&fetch_data_from_redis(&key, conn), &region) {
+            persist_data_to_redis(&key, new_key, conn);
+        }
+    }
+
+    for key in list_redis_keys("transactions:*", conn) {
+        let Some((business_name, _)) = key["transactions:".len()..].split_once(':') else {
+            continue;
+        };
+        let region = migration.region(business_name, conn);
+        let mut records = transactions::load_transactions_at(&key, conn);
+        let mut changed = false;
+        for record in records.iter_mut() {
+            if let Some(new_key) = migration.rekey(&record.customer_id, &region) {
+                record.customer_id = new_key;
+                changed = true;
+            }
+        }
+        if changed {
+            transactions::replace_transactions_at(&key, &records, conn);
+            migration.report.transaction_logs_rewritten += 1;
+        }
+    }
+
+    migration.report.unparseable = migration.unparseable.into_iter().collect();
+    migration.report
+}
+
+#[derive(Default)]
+struct Migration {
+    report: PhoneMigrationReport,
+    unparseable: BTreeSet<String>,
+    regions: HashMap<String, String>,
+}
+
+impl Migration {
+    fn region(&mut self, business_name: &str, conn: &mut redis::Connection) -> String {
+        self.regions
+            .entry(business_name.to_string())
+            .or_insert_with(|| policy::load_policy(business_name, conn).default_region)
+            .clone()
+    }
+
+    fn rekey(&mut self, raw: &str, region: &str) -> Option<String> {
+        match current_key(raw, region) {
+            Ok(new_key) => new_key,
+            Err(()) => {
+                self.unparseable.insert(raw.to_string());
+                None
+            }
+        }
+    }
+
+    fn move_key(&mut self, old_key: &str, new_key: &str, conn: &mut redis::Connection) {
+        // A record written under the new name is newer; keep it.
+        if fetch_data_from_redis(new_key, conn).is_empty() {
+            persist_data_to_redis(new_key, fetch_data_from_redis(old_key, conn), conn);
+        }
+        delete_data_from_redis(old_key, conn);
+        self.report.keys_renamed += 1;
+    }
+}
+
+/// The key a customer stored as `raw` should have now, or `None` if it
+/// already has it. Card, email and pseudonymized keys never change.
+fn current_key(raw: &str, region: &str) -> Result<Option<String>, ()> {
+    if pseudonym::is_pseudonym(raw) || !CustomerId::from_key(raw).is_phone() {
+        return Ok(None);
+    }
+    let phone_number = phone::normalize_phone_number(raw, region).map_err(|_| ())?;
+    let key = pseudonym::phone_key(&phone_number);
+    Ok((key != raw).then_some(key))
+}
+
+/// Re-keys `details.customer_expense_map` by current key. Returns how many
+/// entries were merged into another and whether anything changed.
+fn merge_customers(
+    details: &mut CustomerDiscountDetails,
+    region: &str,
+    unparseable: &mut BTreeSet<String>,
+) -> (usize, bool) {
+    let mut changed = false;
+    let mut merged = 0;
+    let mut customers: HashMap<String, HashMap<String, String>> = HashMap::new();
+    for (raw, days) in details.customer_expense_map.drain() {
+        let key = match current_key(&raw, region) {
+            Ok(Some(new_key)) => {
+                changed = true;
+                new_key
+            }
+            Ok(None) => raw,
+            Err(()) => {
+                unparseable.insert(raw.clone());
+                raw
+            }
+        };
+        match customers.get_mut(&key) {
+            Some(existing) => {
+                merged += 1;
+                for (day, amounts) in days {
+                    let entry = existing.entry(day).or_default();
+                    if entry.is_empty() {
+                        *entry = amounts;
+                    } else if !amounts.is_empty() {
+                        *entry = format!("{},{}", entry, amounts);
+                    }
+                }
+            }
+            None => {
+                customers.insert(key, days);
+            }
+        }
+    }
+    details.customer_expense_map = customers;
+    // Every customer was counted as eligible once; duplicates were counted twice.
+    details.total_eligible_customers = (details.total_eligible_customers - merged as f64).max(0.0);
+    (merged, changed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn days(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(day, amounts)| (day.to_string(), amounts.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_merge_customers() {
+        let mut details = CustomerDiscountDetails {
+            total_eligible_customers: 4.0,
+            ..CustomerDiscountDetails::default()
+        };
+        details
+            .customer_expense_map
+            .insert("9876543210".to_string(), days(&[("13-Oct-2026", "100")]));
+        details
+            .customer_expense_map
+            .insert("+91 98765 43210".to_string(), days(&[("13-Oct-2026", "50"), ("14-Oct-2026", "20")]));
+        details
+            .customer_expense_map
+            .insert("not-a-phone".to_string(), days(&[("14-Oct-2026", "10")]));
+        details
+            .customer_expense_map
+            .insert("card:LC12345678".to_string(), days(&[("14-Oct-2026", "30")]));
+
+        let mut unparseable = BTreeSet::new();
+        assert_eq!(merge_customers(&mut details, "IN", &mut unparseable), (1, true));
+        assert_eq!(details.total_eligible_customers, 3.0);
+        assert_eq!(unparseable.into_iter().collect::<Vec<_>>(), vec!["not-a-phone"]);
+
+        let customer = &details.customer_expense_map["+919876543210"];
+        let mut amounts: Vec<&str> = customer["13-Oct-2026"].split(',').collect();
+        amounts.sort();
+        assert_eq!(amounts, vec!["100", "50"]);
+        assert_eq!(customer["14-Oct-2026"], "20");
+
+        // Already normalized data is left alone.
+        let mut unparseable = BTreeSet::new();
+        assert_eq!(merge_customers(&mut details, "IN", &mut unparseable), (0, false));
+    }
+
+    #[test]
+    fn test_current_key_skips_other_identifiers() {
+        assert_eq!(current_key("card:LC12345678", "IN"), Ok(None));
+        assert_eq!(current_key("email:asha@example.com", "IN"), Ok(None));
+        assert_eq!(current_key("ph_3210_0123456789abcdef", "IN"), Ok(None));
+        assert_eq!(current_key("nonsense", "IN"), Err(()));
+    }
+}
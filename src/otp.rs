@@ -0,0 +1,203 @@
+use crate::secrets::constant_time_eq;
+use crate::{
+    delete_data_from_redis, fetch_data_from_redis, increment_counter,
+    persist_data_to_redis_with_expiry, pseudonym,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+/// How long a code stays valid after it is sent.
+pub const OTP_TTL_SECS: u64 = 5 * 60;
+/// Codes a single phone may request per window.
+pub const OTP_REQUEST_LIMIT: i64 = 3;
+pub const OTP_REQUEST_WINDOW_SECS: u64 = 15 * 60;
+/// Wrong guesses allowed before a code is burned.
+pub const OTP_MAX_ATTEMPTS: u32 = 5;
+
+/// Delivers one-time passwords to customers. The server picks an
+/// implementation at startup; SMS gateways plug in here.
+pub trait MessageSender: Send + Sync {
+    fn send(&self, phone_number: &str, message: &str) -> Result<(), String>;
+}
+
+/// Development sender that only notes in the server log that a message went
+/// out. The number is masked and the message, which holds the code, is left
+/// out, so codes can't be read from the log.
+pub struct LogMessageSender;
+
+impl MessageSender for LogMessageSender {
+    fn send(&self, phone_number: &str, message: &str) -> Result<(), String> {
+        println!(
+            "OTP message to {} ({} characters, not logged)",
+            pseudonym::mask_phone_number(phone_number),
+            message.len()
+        );
+        Ok(())
+    }
+}
+
+/// Used when the server was started without a sender. Every request fails,
+/// so codes are never silently dropped.
+pub struct NoMessageSender;
+
+impl MessageSender for NoMessageSender {
+    fn send(&self, _phone_number: &str, _message: &str) -> Result<(), String> {
+        Err("no OTP sender is configured".to_string())
+    }
+}
+
+/// Development sender that appends each message to a local file.
+pub struct FileMessageSender {
+    pub path: PathBuf,
+}
+
+impl MessageSender for FileMessageSender {
+    fn send(&self, phone_number: &str, message: &str) -> Result<(), String> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| format!("Failed to open {}: {}", self.path.display(), e))?;
+        writeln!(file, "{} {} {}", Utc::now().to_rfc3339(), phone_number, message)
+            .map_err(|e| format!("Failed to write {}: {}", self.path.display(), e))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum OtpError {
+    RateLimited { retry_after_secs: u64 },
+    InvalidCode,
+    NoPendingCode,
+    TooManyAttempts,
+    Delivery(String),
+}
+
+impl std::fmt::Display for OtpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OtpError::RateLimited { retry_after_secs } => write!(
+                f,
+                "Too many OTP requests. Try again in {} seconds.",
+                retry_after_secs
+            ),
+            OtpError::InvalidCode => write!(f, "Invalid OTP."),
+            OtpError::NoPendingCode => write!(f, "No OTP pending for this phone. Request a new one."),
+            OtpError::TooManyAttempts => write!(f, "Too many wrong attempts. Request a new OTP."),
+            OtpError::Delivery(message) => write!(f, "Failed to deliver OTP: {}", message),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PendingOtp {
+    code: String,
+    attempts: u32,
+}
+
+/// Sends a fresh code to `phone_number`, replacing any pending one.
+pub fn request_otp(
+    phone_number: &str,
+    business_name: &str,
+    sender: &dyn MessageSender,
+    conn: &mut redis::Connection,
+) -> Result<(), OtpError> {
+    let (requests, window_left) = increment_counter(
+        &format!("otp_requests:{}:{}", business_name, pseudonym::phone_key(phone_number)),
+        OTP_REQUEST_WINDOW_SECS,
+        conn,
+    );
+    if requests > OTP_REQUEST_LIMIT {
+        return Err(OtpError::RateLimited {
+            retry_after_secs: window_left.max(1) as u64,
+        });
+    }
+
+    let code = generate_code();
+    sender
+        .send(
+            phone_number,
+            &format!(
+                "Your {} loyalty code is {}. It expires in {} minutes.",
+                business_name,
+                code,
+                OTP_TTL_SECS / 60
+            ),
+        )
+        .map_err(OtpError::Delivery)?;
+    store_pending(
+        business_name,
+        phone_number,
+        &PendingOtp { code, attempts: 0 },
+        conn,
+    );
+    Ok(())
+}
+
+/// Checks `code` against the pending code for `phone_number`. A code can be
+/// used once; wrong guesses count against `OTP_MAX_ATTEMPTS`.
+pub fn verify_otp(
+    phone_number: &str,
+    business_name: &str,
+    code: &str,
+    conn: &mut redis::Connection,
+) -> Result<(), OtpError> {
+    let otp_key = otp_redis_key(business_name, phone_number);
+    let data = fetch_data_from_redis(&otp_key, conn);
+    let mut pending: PendingOtp =
+        serde_json::from_str(&data).map_err(|_| OtpError::NoPendingCode)?;
+
+    if constant_time_eq(pending.code.as_bytes(), code.trim().as_bytes()) {
+        delete_data_from_redis(&otp_key, conn);
+        return Ok(());
+    }
+
+    pending.attempts += 1;
+    if pending.attempts >= OTP_MAX_ATTEMPTS {
+        delete_data_from_redis(&otp_key, conn);
+        return Err(OtpError::TooManyAttempts);
+    }
+    store_pending(business_name, phone_number, &pending, conn);
+    Err(OtpError::InvalidCode)
+}
+
+fn store_pending(
+    business_name: &str,
+    phone_number: &str,
+    pending: &PendingOtp,
+    conn: &mut redis::Connection,
+) {
+    // Wrong guesses re-store the code with a fresh TTL; that is bounded by
+    // OTP_MAX_ATTEMPTS so a code can't be kept alive indefinitely.
+    persist_data_to_redis_with_expiry(
+        &otp_redis_key(business_name, phone_number),
+        serde_json::to_string(pending).unwrap(),
+        OTP_TTL_SECS,
+        conn,
+    );
+}
+
+fn otp_redis_key(business_name: &str, phone_number: &str) -> String {
+    format!("otp:{}:{}", business_name, pseudonym::phone_key(phone_number))
+}
+
+fn generate_code() -> String {
+    format!("{:06}", Uuid::new_v4().as_u128() % 1_000_000)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_generate_code_is_six_digits() {
+        for _ in 0..100 {
+            let code = generate_code();
+            assert_eq!(code.len(), 6);
+            assert!(code.chars().all(|c| c.is_ascii_digit()));
+        }
+    }
+}
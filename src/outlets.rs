@@ -0,0 +1,89 @@
+use crate::{fetch_data_from_redis, period_redis_key, persist_data_to_redis};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// A branch of a business. A business's outlets are stored together under
+/// `outlets:<business>`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct Outlet {
+    pub outlet_id: String,
+    pub name: String,
+}
+
+pub fn load_outlets(business_name: &str, conn: &mut redis::Connection) -> Vec<Outlet> {
+    serde_json::from_str(&fetch_data_from_redis(&outlets_redis_key(business_name), conn)).unwrap_or_default()
+}
+
+pub fn outlet_exists(business_name: &str, outlet_id: &str, conn: &mut redis::Connection) -> bool {
+    load_outlets(business_name, conn)
+        .iter()
+        .any(|outlet| outlet.outlet_id == outlet_id)
+}
+
+pub fn create_outlet(business_name: &str, name: &str, conn: &mut redis::Connection) -> Result<Outlet, String> {
+    let name = name.trim();
+    if name.is_empty() || name.len() > 64 {
+        return Err("Outlet name must be 1-64 characters".to_string());
+    }
+    let mut outlets = load_outlets(business_name, conn);
+    if outlets.iter().any(|outlet| outlet.name.eq_ignore_ascii_case(name)) {
+        return Err(format!("An outlet named {} already exists", name));
+    }
+    let outlet = Outlet {
+        outlet_id: format!("out_{}", &Uuid::new_v4().simple().to_string()[..8]),
+        name: name.to_string(),
+    };
+    outlets.push(outlet.clone());
+    persist_data_to_redis(
+        &outlets_redis_key(business_name),
+        serde_json::to_string(&outlets).unwrap(),
+        conn,
+    );
+    println!(
+        "Outlet created - Business: {}, Id: {}, Name: {}",
+        business_name, outlet.outlet_id, outlet.name
+    );
+    Ok(outlet)
+}
+
+/// Name under which the pool of `outlet_id` is stored, in place of the
+/// business name in period keys. Business names can't contain `@`.
+pub fn outlet_pool_name(business_name: &str, outlet_id: &str) -> String {
+    format!("{}@{}", business_name, outlet_id)
+}
+
+/// The business a pool name (the part of a period key before `___`) belongs to.
+pub fn pool_business_name(pool_name: &str) -> &str {
+    pool_name.split('@').next().unwrap_or(pool_name)
+}
+
+/// Period keys of every pool `business_name` may have for the week starting
+/// `monday`: the chain-wide one and one per outlet.
+pub fn pool_redis_keys(business_name: &str, monday: NaiveDate, conn: &mut redis::Connection) -> Vec<String> {
+    std::iter::once(period_redis_key(business_name, monday))
+        .chain(
+            load_outlets(business_name, conn)
+                .iter()
+                .map(|outlet| period_redis_key(&outlet_pool_name(business_name, &outlet.outlet_id), monday)),
+        )
+        .collect()
+}
+
+fn outlets_redis_key(business_name: &str) -> String {
+    format!("outlets:{}", business_name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pool_names() {
+        let pool_name = outlet_pool_name("test102", "out_1234abcd");
+        assert_eq!(pool_name, "test102@out_1234abcd");
+        assert_eq!(pool_business_name(&pool_name), "test102");
+        assert_eq!(pool_business_name("test102"), "test102");
+    }
+}
@@ -0,0 +1,143 @@
+/// Region used when a business has not configured one. The service started
+/// out in India, so bare 10-digit numbers are Indian.
+pub const DEFAULT_REGION: &str = "IN";
+
+/// What we need to know about a region to read numbers dialled locally.
+pub struct Region {
+    /// ISO 3166-1 alpha-2 code, as stored in a business policy.
+    pub code: &'static str,
+    pub calling_code: &'static str,
+    /// Allowed lengths of the national significant number.
+    pub national_lengths: &'static [usize],
+    /// Dialled before the national number for domestic calls ("0" in India).
+    pub trunk_prefix: Option<&'static str>,
+}
+
+static REGIONS: &[Region] = &[
+    Region { code: "IN", calling_code: "91", national_lengths: &[10], trunk_prefix: Some("0") },
+    Region { code: "US", calling_code: "1", national_lengths: &[10], trunk_prefix: Some("1") },
+    Region { code: "CA", calling_code: "1", national_lengths: &[10], trunk_prefix: Some("1") },
+    Region { code: "GB", calling_code: "44", national_lengths: &[9, 10], trunk_prefix: Some("0") },
+    Region { code: "AE", calling_code: "971", national_lengths: &[8, 9], trunk_prefix: Some("0") },
+    Region { code: "SG", calling_code: "65", national_lengths: &[8], trunk_prefix: None },
+    Region { code: "AU", calling_code: "61", national_lengths: &[9], trunk_prefix: Some("0") },
+    Region { code: "NP", calling_code: "977", national_lengths: &[8, 10], trunk_prefix: Some("0") },
+    Region { code: "LK", calling_code: "94", national_lengths: &[9], trunk_prefix: Some("0") },
+    Region { code: "BD", calling_code: "880", national_lengths: &[10], trunk_prefix: Some("0") },
+];
+
+pub fn region(code: &str) -> Option<&'static Region> {
+    REGIONS.iter().find(|region| region.code.eq_ignore_ascii_case(code.trim()))
+}
+
+/// Parses `input` as dialled by a customer of a business in `default_region`
+/// and returns it in E.164 (`+919876543210`), the only form we store.
+///
+/// Numbers starting with `+` or `00` are international. Anything else is
+/// read as a national number of `default_region`, with or without its trunk
+/// prefix or calling code.
+pub fn normalize_phone_number(input: &str, default_region: &str) -> Result<String, String> {
+    let invalid = || format!("Invalid phone number: {}", input.trim());
+    let compact: String = input
+        .trim()
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '-' | '.' | '(' | ')'))
+        .collect();
+    let (international, digits) = match compact.strip_prefix('+') {
+        Some(rest) => (true, rest),
+        None => match compact.strip_prefix("00") {
+            Some(rest) => (true, rest),
+            None => (false, compact.as_str()),
+        },
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+
+    if international {
+        return normalize_international(digits).ok_or_else(invalid);
+    }
+
+    let region = region(default_region)
+        .ok_or_else(|| format!("Unsupported phone region: {}", default_region))?;
+    if region.national_lengths.contains(&digits.len()) {
+        return Ok(format!("+{}{}", region.calling_code, digits));
+    }
+    if let Some(national) = region
+        .trunk_prefix
+        .and_then(|prefix| digits.strip_prefix(prefix))
+        .filter(|national| region.national_lengths.contains(&national.len()))
+    {
+        return Ok(format!("+{}{}", region.calling_code, national));
+    }
+    // Customers often type the calling code without the "+".
+    if let Some(national) = digits
+        .strip_prefix(region.calling_code)
+        .filter(|national| region.national_lengths.contains(&national.len()))
+    {
+        return Ok(format!("+{}{}", region.calling_code, national));
+    }
+    Err(invalid())
+}
+
+fn normalize_international(digits: &str) -> Option<String> {
+    // Calling codes are prefix-free, so at most one region family matches.
+    match REGIONS.iter().find(|region| digits.starts_with(region.calling_code)) {
+        Some(region) => {
+            let national = &digits[region.calling_code.len()..];
+            // "+44 (0)20 ..." style numbers repeat the trunk prefix.
+            let national = match region.trunk_prefix.and_then(|prefix| national.strip_prefix(prefix)) {
+                Some(stripped) if !region.national_lengths.contains(&national.len()) => stripped,
+                _ => national,
+            };
+            region
+                .national_lengths
+                .contains(&national.len())
+                .then(|| format!("+{}{}", region.calling_code, national))
+        }
+        // Regions we have no table for: accept anything E.164 allows.
+        None => ((8..=15).contains(&digits.len()) && !digits.starts_with('0'))
+            .then(|| format!("+{}", digits)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_indian_spellings_normalize_to_one_number() {
+        for input in [
+            "9876543210",
+            "09876543210",
+            "919876543210",
+            "+91 98765 43210",
+            "+91-98765-43210",
+            "0091 9876543210",
+        ] {
+            assert_eq!(
+                normalize_phone_number(input, "IN"),
+                Ok("+919876543210".to_string()),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_other_regions() {
+        assert_eq!(normalize_phone_number("(415) 555-2671", "US"), Ok("+14155552671".to_string()));
+        assert_eq!(normalize_phone_number("020 7946 0018", "GB"), Ok("+442079460018".to_string()));
+        assert_eq!(normalize_phone_number("+44 (0)20 7946 0018", "IN"), Ok("+442079460018".to_string()));
+        assert_eq!(normalize_phone_number("+49 30 901820", "IN"), Ok("+4930901820".to_string()));
+    }
+
+    #[test]
+    fn test_invalid_numbers() {
+        assert!(normalize_phone_number("98765", "IN").is_err());
+        assert!(normalize_phone_number("98765abcde", "IN").is_err());
+        assert!(normalize_phone_number("+91 98765", "IN").is_err());
+        assert!(normalize_phone_number("", "IN").is_err());
+        assert!(normalize_phone_number("9876543210", "XX").is_err());
+    }
+}
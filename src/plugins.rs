@@ -0,0 +1,427 @@
+use crate::error::ApiError;
+use crate::rules::RuleContext;
+use crate::secrets::to_hex;
+use crate::strategy::{self, DiscountContext, DiscountStrategy};
+use crate::{audit, delete_data_from_redis, fetch_data_from_redis, persist_data_to_redis};
+use chrono::{Datelike, Utc};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, RwLock};
+use utoipa::ToSchema;
+use wasmi::{Config, Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+/// Policy `strategy` that runs the business's uploaded plugin.
+pub const PLUGIN_STRATEGY: &str = "wasm_plugin";
+
+pub const MAX_PLUGIN_SIZE: usize = 256 * 1024;
+/// Fuel for one bill, roughly one unit per instruction.
+const PLUGIN_FUEL: u64 = 1_000_000;
+const PLUGIN_MEMORY_LIMIT: usize = 4 * 1024 * 1024;
+
+/// Inputs are passed as this many little-endian `f64`s, in the order of
+/// `PluginInput::to_bytes`.
+const INPUT_VALUES: usize = 12;
+
+lazy_static! {
+    static ref ENGINE: Engine = {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        Engine::new(&config)
+    };
+    // Each business's compiled plugin with the SHA-256 of its bytes, so bills
+    // don't fetch, decode and recompile it. Uploads and deletions replace it.
+    static ref MODULES: RwLock<HashMap<String, (String, Arc<Module>)>> = RwLock::new(HashMap::new());
+}
+
+/// An uploaded plugin, stored under `plugin_info:<business>`. The module
+/// itself is kept base64 encoded alongside it under `plugin:<business>`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct PluginInfo {
+    pub sha256: String,
+    pub size: usize,
+    pub uploaded_at: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredPlugin {
+    #[serde(flatten)]
+    info: PluginInfo,
+    module: String,
+}
+
+/// What a plugin is called with, see the README for the ABI.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PluginInput {
+    pub bill_amount: f64,
+    pub visits_last_week: f64,
+    pub spend_last_week: f64,
+    pub visits_this_week: f64,
+    pub streak_weeks: f64,
+    pub in_previous_period: bool,
+    pub billed_today: bool,
+    pub pool_amount: f64,
+    pub previous_eligible_customers: f64,
+    pub current_eligible_customers: f64,
+    /// 1 for Monday to 7 for Sunday.
+    pub weekday: f64,
+    pub hour: f64,
+}
+
+impl PluginInput {
+    pub fn from_context(context: &DiscountContext) -> PluginInput {
+        let history = RuleContext::new(
+            context.customer_key,
+            context.bill_amount,
+            context.previous_period,
+            context.current_period,
+            context.streak_weeks,
+            context.now,
+            &[],
+        );
+        PluginInput {
+            bill_amount: context.bill_amount,
+            visits_last_week: history.visits_last_week,
+            spend_last_week: history.spend_last_week,
+            visits_this_week: history.visits_this_week,
+            streak_weeks: history.streak_weeks,
+            in_previous_period: context.previous_period.customer_expense_map.contains_key(context.customer_key),
+            billed_today: context.billed_today,
+            pool_amount: context.previous_period.total_pooled_amount,
+            previous_eligible_customers: context.previous_period.total_eligible_customers,
+            current_eligible_customers: context.current_period.total_eligible_customers,
+            weekday: context.now.weekday().number_from_monday() as f64,
+            hour: history.hour,
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let flag = |value: bool| if value { 1.0 } else { 0.0 };
+        let values: [f64; INPUT_VALUES] = [
+            self.bill_amount,
+            self.visits_last_week,
+            self.spend_last_week,
+            self.visits_this_week,
+            self.streak_weeks,
+            flag(self.in_previous_period),
+            flag(self.billed_today),
+            self.pool_amount,
+            self.previous_eligible_customers,
+            self.current_eligible_customers,
+            self.weekday,
+            self.hour,
+        ];
+        values.iter().flat_map(|value| value.to_le_bytes()).collect()
+    }
+}
+
+/// Compiles `wasm` and checks it has the exports of the ABI and no imports.
+pub fn compile(wasm: &[u8]) -> Result<Arc<Module>, String> {
+    if wasm.len() > MAX_PLUGIN_SIZE {
+        return Err(format!("Plugins are at most {} KiB", MAX_PLUGIN_SIZE / 1024));
+    }
+    let module = Module::new(&ENGINE, wasm).map_err(|error| format!("Invalid WebAssembly module: {}", error))?;
+    if let Some(import) = module.imports().next() {
+        return Err(format!(
+            "Plugins can't import anything, but this one imports {}.{}",
+            import.module(),
+            import.name()
+        ));
+    }
+    // Instantiating looks the exports up with their types.
+    Sandbox::new(&module)?;
+    Ok(Arc::new(module))
+}
+
+/// Runs the plugin on one bill. `Ok(None)` means the customer gets no share;
+/// a share is never more than the bill. Traps, running out of fuel or
+/// memory, and shares that are not finite numbers are errors.
+pub fn run(module: &Module, input: &PluginInput) -> Result<Option<f64>, String> {
+    let mut sandbox = Sandbox::new(module)?;
+    let bytes = input.to_bytes();
+    let pointer = sandbox
+        .alloc
+        .call(&mut sandbox.store, bytes.len() as i32)
+        .map_err(|error| format!("alloc failed: {}", error))?;
+    sandbox
+        .memory
+        .write(&mut sandbox.store, pointer as u32 as usize, &bytes)
+        .map_err(|_| "alloc returned memory out of bounds".to_string())?;
+    let share = sandbox
+        .discount_share
+        .call(&mut sandbox.store, pointer)
+        .map_err(|error| format!("discount_share failed: {}", error))?;
+    if !share.is_finite() {
+        return Err(format!("discount_share returned {}", share));
+    }
+    Ok((share >= 0.0).then_some(share.min(input.bill_amount)))
+}
+
+struct Sandbox {
+    store: Store<StoreLimits>,
+    memory: wasmi::Memory,
+    alloc: wasmi::TypedFunc<i32, i32>,
+    discount_share: wasmi::TypedFunc<i32, f64>,
+}
+
+impl Sandbox {
+    // A fresh instance per bill: plugins keep no state between bills.
+    fn new(module: &Module) -> Result<Sandbox, String> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(PLUGIN_MEMORY_LIMIT)
+            .instances(1)
+            .build();
+        let mut store = Store::new(&ENGINE, limits);
+        store.limiter(|limits| limits);
+        store.set_fuel(PLUGIN_FUEL).map_err(|error| error.to_string())?;
+        let instance = Linker::new(&ENGINE)
+            .instantiate(&mut store, module)
+            .and_then(|instance| instance.start(&mut store))
+            .map_err(|error| format!("Plugin failed to start: {}", error))?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or("Plugins must export their memory as \"memory\"")?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&store, "alloc")
+            .map_err(|_| "Plugins must export alloc(i32) -> i32")?;
+        let discount_share = instance
+            .get_typed_func::<i32, f64>(&store, "discount_share")
+            .map_err(|_| "Plugins must export discount_share(i32) -> f64")?;
+        Ok(Sandbox { store, memory, alloc, discount_share })
+    }
+}
+
+/// Runs a business's plugin for the share and falls back to the default
+/// strategy when it fails. Pool contributions always follow the default.
+pub struct PluginStrategy {
+    module: Arc<Module>,
+    fallback: Arc<dyn DiscountStrategy>,
+    // Eligibility and share come from one run per bill.
+    outcome: OnceLock<Result<Option<f64>, String>>,
+}
+
+impl PluginStrategy {
+    fn outcome(&self, context: &DiscountContext) -> &Result<Option<f64>, String> {
+        self.outcome.get_or_init(|| {
+            let outcome = run(&self.module, &PluginInput::from_context(context));
+            if let Err(error) = &outcome {
+                println!(
+                    "Plugin failed, using {} - Business: {}, Error: {}",
+                    strategy::DEFAULT_STRATEGY, context.business_name, error
+                );
+            }
+            outcome
+        })
+    }
+}
+
+impl DiscountStrategy for PluginStrategy {
+    fn is_eligible(&self, context: &DiscountContext) -> bool {
+        match self.outcome(context) {
+            Ok(share) => share.is_some(),
+            Err(_) => self.fallback.is_eligible(context),
+        }
+    }
+
+    fn share_value(&self, context: &DiscountContext) -> f64 {
+        match self.outcome(context) {
+            Ok(share) => share.unwrap_or(0.0),
+            Err(_) => self.fallback.share_value(context),
+        }
+    }
+
+    fn pool_contribution(&self, context: &DiscountContext, final_amount: f64) -> f64 {
+        self.fallback.pool_contribution(context, final_amount)
+    }
+}
+
+/// The strategy for a business whose policy names `strategy_name`: its
+/// plugin for `wasm_plugin`, otherwise a registered strategy. A business
+/// without a usable plugin gets the default.
+pub fn strategy_for(
+    business_name: &str,
+    strategy_name: &str,
+    conn: &mut redis::Connection,
+) -> Arc<dyn DiscountStrategy> {
+    let fallback = strategy::strategy_for(strategy::DEFAULT_STRATEGY);
+    if strategy_name != PLUGIN_STRATEGY {
+        return strategy::strategy_for(strategy_name);
+    }
+    let Some(info) = load_plugin_info(business_name, conn) else {
+        println!("No plugin uploaded, using {} - Business: {}", strategy::DEFAULT_STRATEGY, business_name);
+        return fallback;
+    };
+    match module_for(business_name, &info, conn) {
+        Ok(module) => Arc::new(PluginStrategy { module, fallback, outcome: OnceLock::new() }),
+        Err(error) => {
+            println!(
+                "Plugin unusable, using {} - Business: {}, Error: {}",
+                strategy::DEFAULT_STRATEGY, business_name, error
+            );
+            fallback
+        }
+    }
+}
+
+// The compiled module of `info`, from the cache while it is still the one
+// uploaded; otherwise it is read from the store and replaces the cached one.
+fn module_for(business_name: &str, info: &PluginInfo, conn: &mut redis::Connection) -> Result<Arc<Module>, String> {
+    if let Some((sha256, module)) = MODULES.read().unwrap().get(business_name) {
+        if *sha256 == info.sha256 {
+            return Ok(module.clone());
+        }
+    }
+    let stored = load_stored_plugin(business_name, conn).ok_or("Plugin module is missing")?;
+    let wasm = base64::decode(&stored.module).map_err(|error| error.to_string())?;
+    let module = compile(&wasm)?;
+    cache_module(business_name, Some((fingerprint(&wasm), module.clone())));
+    Ok(module)
+}
+
+fn cache_module(business_name: &str, module: Option<(String, Arc<Module>)>) {
+    let mut modules = MODULES.write().unwrap();
+    match module {
+        Some(module) => modules.insert(business_name.to_string(), module),
+        None => modules.remove(business_name),
+    };
+}
+
+/// Checks and stores a business's plugin, replacing any earlier one. It is
+/// only run for businesses whose policy `strategy` is `wasm_plugin`.
+pub fn upload_plugin(
+    business_name: &str,
+    wasm: &[u8],
+    actor: &str,
+    conn: &mut redis::Connection,
+) -> Result<PluginInfo, ApiError> {
+    let module = compile(wasm).map_err(ApiError::invalid_request)?;
+    let info = PluginInfo {
+        sha256: fingerprint(wasm),
+        size: wasm.len(),
+        uploaded_at: Utc::now().to_rfc3339(),
+    };
+    let stored = StoredPlugin { info: info.clone(), module: base64::encode(wasm) };
+    persist_data_to_redis(&plugin_redis_key(business_name), serde_json::to_string(&stored).unwrap(), conn);
+    persist_data_to_redis(&plugin_info_redis_key(business_name), serde_json::to_string(&info).unwrap(), conn);
+    cache_module(business_name, Some((info.sha256.clone(), module)));
+    audit::record_audit(
+        business_name,
+        actor,
+        "plugin_uploaded",
+        serde_json::json!({ "sha256": info.sha256, "size": info.size }),
+        conn,
+    );
+    Ok(info)
+}
+
+pub fn load_plugin_info(business_name: &str, conn: &mut redis::Connection) -> Option<PluginInfo> {
+    // Plugins uploaded before the info had its own key only have the module's.
+    serde_json::from_str(&fetch_data_from_redis(&plugin_info_redis_key(business_name), conn))
+        .ok()
+        .or_else(|| load_stored_plugin(business_name, conn).map(|stored| stored.info))
+}
+
+pub fn delete_plugin(business_name: &str, actor: &str, conn: &mut redis::Connection) -> Result<(), ApiError> {
+    let info = load_plugin_info(business_name, conn)
+        .ok_or_else(|| ApiError::not_found("No plugin uploaded"))?;
+    delete_data_from_redis(&plugin_redis_key(business_name), conn);
+    delete_data_from_redis(&plugin_info_redis_key(business_name), conn);
+    cache_module(business_name, None);
+    audit::record_audit(
+        business_name,
+        actor,
+        "plugin_deleted",
+        serde_json::json!({ "sha256": info.sha256 }),
+        conn,
+    );
+    Ok(())
+}
+
+fn load_stored_plugin(business_name: &str, conn: &mut redis::Connection) -> Option<StoredPlugin> {
+    serde_json::from_str(&fetch_data_from_redis(&plugin_redis_key(business_name), conn)).ok()
+}
+
+fn plugin_redis_key(business_name: &str) -> String {
+    format!("plugin:{}", business_name)
+}
+
+fn plugin_info_redis_key(business_name: &str) -> String {
+    format!("plugin_info:{}", business_name)
+}
+
+fn fingerprint(wasm: &[u8]) -> String {
+    to_hex(&Sha256::digest(wasm))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Pays 10% of the bill to customers with at least two visits last week.
+    const LOYAL_TENTH: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+          (func (export "discount_share") (param $input i32) (result f64)
+            (if (result f64) (f64.ge (f64.load offset=8 (local.get $input)) (f64.const 2))
+              (then (f64.mul (f64.load (local.get $input)) (f64.const 0.1)))
+              (else (f64.const -1)))))
+    "#;
+
+    const PAYS_TOO_MUCH: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "alloc") (param i32) (result i32) (i32.const 0))
+          (func (export "discount_share") (param i32) (result f64) (f64.const 1000000)))
+    "#;
+
+    const SPINS_FOREVER: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "alloc") (param i32) (result i32) (i32.const 0))
+          (func (export "discount_share") (param i32) (result f64)
+            (loop $forever (br $forever))
+            (f64.const 0)))
+    "#;
+
+    fn input(visits_last_week: f64) -> PluginInput {
+        PluginInput {
+            bill_amount: 400.0,
+            visits_last_week,
+            spend_last_week: 0.0,
+            visits_this_week: 0.0,
+            streak_weeks: 1.0,
+            in_previous_period: true,
+            billed_today: false,
+            pool_amount: 0.0,
+            previous_eligible_customers: 0.0,
+            current_eligible_customers: 0.0,
+            weekday: 1.0,
+            hour: 12.0,
+        }
+    }
+
+    #[test]
+    fn test_plugin_runs_in_a_sandbox() {
+        let module = compile(&wat::parse_str(LOYAL_TENTH).unwrap()).unwrap();
+        assert_eq!(run(&module, &input(2.0)), Ok(Some(40.0)));
+        assert_eq!(run(&module, &input(1.0)), Ok(None));
+
+        let module = compile(&wat::parse_str(PAYS_TOO_MUCH).unwrap()).unwrap();
+        assert_eq!(run(&module, &input(2.0)), Ok(Some(400.0)));
+
+        let module = compile(&wat::parse_str(SPINS_FOREVER).unwrap()).unwrap();
+        assert!(run(&module, &input(2.0)).unwrap_err().contains("discount_share failed"));
+    }
+
+    #[test]
+    fn test_compile_checks_the_abi() {
+        assert!(compile(b"not wasm").is_err());
+        let no_exports = wat::parse_str("(module (memory (export \"memory\") 1))").unwrap();
+        assert!(compile(&no_exports).unwrap_err().contains("alloc"));
+        let imports = wat::parse_str("(module (import \"env\" \"clock\" (func)))").unwrap();
+        assert!(compile(&imports).unwrap_err().contains("env.clock"));
+        assert!(compile(&vec![0; MAX_PLUGIN_SIZE + 1]).is_err());
+    }
+}
@@ -0,0 +1,257 @@
+use crate::phone::{self, DEFAULT_REGION};
+use crate::rules::{self, EligibilityRule};
+use crate::{fetch_data_from_redis, items, persist_data_to_redis, plugins, strategy};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+/// Share of every final bill that goes into the next week's pool, unless a
+/// business overrides it.
+pub const DEFAULT_POOL_PERCENTAGE: f64 = 0.03;
+
+/// Most pool shares a referral may be worth.
+pub const MAX_REFERRAL_POOL_SHARES: f64 = 10.0;
+
+/// Most flat credit, in the bill's currency, a referral may be worth.
+pub const MAX_REFERRAL_CREDIT: f64 = 10_000.0;
+
+/// Per-business settings that owners may change. Stored as JSON under
+/// `policy:<business>`; missing fields fall back to the defaults so older
+/// records keep loading.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+#[serde(default)]
+pub struct BusinessPolicy {
+    pub pool_percentage: f64,
+    /// Region whose numbering plan applies to phone numbers entered without
+    /// a country code, as an ISO 3166-1 alpha-2 code.
+    pub default_region: String,
+    /// Multipliers on the pool share of customers billed in several
+    /// consecutive weeks. Empty, the default, pays everyone the same share.
+    pub streak_bonuses: Vec<StreakBonus>,
+    /// What both sides of a referral get. Referrals are off while unset.
+    pub referral_bonus: Option<ReferralBonus>,
+    pub wallet: WalletPolicy,
+    /// Pool contribution rates for line item categories (lower case), e.g.
+    /// `{"alcohol": 0.0}`. Other categories use `pool_percentage`.
+    pub category_rates: HashMap<String, f64>,
+    pub pool_scope: PoolScope,
+    pub fraud: FraudPolicy,
+    /// Registered `DiscountStrategy` that decides eligibility, shares and
+    /// contributions. `equal_split`, the default, splits last week's pool
+    /// equally; `wasm_plugin` runs the business's uploaded plugin.
+    pub strategy: String,
+    /// Conditions a customer must meet, all of them, to get a pool share.
+    pub eligibility_rules: Vec<EligibilityRule>,
+    /// Dates as `17-Oct-2026`, for rules using `is_holiday`.
+    pub holidays: Vec<String>,
+    /// Lets customer tokens bill themselves from the chat widget. Off by
+    /// default: only cashiers and terminals bill.
+    pub customer_self_billing: bool,
+}
+
+/// Whether the outlets of a business share one pool.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PoolScope {
+    /// One pool for the whole chain.
+    #[default]
+    Chain,
+    /// A pool per outlet. Bills without an outlet use the chain pool.
+    Outlet,
+}
+
+/// Whether customers may bank their pool shares instead of having them
+/// taken off the next bill.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, ToSchema)]
+#[serde(default)]
+pub struct WalletPolicy {
+    pub enabled: bool,
+    /// Days a banked share can be redeemed for; forever when unset.
+    pub expiry_days: Option<u32>,
+}
+
+/// Rules run on every bill. Each is off while unset.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, ToSchema)]
+#[serde(default)]
+pub struct FraudPolicy {
+    /// Bills for one customer within an hour.
+    pub customer_bills_per_hour: Option<FraudRule>,
+    /// Bills by one cashier token or terminal within an hour.
+    pub cashier_bills_per_hour: Option<FraudRule>,
+    /// Customers never billed before, entered by one cashier token or
+    /// terminal within an hour.
+    pub new_customers_per_cashier_per_hour: Option<FraudRule>,
+    /// Pool share granted on a bill over the bill amount, e.g. 0.5.
+    pub discount_ratio: Option<FraudRule>,
+}
+
+/// Trips when what a rule measures goes above `limit`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct FraudRule {
+    pub limit: f64,
+    pub action: FraudAction,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FraudAction {
+    /// Bill as usual and queue the bill for the owner to review.
+    Flag,
+    /// Refuse the bill.
+    Block,
+}
+
+/// Customers on a streak of at least `min_weeks` weeks, counting the
+/// current one, get `multiplier` times their pool share.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct StreakBonus {
+    pub min_weeks: u32,
+    pub multiplier: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct ReferralBonus {
+    pub kind: ReferralBonusKind,
+    /// Number of extra pool shares, or the credit in the bill's currency.
+    pub amount: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReferralBonusKind {
+    /// Extra shares of the weekly pool on the next bill.
+    PoolShare,
+    /// A flat amount off the next bills.
+    FlatCredit,
+}
+
+impl Default for BusinessPolicy {
+    fn default() -> BusinessPolicy {
+        BusinessPolicy {
+            pool_percentage: DEFAULT_POOL_PERCENTAGE,
+            default_region: DEFAULT_REGION.to_string(),
+            streak_bonuses: Vec::new(),
+            referral_bonus: None,
+            wallet: WalletPolicy::default(),
+            category_rates: HashMap::new(),
+            pool_scope: PoolScope::Chain,
+            fraud: FraudPolicy::default(),
+            strategy: strategy::DEFAULT_STRATEGY.to_string(),
+            eligibility_rules: Vec::new(),
+            holidays: Vec::new(),
+            customer_self_billing: false,
+        }
+    }
+}
+
+impl BusinessPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.pool_percentage) {
+            return Err("pool_percentage must be between 0 and 1".to_string());
+        }
+        if phone::region(&self.default_region).is_none() {
+            return Err(format!("Unsupported default_region: {}", self.default_region));
+        }
+        for bonus in &self.streak_bonuses {
+            if bonus.min_weeks < 2 {
+                return Err("streak_bonuses min_weeks must be at least 2".to_string());
+            }
+            if !(1.0..=10.0).contains(&bonus.multiplier) {
+                return Err("streak_bonuses multiplier must be between 1 and 10".to_string());
+            }
+        }
+        if let Some(bonus) = &self.referral_bonus {
+            let (max_amount, unit) = match bonus.kind {
+                ReferralBonusKind::PoolShare => (MAX_REFERRAL_POOL_SHARES, "pool shares"),
+                ReferralBonusKind::FlatCredit => (MAX_REFERRAL_CREDIT, "in credit"),
+            };
+            if !(bonus.amount > 0.0 && bonus.amount <= max_amount) {
+                return Err(format!(
+                    "referral_bonus amount must be positive, and at most {} {}",
+                    max_amount, unit
+                ));
+            }
+        }
+        for (category, rate) in &self.category_rates {
+            if category.is_empty() || *category != items::normalize_category(category) {
+                return Err(format!("category_rates keys must be lower case: {:?}", category));
+            }
+            if !(0.0..=1.0).contains(rate) {
+                return Err(format!("The rate for {} must be between 0 and 1", category));
+            }
+        }
+        if self.wallet.expiry_days == Some(0) {
+            return Err("wallet expiry_days must be at least 1".to_string());
+        }
+        let fraud_rules = [
+            &self.fraud.customer_bills_per_hour,
+            &self.fraud.cashier_bills_per_hour,
+            &self.fraud.new_customers_per_cashier_per_hour,
+            &self.fraud.discount_ratio,
+        ];
+        if fraud_rules.into_iter().flatten().any(|rule| !(rule.limit.is_finite() && rule.limit > 0.0)) {
+            return Err("fraud rule limits must be positive".to_string());
+        }
+        if !strategy::is_registered(&self.strategy) && self.strategy != plugins::PLUGIN_STRATEGY {
+            return Err(format!("Unknown strategy: {}", self.strategy));
+        }
+        rules::validate_rules(&self.eligibility_rules)?;
+        if let Some(holiday) = self
+            .holidays
+            .iter()
+            .find(|holiday| NaiveDate::parse_from_str(holiday, "%d-%b-%Y").is_err())
+        {
+            return Err(format!("holidays are dates like 17-Oct-2026, not {:?}", holiday));
+        }
+        Ok(())
+    }
+}
+
+pub fn load_policy(business_name: &str, conn: &mut redis::Connection) -> BusinessPolicy {
+    let data = fetch_data_from_redis(&policy_redis_key(business_name), conn);
+    if data.is_empty() {
+        BusinessPolicy::default()
+    } else {
+        serde_json::from_str(&data).unwrap_or_default()
+    }
+}
+
+pub fn save_policy(
+    business_name: &str,
+    policy: &BusinessPolicy,
+    conn: &mut redis::Connection,
+) -> Result<(), String> {
+    policy.validate()?;
+    persist_data_to_redis(
+        &policy_redis_key(business_name),
+        serde_json::to_string(policy).unwrap(),
+        conn,
+    );
+    Ok(())
+}
+
+fn policy_redis_key(business_name: &str) -> String {
+    format!("policy:{}", business_name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_referral_bonus_limits() {
+        let with_bonus = |kind, amount| BusinessPolicy {
+            referral_bonus: Some(ReferralBonus { kind, amount }),
+            ..BusinessPolicy::default()
+        };
+        assert!(with_bonus(ReferralBonusKind::PoolShare, 10.0).validate().is_ok());
+        assert!(with_bonus(ReferralBonusKind::FlatCredit, 500.0).validate().is_ok());
+
+        let shares = with_bonus(ReferralBonusKind::PoolShare, 11.0).validate().unwrap_err();
+        assert!(shares.contains("at most 10 pool shares"), "{}", shares);
+        let credit = with_bonus(ReferralBonusKind::FlatCredit, 1e12).validate().unwrap_err();
+        assert!(credit.contains("at most 10000 in credit"), "{}", credit);
+        assert!(with_bonus(ReferralBonusKind::FlatCredit, 0.0).validate().is_err());
+    }
+}
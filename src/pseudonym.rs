@@ -0,0 +1,114 @@
+use crate::secrets::to_hex;
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use sha2::Sha256;
+use std::sync::{Arc, RwLock};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Pseudonyms look like `ph_<last 4 digits>_<hash>`. The digits are kept so
+/// receipts and reports can show a masked number.
+pub const PSEUDONYM_PREFIX: &str = "ph_";
+const VISIBLE_DIGITS: usize = 4;
+
+lazy_static! {
+    static ref PSEUDONYMIZER: RwLock<Option<Arc<Pseudonymizer>>> = RwLock::new(None);
+}
+
+/// Replaces phone numbers with a keyed hash. The pepper lives outside Redis
+/// (in `PHONE_PEPPER`), so a copy of the database alone can't be reversed by
+/// hashing every possible number.
+pub struct Pseudonymizer {
+    pepper: Vec<u8>,
+}
+
+impl Pseudonymizer {
+    pub fn new(pepper: Vec<u8>) -> Result<Pseudonymizer, String> {
+        if pepper.len() < 32 {
+            return Err("The phone pepper must be at least 32 bytes".to_string());
+        }
+        Ok(Pseudonymizer { pepper })
+    }
+
+    /// The stored form of the E.164 number `phone_number`.
+    pub fn pseudonym(&self, phone_number: &str) -> String {
+        let mut mac = HmacSha256::new_from_slice(&self.pepper).expect("HMAC accepts keys of any length");
+        mac.update(phone_number.as_bytes());
+        let hash = mac.finalize().into_bytes();
+        format!("{}{}_{}", PSEUDONYM_PREFIX, last_digits(phone_number), to_hex(&hash[..16]))
+    }
+}
+
+/// Switches phone storage to pseudonyms. Data stored before is rewritten by
+/// `migrations::normalize_stored_phone_numbers`.
+pub fn install_pseudonymizer(pseudonymizer: Pseudonymizer) {
+    *PSEUDONYMIZER.write().unwrap() = Some(Arc::new(pseudonymizer));
+}
+
+pub fn installed_pseudonymizer() -> Option<Arc<Pseudonymizer>> {
+    PSEUDONYMIZER.read().unwrap().clone()
+}
+
+/// What to store for the E.164 number `phone_number`: its pseudonym when a
+/// pepper is installed, the number itself otherwise.
+pub fn phone_key(phone_number: &str) -> String {
+    match installed_pseudonymizer() {
+        Some(pseudonymizer) => pseudonymizer.pseudonym(phone_number),
+        None => phone_number.to_string(),
+    }
+}
+
+pub fn is_pseudonym(key: &str) -> bool {
+    key.starts_with(PSEUDONYM_PREFIX)
+}
+
+/// How to show a stored customer key: `******3210` for a pseudonym, the key
+/// itself otherwise.
+pub fn display(key: &str) -> String {
+    match key.strip_prefix(PSEUDONYM_PREFIX) {
+        Some(rest) => format!("******{}", rest.split('_').next().unwrap_or_default()),
+        None => key.to_string(),
+    }
+}
+
+/// `phone_number` with all but its last digits hidden, e.g. `******3210`,
+/// for log lines.
+pub fn mask_phone_number(phone_number: &str) -> String {
+    format!("******{}", last_digits(phone_number))
+}
+
+fn last_digits(phone_number: &str) -> String {
+    let digits: Vec<char> = phone_number.chars().filter(|c| c.is_ascii_digit()).collect();
+    digits[digits.len().saturating_sub(VISIBLE_DIGITS)..].iter().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PEPPER_A: &[u8] = b"0123456789abcdef0123456789abcdef";
+    const PEPPER_B: &[u8] = b"fedcba9876543210fedcba9876543210";
+
+    #[test]
+    fn test_pseudonym_is_stable_and_keyed() {
+        let a = Pseudonymizer::new(PEPPER_A.to_vec()).unwrap();
+        let b = Pseudonymizer::new(PEPPER_B.to_vec()).unwrap();
+        let pseudonym = a.pseudonym("+919876543210");
+        assert!(is_pseudonym(&pseudonym));
+        assert!(pseudonym.starts_with("ph_3210_"));
+        assert!(!pseudonym.contains("9876543210"));
+        assert_eq!(pseudonym, a.pseudonym("+919876543210"));
+        assert_ne!(pseudonym, a.pseudonym("+919876543211"));
+        assert_ne!(pseudonym, b.pseudonym("+919876543210"));
+        assert!(Pseudonymizer::new(b"short".to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_display_masks_phones() {
+        let pseudonym = Pseudonymizer::new(PEPPER_A.to_vec()).unwrap().pseudonym("+919876543210");
+        assert_eq!(display(&pseudonym), "******3210");
+        assert_eq!(display("+919876543210"), "+919876543210");
+        assert_eq!(display("card:LC12345678"), "card:LC12345678");
+        assert_eq!(mask_phone_number("+919876543210"), "******3210");
+    }
+}
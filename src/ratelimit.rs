@@ -0,0 +1,150 @@
+use crate::error::ApiError;
+use crate::{fetch_data_from_redis, increment_counter};
+use chrono::Utc;
+
+/// At most `requests` requests in any `window_secs` seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub requests: u32,
+    pub window_secs: u64,
+}
+
+impl RateLimit {
+    /// Reads `<requests>/<seconds>`, e.g. `60/60`. `off` disables the limit.
+    pub fn parse(value: &str) -> Result<Option<RateLimit>, String> {
+        let value = value.trim();
+        if value.eq_ignore_ascii_case("off") {
+            return Ok(None);
+        }
+        let invalid = || format!("Rate limits look like 60/60 or off, not {:?}", value);
+        let (requests, window_secs) = value.split_once('/').ok_or_else(invalid)?;
+        let requests: u32 = requests.trim().parse().map_err(|_| invalid())?;
+        let window_secs: u64 = window_secs.trim().parse().map_err(|_| invalid())?;
+        if requests == 0 || window_secs == 0 {
+            return Err(invalid());
+        }
+        Ok(Some(RateLimit { requests, window_secs }))
+    }
+}
+
+/// Limits on the public endpoints, counted in Redis so that they hold
+/// across server instances. Each endpoint has its own counters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimits {
+    pub per_ip: Option<RateLimit>,
+    pub per_token: Option<RateLimit>,
+    pub per_business: Option<RateLimit>,
+}
+
+impl Default for RateLimits {
+    fn default() -> RateLimits {
+        RateLimits {
+            per_ip: Some(RateLimit { requests: 60, window_secs: 60 }),
+            per_token: Some(RateLimit { requests: 30, window_secs: 60 }),
+            per_business: Some(RateLimit { requests: 600, window_secs: 60 }),
+        }
+    }
+}
+
+/// Who a request is counted against. Missing parts are not limited.
+#[derive(Debug, Default)]
+pub struct RateLimitSubject<'a> {
+    pub ip: Option<&'a str>,
+    /// A token id from `auth::token_id`, never the token itself.
+    pub token_id: Option<&'a str>,
+    pub business_name: Option<&'a str>,
+}
+
+impl RateLimits {
+    /// Counts one request to `endpoint` and refuses it with a 429 once any
+    /// of the subject's limits is used up.
+    pub fn check(
+        &self,
+        endpoint: &str,
+        subject: &RateLimitSubject,
+        conn: &mut redis::Connection,
+    ) -> Result<(), ApiError> {
+        let checks = [
+            ("ip", subject.ip, self.per_ip),
+            ("token", subject.token_id, self.per_token),
+            ("business", subject.business_name, self.per_business),
+        ];
+        for (kind, value, limit) in checks {
+            let (Some(value), Some(limit)) = (value, limit) else {
+                continue;
+            };
+            let counter = format!("ratelimit:{}:{}:{}", endpoint, kind, value);
+            if let Some(retry_after_secs) = count_request(&counter, limit, Utc::now().timestamp() as u64, conn) {
+                println!(
+                    "Rate limited - Endpoint: {}, By: {}, Retry after: {}s",
+                    endpoint, kind, retry_after_secs
+                );
+                return Err(ApiError::rate_limited(
+                    format!("Too many requests per {}. Try again later.", kind),
+                    retry_after_secs,
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Sliding window counter: one fixed-window count per window, with the
+// previous window's count weighted by how much of it the sliding window
+// still covers. Returns the seconds to wait when over the limit.
+fn count_request(counter: &str, limit: RateLimit, now: u64, conn: &mut redis::Connection) -> Option<u64> {
+    let window = now / limit.window_secs;
+    let (current, _) = increment_counter(&format!("{}:{}", counter, window), limit.window_secs * 2, conn);
+    let previous = fetch_data_from_redis(&format!("{}:{}", counter, window.wrapping_sub(1)), conn)
+        .parse()
+        .unwrap_or(0);
+    retry_after(limit, previous, current.max(0) as u64, now % limit.window_secs)
+}
+
+fn retry_after(limit: RateLimit, previous: u64, current: u64, elapsed: u64) -> Option<u64> {
+    let window = limit.window_secs as f64;
+    let covered = 1.0 - elapsed as f64 / window;
+    let limit = limit.requests as f64;
+    let current = current as f64;
+    if previous as f64 * covered + current <= limit {
+        return None;
+    }
+    let wait = if current > limit {
+        // This window alone is over: wait for the next one, until the part
+        // of this one still covered falls to the limit.
+        window - elapsed as f64 + window * (1.0 - limit / current)
+    } else {
+        // Wait for enough of the previous window to slide out.
+        let needed = 1.0 - (limit - current) / previous as f64;
+        (needed - (1.0 - covered)) * window
+    };
+    // Less a rounding error, so exact waits aren't rounded up a second.
+    Some((wait - 1e-9).ceil().max(1.0) as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_rate_limit() {
+        assert_eq!(
+            RateLimit::parse(" 30/60 "),
+            Ok(Some(RateLimit { requests: 30, window_secs: 60 }))
+        );
+        assert_eq!(RateLimit::parse("OFF"), Ok(None));
+        assert!(RateLimit::parse("30").is_err());
+        assert!(RateLimit::parse("0/60").is_err());
+    }
+
+    #[test]
+    fn test_sliding_window_retry_after() {
+        let limit = RateLimit { requests: 10, window_secs: 60 };
+        // 10 in the last window, a quarter of the way into this one: 7.5 + 2.
+        assert_eq!(retry_after(limit, 10, 2, 15), None);
+        // 7.5 + 3 is over; 3 more seconds bring it back to 10.
+        assert_eq!(retry_after(limit, 10, 3, 15), Some(3));
+        // 11 already this window: wait 45s for the next, then 5.5s more.
+        assert_eq!(retry_after(limit, 0, 11, 15), Some(51));
+    }
+}
@@ -0,0 +1,270 @@
+use crate::error::{ApiError, ErrorCode};
+use crate::policy::{ReferralBonus, ReferralBonusKind};
+use crate::{
+    customer, delete_data_from_redis, fetch_data_from_redis, increment_counter, list_redis_keys,
+    persist_data_to_redis, streaks,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Referral codes one client address, terminal or device may redeem per
+/// window, whatever numbers are typed into it.
+pub const DEVICE_REDEMPTION_LIMIT: i64 = 2;
+pub const DEVICE_REDEMPTION_WINDOW_SECS: u64 = 30 * 24 * 60 * 60;
+
+/// A redeemed referral, stored under `referred_by:<business>:<referee>`.
+/// A customer can only ever be referred once.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct Referral {
+    pub code: String,
+    pub referrer: String,
+    pub referee: String,
+    pub device_id: Option<String>,
+    pub timestamp: String,
+}
+
+/// Referral bonuses not paid out yet, stored under
+/// `referral_reward:<business>:<customer>`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct PendingReward {
+    /// Extra shares of the weekly pool, paid on the next bill that has a
+    /// pool to share.
+    pub pool_shares: f64,
+    /// Flat amount taken off the next bills until used up.
+    pub credit: f64,
+}
+
+impl PendingReward {
+    pub fn is_empty(&self) -> bool {
+        self.pool_shares <= 0.0 && self.credit <= 0.0
+    }
+
+    /// Pays out what it can on a bill with `payable` left to discount, given
+    /// the value of one pool share this week. Returns the amount paid; what
+    /// is left stays pending.
+    pub fn redeem(&mut self, share_value: f64, payable: f64) -> f64 {
+        let mut paid = 0.0;
+        if self.pool_shares > 0.0 && share_value > 0.0 {
+            paid += (self.pool_shares * share_value).min(payable);
+            self.pool_shares = 0.0;
+        }
+        let credit = self.credit.min((payable - paid).max(0.0));
+        self.credit -= credit;
+        paid + credit
+    }
+}
+
+/// The referral code of `customer_key`, created on first request. Only
+/// customers who have been billed before may refer others.
+pub fn referral_code(
+    business_name: &str,
+    customer_key: &str,
+    conn: &mut redis::Connection,
+) -> Result<String, ApiError> {
+    let existing = fetch_data_from_redis(&code_of_redis_key(business_name, customer_key), conn);
+    if !existing.is_empty() {
+        return Ok(existing);
+    }
+    if !has_been_billed(business_name, customer_key, conn) {
+        return Err(ApiError::new(
+            ErrorCode::Forbidden,
+            "Only existing customers can refer others",
+        ));
+    }
+    let code = loop {
+        let code = Uuid::new_v4().simple().to_string()[..8].to_uppercase();
+        if store_if_absent(&code_redis_key(business_name, &code), customer_key.to_string(), conn) {
+            break code;
+        }
+    };
+    persist_data_to_redis(&code_of_redis_key(business_name, customer_key), code.clone(), conn);
+    println!(
+        "Referral code issued - Business: {}, Customer: {}",
+        business_name, customer_key
+    );
+    Ok(code)
+}
+
+/// Redeems `code` for `referee_key` on their first bill and credits `bonus`
+/// to both sides. Refuses self-referrals, customers who were billed or
+/// referred before, and sources or devices that have redeemed too many codes.
+/// `source` is what the server saw the bill come from; `device_id` is sent
+/// by the client, so it only adds a limit.
+pub fn redeem_referral(
+    business_name: &str,
+    code: &str,
+    referee_key: &str,
+    source: Option<&str>,
+    device_id: Option<&str>,
+    bonus: &ReferralBonus,
+    conn: &mut redis::Connection,
+) -> Result<Referral, ApiError> {
+    let code = code.trim().to_uppercase();
+    let referrer = fetch_data_from_redis(&code_redis_key(business_name, &code), conn);
+    if referrer.is_empty() {
+        return Err(ApiError::invalid_request("Unknown referral code"));
+    }
+    // The code is stored under the referrer's profile, so any identifier
+    // linked to it resolves to the same key.
+    if referrer == referee_key {
+        return Err(ApiError::new(ErrorCode::Forbidden, "Customers can't refer themselves"));
+    }
+    if has_been_billed(business_name, referee_key, conn) {
+        return Err(ApiError::new(
+            ErrorCode::Conflict,
+            "Referral codes can only be used on a first bill",
+        ));
+    }
+    let counters = [
+        source.map(|source| format!("referral_source:{}:{}", business_name, source)),
+        device_id.map(|device_id| format!("referral_device:{}:{}", business_name, device_id)),
+    ];
+    for counter in counters.into_iter().flatten() {
+        let (redemptions, window_left) = increment_counter(&counter, DEVICE_REDEMPTION_WINDOW_SECS, conn);
+        if redemptions > DEVICE_REDEMPTION_LIMIT {
+            println!(
+                "Referral blocked - Business: {}, Counter: {}, Redemptions: {}",
+                business_name, counter, redemptions
+            );
+            return Err(ApiError::rate_limited(
+                "Too many referral codes redeemed from this device",
+                window_left.max(1) as u64,
+            ));
+        }
+    }
+
+    let referral = Referral {
+        code,
+        referrer: referrer.clone(),
+        referee: referee_key.to_string(),
+        device_id: device_id.map(str::to_string),
+        timestamp: Utc::now().to_rfc3339(),
+    };
+    if !store_if_absent(
+        &referred_by_redis_key(business_name, referee_key),
+        serde_json::to_string(&referral).unwrap(),
+        conn,
+    ) {
+        return Err(ApiError::new(ErrorCode::Conflict, "Customer was already referred"));
+    }
+    for customer_key in [referrer.as_str(), referee_key] {
+        let mut reward = load_pending_reward(business_name, customer_key, conn);
+        match bonus.kind {
+            ReferralBonusKind::PoolShare => reward.pool_shares += bonus.amount,
+            ReferralBonusKind::FlatCredit => reward.credit += bonus.amount,
+        }
+        save_pending_reward(business_name, customer_key, &reward, conn);
+    }
+    println!(
+        "Referral redeemed - Business: {}, Referrer: {}, Referee: {}",
+        business_name, referrer, referee_key
+    );
+    Ok(referral)
+}
+
+/// Points the referral codes of `from` at `into` when the two customers are
+/// merged, so links already shared keep crediting the person. `into` takes
+/// over the code of `from` if it has none of its own, and the record of who
+/// referred `from` if it wasn't referred itself. Returns the codes moved.
+pub fn merge_referrals(
+    business_name: &str,
+    from: &str,
+    into: &str,
+    conn: &mut redis::Connection,
+) -> usize {
+    let mut moved = 0;
+    for key in list_redis_keys(&code_redis_key(business_name, "*"), conn) {
+        if fetch_data_from_redis(&key, conn) == from {
+            persist_data_to_redis(&key, into.to_string(), conn);
+            moved += 1;
+        }
+    }
+
+    let from_code = fetch_data_from_redis(&code_of_redis_key(business_name, from), conn);
+    if !from_code.is_empty() {
+        store_if_absent(&code_of_redis_key(business_name, into), from_code, conn);
+        delete_data_from_redis(&code_of_redis_key(business_name, from), conn);
+    }
+    let referred_by = fetch_data_from_redis(&referred_by_redis_key(business_name, from), conn);
+    if !referred_by.is_empty() {
+        store_if_absent(&referred_by_redis_key(business_name, into), referred_by, conn);
+        delete_data_from_redis(&referred_by_redis_key(business_name, from), conn);
+    }
+    moved
+}
+
+pub fn load_pending_reward(
+    business_name: &str,
+    customer_key: &str,
+    conn: &mut redis::Connection,
+) -> PendingReward {
+    serde_json::from_str(&fetch_data_from_redis(&reward_redis_key(business_name, customer_key), conn))
+        .unwrap_or_default()
+}
+
+pub fn save_pending_reward(
+    business_name: &str,
+    customer_key: &str,
+    reward: &PendingReward,
+    conn: &mut redis::Connection,
+) {
+    persist_data_to_redis(
+        &reward_redis_key(business_name, customer_key),
+        serde_json::to_string(reward).unwrap(),
+        conn,
+    );
+}
+
+// Like `persist_data_to_redis_if_absent`, without an expiry.
+fn store_if_absent(redis_key: &str, value: String, conn: &mut redis::Connection) -> bool {
+    let stored: Option<String> = redis::cmd("SET")
+        .arg(redis_key)
+        .arg(value)
+        .arg("NX")
+        .query(conn)
+        .unwrap_or(None);
+    stored.is_some()
+}
+
+// Streaks are kept for every bill; the weekly blobs cover customers billed
+// before streaks were tracked.
+fn has_been_billed(business_name: &str, customer_key: &str, conn: &mut redis::Connection) -> bool {
+    streaks::load_streak(business_name, customer_key, conn).is_some()
+        || customer::has_recent_history(business_name, customer_key, conn)
+}
+
+fn code_redis_key(business_name: &str, code: &str) -> String {
+    format!("referral_code:{}:{}", business_name, code)
+}
+
+fn code_of_redis_key(business_name: &str, customer_key: &str) -> String {
+    format!("referral_code_of:{}:{}", business_name, customer_key)
+}
+
+fn referred_by_redis_key(business_name: &str, customer_key: &str) -> String {
+    format!("referred_by:{}:{}", business_name, customer_key)
+}
+
+fn reward_redis_key(business_name: &str, customer_key: &str) -> String {
+    format!("referral_reward:{}:{}", business_name, customer_key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pending_reward_redeem() {
+        let mut reward = PendingReward { pool_shares: 1.0, credit: 50.0 };
+        // No pool this week: the share waits, the credit is paid.
+        assert_eq!(reward.redeem(0.0, 30.0), 30.0);
+        assert_eq!(reward, PendingReward { pool_shares: 1.0, credit: 20.0 });
+        assert_eq!(reward.redeem(15.0, 100.0), 35.0);
+        assert!(reward.is_empty());
+        // Never more than the bill.
+        let mut reward = PendingReward { pool_shares: 2.0, credit: 0.0 };
+        assert_eq!(reward.redeem(40.0, 60.0), 60.0);
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{fetch_data_from_redis, get_latest_monday, outlets, parse_customer_discount_details, transactions};
+use chrono::{Datelike, Duration, NaiveDate, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct PeriodSummary {
+    pub period_start: String,
+    pub total_pooled_amount: f64,
+    pub total_eligible_customers: f64,
+    pub total_discount_given: f64,
+    pub customers: usize,
+    pub transactions: usize,
+    /// Billing by outlet, from the transaction log. Bills made without an
+    /// outlet are listed with no `outlet_id`.
+    pub outlets: Vec<OutletActivity>,
+}
+
+#[derive(Serialize, Debug, PartialEq, ToSchema)]
+pub struct OutletActivity {
+    pub outlet_id: Option<String>,
+    pub name: Option<String>,
+    pub transactions: usize,
+    pub bill_amount: f64,
+    /// Put into the pool. Older log entries don't record it.
+    pub pool_contribution: f64,
+    /// Taken off bills: pool shares, referral bonuses and wallet redemptions.
+    pub discount_given: f64,
+}
+
+/// What an owner sees for their business: the week in progress and the week
+/// whose pool is being paid out now. Totals cover the chain-wide pool and
+/// every outlet pool.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct BusinessReport {
+    pub business_name: String,
+    pub current_week: PeriodSummary,
+    pub previous_week: PeriodSummary,
+}
+
+pub fn business_report(business_name: &str, conn: &mut redis::Connection) -> BusinessReport {
+    let current_monday = get_latest_monday(Utc::now().iso_week().week());
+    BusinessReport {
+        business_name: business_name.to_string(),
+        current_week: period_summary(business_name, current_monday, conn),
+        previous_week: period_summary(business_name, current_monday - Duration::days(7), conn),
+    }
+}
+
+pub fn period_summary(
+    business_name: &str,
+    monday: NaiveDate,
+    conn: &mut redis::Connection,
+) -> PeriodSummary {
+    let mut summary = PeriodSummary {
+        period_start: monday.format("%d-%b-%Y").to_string(),
+        total_pooled_amount: 0.0,
+        total_eligible_customers: 0.0,
+        total_discount_given: 0.0,
+        customers: 0,
+        transactions: 0,
+        outlets: outlet_activity(business_name, monday, conn),
+    };
+    for redis_key in outlets::pool_redis_keys(business_name, monday, conn) {
+        let details = parse_customer_discount_details(&fetch_data_from_redis(&redis_key, conn));
+        summary.total_pooled_amount += details.total_pooled_amount;
+        summary.total_eligible_customers += details.total_eligible_customers;
+        summary.total_discount_given += details.total_discount_given;
+        summary.customers += details.customer_expense_map.len();
+        summary.transactions += details
+            .customer_expense_map
+            .values()
+            .flat_map(|days| days.values())
+            .map(|amounts| amounts.split(',').filter(|a| !a.is_empty()).count())
+            .sum::<usize>();
+    }
+    summary
+}
+
+fn outlet_activity(business_name: &str, monday: NaiveDate, conn: &mut redis::Connection) -> Vec<OutletActivity> {
+    let known = outlets::load_outlets(business_name, conn);
+    let mut activity: Vec<OutletActivity> = Vec::new();
+    for day in 0..7 {
+        for record in transactions::load_transactions(business_name, monday + Duration::days(day), conn) {
+            let position = match activity.iter().position(|entry| entry.outlet_id == record.outlet_id) {
+                Some(position) => position,
+                None => {
+                    let name = known
+                        .iter()
+                        .find(|outlet| Some(&outlet.outlet_id) == record.outlet_id.as_ref())
+                        .map(|outlet| outlet.name.clone());
+                    activity.push(OutletActivity {
+                        outlet_id: record.outlet_id.clone(),
+                        name,
+                        transactions: 0,
+                        bill_amount: 0.0,
+                        pool_contribution: 0.0,
+                        discount_given: 0.0,
+                    });
+                    activity.len() - 1
+                }
+            };
+            let entry = &mut activity[position];
+            entry.transactions += 1;
+            entry.bill_amount += record.bill_amount;
+            entry.pool_contribution += record.pool_contribution.unwrap_or(0.0);
+            entry.discount_given += record.discount;
+        }
+    }
+    activity.sort_by(|a, b| a.outlet_id.cmp(&b.outlet_id));
+    activity
+}
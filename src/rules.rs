@@ -0,0 +1,712 @@
+use crate::CustomerDiscountDetails;
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use utoipa::ToSchema;
+
+const MAX_RULES: usize = 20;
+const MAX_CONDITION_LENGTH: usize = 500;
+const MAX_NESTING: usize = 32;
+
+/// A condition a customer must meet to get a pool share, e.g.
+/// `visits_last_week >= 2 and bill_amount >= 300`. See `VARIABLES` for what
+/// conditions can refer to.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct EligibilityRule {
+    pub name: String,
+    pub condition: String,
+}
+
+/// What rules are evaluated against.
+#[derive(Serialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct RuleContext {
+    pub bill_amount: f64,
+    /// Days the customer was billed on in the pool's previous period.
+    pub visits_last_week: f64,
+    /// What the customer spent in the previous period, after discounts.
+    pub spend_last_week: f64,
+    /// Days the customer was billed on this period, before this bill.
+    pub visits_this_week: f64,
+    pub streak_weeks: f64,
+    /// `mon` to `sun`, in UTC like all server dates.
+    pub weekday: String,
+    /// 0 to 23, in UTC.
+    pub hour: f64,
+    /// Today is one of the policy's `holidays`.
+    pub is_holiday: bool,
+}
+
+impl RuleContext {
+    pub fn new(
+        customer_key: &str,
+        bill_amount: f64,
+        previous_period: &CustomerDiscountDetails,
+        current_period: &CustomerDiscountDetails,
+        streak_weeks: u32,
+        now: DateTime<Utc>,
+        holidays: &[String],
+    ) -> RuleContext {
+        let previous = previous_period.customer_expense_map.get(customer_key);
+        let current = current_period.customer_expense_map.get(customer_key);
+        let today = now.format("%d-%b-%Y").to_string();
+        RuleContext {
+            bill_amount,
+            visits_last_week: previous.map_or(0, |days| days.len()) as f64,
+            spend_last_week: previous
+                .into_iter()
+                .flat_map(|days| days.values())
+                .flat_map(|amounts| amounts.split(','))
+                .filter_map(|amount| amount.trim().parse::<f64>().ok())
+                // Not `sum`, which gives -0 for customers with no bills.
+                .fold(0.0, |total, amount| total + amount),
+            visits_this_week: current.map_or(0, |days| days.len()) as f64,
+            streak_weeks: streak_weeks as f64,
+            weekday: now.weekday().to_string().to_lowercase(),
+            hour: now.hour() as f64,
+            is_holiday: holidays.iter().any(|holiday| holiday.eq_ignore_ascii_case(&today)),
+        }
+    }
+
+    fn value_of(&self, variable: &str) -> Value {
+        match variable {
+            "bill_amount" => Value::Number(self.bill_amount),
+            "visits_last_week" => Value::Number(self.visits_last_week),
+            "spend_last_week" => Value::Number(self.spend_last_week),
+            "visits_this_week" => Value::Number(self.visits_this_week),
+            "streak_weeks" => Value::Number(self.streak_weeks),
+            "weekday" => Value::Text(self.weekday.clone()),
+            "hour" => Value::Number(self.hour),
+            "is_holiday" => Value::Bool(self.is_holiday),
+            // Conditions are type checked against VARIABLES before they run.
+            _ => unreachable!("unknown variable {}", variable),
+        }
+    }
+}
+
+/// The variables conditions can use, with their types.
+pub const VARIABLES: &[(&str, Type)] = &[
+    ("bill_amount", Type::Number),
+    ("visits_last_week", Type::Number),
+    ("spend_last_week", Type::Number),
+    ("visits_this_week", Type::Number),
+    ("streak_weeks", Type::Number),
+    ("weekday", Type::Text),
+    ("hour", Type::Number),
+    ("is_holiday", Type::Bool),
+];
+
+/// Name of the trace step for the strategy's own eligibility decision.
+/// Rules can't use it.
+pub const STRATEGY_STEP: &str = "strategy";
+
+/// How every rule of a business went for one bill.
+#[derive(Serialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct RuleTrace {
+    /// The strategy, when there was one, and all rules passed.
+    pub passed: bool,
+    pub context: RuleContext,
+    pub rules: Vec<RuleOutcome>,
+}
+
+impl RuleTrace {
+    pub fn failed_rules(&self) -> Vec<String> {
+        self.rules
+            .iter()
+            .filter(|rule| !rule.passed)
+            .map(|rule| rule.name.clone())
+            .collect()
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct RuleOutcome {
+    pub name: String,
+    pub condition: String,
+    pub passed: bool,
+    /// Each comparison that was evaluated, with the values it saw, e.g.
+    /// `visits_last_week >= 2: 1 >= 2 is false`.
+    pub steps: Vec<String>,
+}
+
+/// Checks a business's rules when its policy is saved.
+pub fn validate_rules(rules: &[EligibilityRule]) -> Result<(), String> {
+    if rules.len() > MAX_RULES {
+        return Err(format!("At most {} eligibility rules", MAX_RULES));
+    }
+    for (index, rule) in rules.iter().enumerate() {
+        if rule.name.trim().is_empty() || rule.name.len() > 64 {
+            return Err("Eligibility rule names must be 1-64 characters".to_string());
+        }
+        if rule.name == STRATEGY_STEP {
+            return Err(format!("Eligibility rules can't be named {}", STRATEGY_STEP));
+        }
+        if rules[..index].iter().any(|other| other.name == rule.name) {
+            return Err(format!("Duplicate eligibility rule: {}", rule.name));
+        }
+        parse_condition(&rule.condition).map_err(|error| format!("Rule {}: {}", rule.name, error))?;
+    }
+    Ok(())
+}
+
+/// Evaluates every rule, so the trace shows all that failed and not only
+/// the first.
+pub fn evaluate(rules: &[EligibilityRule], context: RuleContext) -> RuleTrace {
+    let rules: Vec<RuleOutcome> = rules
+        .iter()
+        .map(|rule| {
+            let mut steps = Vec::new();
+            // Saved rules were validated; one that no longer parses fails.
+            let passed = match parse_condition(&rule.condition) {
+                Ok(condition) => condition.eval(&context, &mut steps) == Value::Bool(true),
+                Err(error) => {
+                    steps.push(format!("invalid condition: {}", error));
+                    false
+                }
+            };
+            RuleOutcome {
+                name: rule.name.clone(),
+                condition: rule.condition.clone(),
+                passed,
+                steps,
+            }
+        })
+        .collect();
+    RuleTrace {
+        passed: rules.iter().all(|rule| rule.passed),
+        context,
+        rules,
+    }
+}
+
+/// `evaluate`, after the discount strategy named `strategy` has decided
+/// whether the bill gets a share. Its decision comes first in the trace, as
+/// the `strategy` step.
+pub fn evaluate_with_strategy(
+    strategy: &str,
+    strategy_eligible: bool,
+    rules: &[EligibilityRule],
+    context: RuleContext,
+) -> RuleTrace {
+    let mut trace = evaluate(rules, context);
+    let decision = if strategy_eligible { "grants a share" } else { "grants no share" };
+    trace.rules.insert(
+        0,
+        RuleOutcome {
+            name: STRATEGY_STEP.to_string(),
+            condition: strategy.to_string(),
+            passed: strategy_eligible,
+            steps: vec![format!("{} {}", strategy, decision)],
+        },
+    );
+    trace.passed &= strategy_eligible;
+    trace
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Number,
+    Text,
+    Bool,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Type::Number => "a number",
+            Type::Text => "text",
+            Type::Bool => "true or false",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Number(f64),
+    Text(String),
+    Bool(bool),
+}
+
+impl Value {
+    fn kind(&self) -> Type {
+        match self {
+            Value::Number(_) => Type::Number,
+            Value::Text(_) => Type::Text,
+            Value::Bool(_) => Type::Bool,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number(number) => write!(f, "{}", number),
+            Value::Text(text) => write!(f, "{:?}", text),
+            Value::Bool(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    fn holds(self, left: &Value, right: &Value) -> bool {
+        match (self, left, right) {
+            (Comparison::Equal, _, _) => left == right,
+            (Comparison::NotEqual, _, _) => left != right,
+            (Comparison::Less, Value::Number(left), Value::Number(right)) => left < right,
+            (Comparison::LessOrEqual, Value::Number(left), Value::Number(right)) => left <= right,
+            (Comparison::Greater, Value::Number(left), Value::Number(right)) => left > right,
+            (Comparison::GreaterOrEqual, Value::Number(left), Value::Number(right)) => left >= right,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Literal(Value),
+    Variable(String),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(Box<Expr>, Comparison, Box<Expr>),
+    In(Box<Expr>, Vec<Value>),
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Literal(value) => write!(f, "{}", value),
+            Expr::Variable(name) => f.write_str(name),
+            Expr::Not(inner) => write!(f, "not {}", inner),
+            Expr::And(left, right) => write!(f, "({} and {})", left, right),
+            Expr::Or(left, right) => write!(f, "({} or {})", left, right),
+            Expr::Compare(left, comparison, right) => write!(f, "{} {} {}", left, comparison, right),
+            Expr::In(left, values) => {
+                let values: Vec<String> = values.iter().map(Value::to_string).collect();
+                write!(f, "{} in [{}]", left, values.join(", "))
+            }
+        }
+    }
+}
+
+impl Expr {
+    fn type_check(&self) -> Result<Type, String> {
+        match self {
+            Expr::Literal(value) => Ok(value.kind()),
+            Expr::Variable(name) => VARIABLES
+                .iter()
+                .find(|(variable, _)| variable == name)
+                .map(|(_, kind)| *kind)
+                .ok_or_else(|| format!("Unknown variable: {}", name)),
+            Expr::Not(inner) => {
+                expect_type(inner, Type::Bool, "not")?;
+                Ok(Type::Bool)
+            }
+            Expr::And(left, right) | Expr::Or(left, right) => {
+                expect_type(left, Type::Bool, "and/or")?;
+                expect_type(right, Type::Bool, "and/or")?;
+                Ok(Type::Bool)
+            }
+            Expr::Compare(left, comparison, right) => {
+                let kind = left.type_check()?;
+                expect_type(right, kind, &comparison.to_string())?;
+                let ordered = !matches!(comparison, Comparison::Equal | Comparison::NotEqual);
+                if ordered && kind != Type::Number {
+                    return Err(format!("{} compares numbers, not {}", comparison, kind));
+                }
+                Ok(Type::Bool)
+            }
+            Expr::In(left, values) => {
+                let kind = left.type_check()?;
+                if let Some(value) = values.iter().find(|value| value.kind() != kind) {
+                    return Err(format!("{} in a list of {} values", value, kind));
+                }
+                Ok(Type::Bool)
+            }
+        }
+    }
+
+    fn eval(&self, context: &RuleContext, steps: &mut Vec<String>) -> Value {
+        match self {
+            Expr::Literal(value) => value.clone(),
+            Expr::Variable(name) => {
+                let value = context.value_of(name);
+                if let Value::Bool(_) = value {
+                    steps.push(format!("{} is {}", name, value));
+                }
+                value
+            }
+            Expr::Not(inner) => Value::Bool(inner.eval(context, steps) != Value::Bool(true)),
+            Expr::And(left, right) => Value::Bool(
+                left.eval(context, steps) == Value::Bool(true) && right.eval(context, steps) == Value::Bool(true),
+            ),
+            Expr::Or(left, right) => Value::Bool(
+                left.eval(context, steps) == Value::Bool(true) || right.eval(context, steps) == Value::Bool(true),
+            ),
+            Expr::Compare(left, comparison, right) => {
+                let left_value = left.eval(context, steps);
+                let right_value = right.eval(context, steps);
+                let holds = comparison.holds(&left_value, &right_value);
+                steps.push(format!(
+                    "{}: {} {} {} is {}",
+                    self, left_value, comparison, right_value, holds
+                ));
+                Value::Bool(holds)
+            }
+            Expr::In(left, values) => {
+                let left_value = left.eval(context, steps);
+                let holds = values.contains(&left_value);
+                steps.push(format!("{}: {} is {}", self, left_value, if holds { "listed" } else { "not listed" }));
+                Value::Bool(holds)
+            }
+        }
+    }
+}
+
+fn expect_type(expr: &Expr, expected: Type, operator: &str) -> Result<(), String> {
+    let kind = expr.type_check()?;
+    if kind != expected {
+        return Err(format!("{} needs {}, but {} is {}", operator, expected, expr, kind));
+    }
+    Ok(())
+}
+
+/// Parses and type checks a condition, which must be true or false.
+fn parse_condition(condition: &str) -> Result<Expr, String> {
+    if condition.len() > MAX_CONDITION_LENGTH {
+        return Err(format!("Conditions are at most {} characters", MAX_CONDITION_LENGTH));
+    }
+    let tokens = tokenize(condition)?;
+    let mut parser = Parser { tokens, position: 0, depth: 0 };
+    let expr = parser.or()?;
+    if let Some(token) = parser.tokens.get(parser.position) {
+        return Err(format!("Unexpected {}", token));
+    }
+    if expr.type_check()? != Type::Bool {
+        return Err("The condition must be true or false, e.g. bill_amount >= 300".to_string());
+    }
+    Ok(expr)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Text(String),
+    Word(String),
+    Comparison(Comparison),
+    Open,
+    Close,
+    OpenList,
+    CloseList,
+    Comma,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(number) => write!(f, "{}", number),
+            Token::Text(text) => write!(f, "{:?}", text),
+            Token::Word(word) => f.write_str(word),
+            Token::Comparison(comparison) => write!(f, "{}", comparison),
+            Token::Open => f.write_str("("),
+            Token::Close => f.write_str(")"),
+            Token::OpenList => f.write_str("["),
+            Token::CloseList => f.write_str("]"),
+            Token::Comma => f.write_str(","),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() {
+            let mut number = String::new();
+            while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit() || **c == '.') {
+                number.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Number(number.parse().map_err(|_| format!("Invalid number: {}", number))?));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek().filter(|c| c.is_ascii_alphanumeric() || **c == '_') {
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Word(word));
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some(end) if end == c => break,
+                    Some(other) => text.push(other),
+                    None => return Err("Unterminated text".to_string()),
+                }
+            }
+            tokens.push(Token::Text(text));
+        } else {
+            chars.next();
+            let followed_by_equals = chars.next_if_eq(&'=').is_some();
+            tokens.push(match (c, followed_by_equals) {
+                ('=', true) => Token::Comparison(Comparison::Equal),
+                ('!', true) => Token::Comparison(Comparison::NotEqual),
+                ('<', true) => Token::Comparison(Comparison::LessOrEqual),
+                ('>', true) => Token::Comparison(Comparison::GreaterOrEqual),
+                ('<', false) => Token::Comparison(Comparison::Less),
+                ('>', false) => Token::Comparison(Comparison::Greater),
+                ('(', false) => Token::Open,
+                (')', false) => Token::Close,
+                ('[', false) => Token::OpenList,
+                (']', false) => Token::CloseList,
+                (',', false) => Token::Comma,
+                ('=', false) => return Err("Use == to compare".to_string()),
+                _ => return Err(format!("Unexpected character: {}", c)),
+            });
+        }
+    }
+    Ok(tokens)
+}
+
+// or := and ("or" and)*; and := not ("and" not)*; not := "not" not | comparison;
+// comparison := atom (op atom | "in" list)?; atom := literal | variable | "(" or ")"
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn next_is_word(&mut self, word: &str) -> bool {
+        let found = matches!(self.tokens.get(self.position), Some(Token::Word(next)) if next == word);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.next_is_word("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.not()?;
+        while self.next_is_word("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        if self.next_is_word("not") {
+            self.nest(|parser| parser.not().map(|inner| Expr::Not(Box::new(inner))))
+        } else {
+            self.comparison()
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let left = self.atom()?;
+        if let Some(Token::Comparison(comparison)) = self.tokens.get(self.position).cloned() {
+            self.position += 1;
+            return Ok(Expr::Compare(Box::new(left), comparison, Box::new(self.atom()?)));
+        }
+        if self.next_is_word("in") {
+            return Ok(Expr::In(Box::new(left), self.list()?));
+        }
+        Ok(left)
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Open) => {
+                let expr = self.nest(Parser::or)?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err("Missing )".to_string()),
+                }
+            }
+            Some(Token::Word(word)) => Ok(match word.as_str() {
+                "true" => Expr::Literal(Value::Bool(true)),
+                "false" => Expr::Literal(Value::Bool(false)),
+                "and" | "or" | "not" | "in" => return Err(format!("Unexpected {}", word)),
+                _ => Expr::Variable(word),
+            }),
+            Some(Token::Number(number)) => Ok(Expr::Literal(Value::Number(number))),
+            Some(Token::Text(text)) => Ok(Expr::Literal(Value::Text(text))),
+            Some(token) => Err(format!("Unexpected {}", token)),
+            None => Err("The condition ends too early".to_string()),
+        }
+    }
+
+    fn list(&mut self) -> Result<Vec<Value>, String> {
+        if self.next() != Some(Token::OpenList) {
+            return Err("in needs a list, e.g. weekday in [\"sat\", \"sun\"]".to_string());
+        }
+        let mut values = Vec::new();
+        loop {
+            match self.next() {
+                Some(Token::CloseList) if values.is_empty() => return Ok(values),
+                Some(Token::Number(number)) => values.push(Value::Number(number)),
+                Some(Token::Text(text)) => values.push(Value::Text(text)),
+                Some(Token::Word(word)) if word == "true" || word == "false" => {
+                    values.push(Value::Bool(word == "true"))
+                }
+                _ => return Err("Lists hold numbers, text, true or false".to_string()),
+            }
+            match self.next() {
+                Some(Token::Comma) => continue,
+                Some(Token::CloseList) => return Ok(values),
+                _ => return Err("Missing ]".to_string()),
+            }
+        }
+    }
+
+    fn nest(&mut self, parse: impl FnOnce(&mut Parser) -> Result<Expr, String>) -> Result<Expr, String> {
+        self.depth += 1;
+        if self.depth > MAX_NESTING {
+            return Err("The condition is nested too deeply".to_string());
+        }
+        let expr = parse(self);
+        self.depth -= 1;
+        expr
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn rule(name: &str, condition: &str) -> EligibilityRule {
+        EligibilityRule { name: name.to_string(), condition: condition.to_string() }
+    }
+
+    fn context(visits_last_week: f64, bill_amount: f64) -> RuleContext {
+        RuleContext {
+            bill_amount,
+            visits_last_week,
+            spend_last_week: 0.0,
+            visits_this_week: 0.0,
+            streak_weeks: 1.0,
+            weekday: "sat".to_string(),
+            hour: 18.0,
+            is_holiday: false,
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse_condition("visits_last_week >= 2 and (bill_amount >= 300 or not is_holiday)").is_ok());
+        assert!(parse_condition("weekday in ['sat', 'sun']").is_ok());
+        assert_eq!(
+            parse_condition("bill_amount").unwrap_err(),
+            "The condition must be true or false, e.g. bill_amount >= 300"
+        );
+        assert_eq!(parse_condition("visits >= 2").unwrap_err(), "Unknown variable: visits");
+        assert!(parse_condition("weekday > 'mon'").unwrap_err().contains("compares numbers"));
+        assert!(parse_condition("bill_amount == 'high'").is_err());
+        assert!(parse_condition("hour in [1, 'two']").is_err());
+        assert!(parse_condition("bill_amount = 300").is_err());
+        assert!(parse_condition("bill_amount >= 300 )").is_err());
+        assert!(parse_condition("(bill_amount >= 300").is_err());
+        assert!(parse_condition(&format!("{}is_holiday", "not ".repeat(40))).is_err());
+        assert!(validate_rules(&[rule("a", "is_holiday"), rule("a", "not is_holiday")]).is_err());
+    }
+
+    #[test]
+    fn test_evaluation_trace() {
+        let rules = [
+            rule("regulars", "visits_last_week >= 2 and bill_amount >= 300"),
+            rule("no holidays", "not is_holiday"),
+        ];
+        let trace = evaluate(&rules, context(1.0, 500.0));
+        assert!(!trace.passed);
+        assert_eq!(trace.failed_rules(), vec!["regulars"]);
+        // `and` stops at the first false comparison.
+        assert_eq!(trace.rules[0].steps, vec!["visits_last_week >= 2: 1 >= 2 is false"]);
+        assert_eq!(trace.rules[1].steps, vec!["is_holiday is false"]);
+
+        let trace = evaluate(&rules, context(3.0, 300.0));
+        assert!(trace.passed);
+        assert_eq!(trace.rules[0].steps.len(), 2);
+        let trace = evaluate(&[rule("weekends", "weekday in [\"sat\", \"sun\"]")], context(0.0, 0.0));
+        assert_eq!(trace.rules[0].steps, vec!["weekday in [\"sat\", \"sun\"]: \"sat\" is listed"]);
+        assert!(evaluate(&[], context(0.0, 0.0)).passed);
+
+        let trace = evaluate_with_strategy("equal_split", false, &rules, context(3.0, 300.0));
+        assert!(!trace.passed);
+        assert_eq!(trace.failed_rules(), vec![STRATEGY_STEP]);
+        assert_eq!(trace.rules[0].steps, vec!["equal_split grants no share"]);
+        assert!(validate_rules(&[rule(STRATEGY_STEP, "is_holiday")]).is_err());
+    }
+
+    #[test]
+    fn test_context_from_periods() {
+        let previous_period = CustomerDiscountDetails {
+            customer_expense_map: [(
+                "+919876543210".to_string(),
+                [
+                    ("05-Oct-2026".to_string(), "100,50.5".to_string()),
+                    ("07-Oct-2026".to_string(), "200".to_string()),
+                ]
+                .into(),
+            )]
+            .into(),
+            ..CustomerDiscountDetails::default()
+        };
+        let now = Utc.with_ymd_and_hms(2026, 10, 17, 9, 30, 0).unwrap();
+        let context = RuleContext::new(
+            "+919876543210",
+            300.0,
+            &previous_period,
+            &CustomerDiscountDetails::default(),
+            2,
+            now,
+            &["17-oct-2026".to_string()],
+        );
+        assert_eq!(context.visits_last_week, 2.0);
+        assert_eq!(context.spend_last_week, 350.5);
+        assert_eq!(context.visits_this_week, 0.0);
+        let newcomer = RuleContext::new("+919812345678", 300.0, &previous_period, &previous_period, 1, now, &[]);
+        assert!(newcomer.spend_last_week.is_sign_positive());
+        assert_eq!(context.weekday, "sat");
+        assert_eq!(context.hour, 9.0);
+        assert!(context.is_holiday);
+    }
+}
@@ -0,0 +1,322 @@
+use crate::auth::Role;
+use crate::{fetch_data_from_redis, pseudonym};
+use chrono::{Duration, Utc};
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Signed tokens look like `v1.<key id>.<claims>.<signature>`, with claims
+/// and signature base64url-encoded. UUID tokens never contain a dot.
+pub const SIGNED_TOKEN_PREFIX: &str = "v1.";
+/// Sorted set of revoked token ids, scored by the token's expiry.
+static REVOKED_TOKENS_REDIS_KEY: &str = "revoked_token_ids";
+/// JSON map of token id to expiry, written by earlier versions. Still read
+/// until the tokens on it have expired.
+static LEGACY_REVOKED_TOKENS_REDIS_KEY: &str = "revoked_tokens";
+/// How long the revocation list is trusted before it is read again.
+const REVOCATION_REFRESH_SECS: i64 = 30;
+
+lazy_static! {
+    static ref SIGNER: RwLock<Option<Arc<TokenSigner>>> = RwLock::new(None);
+    static ref REVOCATIONS: RwLock<RevocationCache> = RwLock::new(RevocationCache::default());
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TokenClaims {
+    pub business_name: String,
+    /// Stored key of the verified phone (`pseudonym::phone_key`), so the
+    /// number is only readable from the token when no pepper is set.
+    #[serde(alias = "phone_number")]
+    pub customer_key: String,
+    pub role: Role,
+    /// Expiry as a Unix timestamp.
+    pub exp: i64,
+    /// Unique id, used to revoke a single token.
+    pub jti: String,
+}
+
+pub struct SigningKey {
+    pub kid: String,
+    pub secret: Vec<u8>,
+}
+
+/// Signs with the first key and verifies with any of them, so keys can be
+/// rotated by prepending a new one and dropping the old one once every token
+/// it signed has expired.
+pub struct TokenSigner {
+    keys: Vec<SigningKey>,
+}
+
+impl TokenSigner {
+    pub fn new(keys: Vec<SigningKey>) -> Result<TokenSigner, String> {
+        if keys.is_empty() {
+            return Err("At least one signing key is required".to_string());
+        }
+        if keys.iter().any(|key| key.kid.is_empty() || key.kid.contains('.')) {
+            return Err("Key ids must be non-empty and must not contain '.'".to_string());
+        }
+        if keys.iter().any(|key| key.secret.len() < 32) {
+            return Err("Signing secrets must be at least 32 bytes".to_string());
+        }
+        Ok(TokenSigner { keys })
+    }
+
+    /// Parses `kid:secret,kid:secret`, as found in `TOKEN_SIGNING_KEYS`.
+    pub fn from_config(value: &str) -> Result<TokenSigner, String> {
+        let keys = value
+            .split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| match entry.trim().split_once(':') {
+                Some((kid, secret)) => Ok(SigningKey {
+                    kid: kid.to_string(),
+                    secret: secret.as_bytes().to_vec(),
+                }),
+                None => Err(format!("Signing key '{}' is not in kid:secret form", entry)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        TokenSigner::new(keys)
+    }
+
+    pub fn sign(&self, claims: &TokenClaims) -> String {
+        let key = &self.keys[0];
+        let payload = base64::encode_config(
+            serde_json::to_vec(claims).unwrap(),
+            base64::URL_SAFE_NO_PAD,
+        );
+        let signed_part = format!("{}{}.{}", SIGNED_TOKEN_PREFIX, key.kid, payload);
+        let signature = base64::encode_config(
+            mac(&key.secret, &signed_part).finalize().into_bytes(),
+            base64::URL_SAFE_NO_PAD,
+        );
+        format!("{}.{}", signed_part, signature)
+    }
+
+    /// Checks the signature and expiry. Revocation is checked separately.
+    pub fn verify(&self, token: &str, now: i64) -> Result<TokenClaims, String> {
+        let rest = token
+            .strip_prefix(SIGNED_TOKEN_PREFIX)
+            .ok_or("Not a signed token")?;
+        let (kid, payload, signature) = match rest.split('.').collect::<Vec<_>>().as_slice() {
+            [kid, payload, signature] => (*kid, *payload, *signature),
+            _ => return Err("Malformed signed token".to_string()),
+        };
+        let key = self
+            .keys
+            .iter()
+            .find(|key| key.kid == kid)
+            .ok_or("Unknown signing key")?;
+        let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD)
+            .map_err(|_| "Malformed token signature")?;
+        let signed_part = format!("{}{}.{}", SIGNED_TOKEN_PREFIX, kid, payload);
+        mac(&key.secret, &signed_part)
+            .verify_slice(&signature)
+            .map_err(|_| "Invalid token signature")?;
+
+        let claims: TokenClaims = base64::decode_config(payload, base64::URL_SAFE_NO_PAD)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or("Malformed token claims")?;
+        if claims.exp < now {
+            return Err("Token expired".to_string());
+        }
+        Ok(claims)
+    }
+}
+
+fn mac(secret: &[u8], data: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(data.as_bytes());
+    mac
+}
+
+/// Switches token issuance to signed tokens. UUID tokens issued before keep
+/// validating against Redis until they expire.
+pub fn install_signer(signer: TokenSigner) {
+    *SIGNER.write().unwrap() = Some(Arc::new(signer));
+}
+
+pub fn installed_signer() -> Option<Arc<TokenSigner>> {
+    SIGNER.read().unwrap().clone()
+}
+
+pub fn is_signed_token(token: &str) -> bool {
+    token.starts_with(SIGNED_TOKEN_PREFIX)
+}
+
+pub fn issue_signed_token(
+    signer: &TokenSigner,
+    phone_number: &str,
+    business_name: &str,
+    role: Role,
+) -> String {
+    signer.sign(&TokenClaims {
+        business_name: business_name.to_string(),
+        customer_key: pseudonym::phone_key(phone_number),
+        role,
+        exp: (Utc::now() + Duration::days(7)).timestamp(),
+        jti: Uuid::new_v4().simple().to_string(),
+    })
+}
+
+/// Verifies a signed token for `business_name`. Redis is only read when the
+/// cached revocation list is older than `REVOCATION_REFRESH_SECS`.
+pub fn validate_signed_token(
+    token: &str,
+    business_name: &str,
+    conn: &mut redis::Connection,
+) -> Result<TokenClaims, String> {
+    let signer = installed_signer().ok_or("Signed tokens are not enabled")?;
+    let now = Utc::now().timestamp();
+    let claims = signer.verify(token, now)?;
+    if claims.business_name != business_name {
+        return Err("Token issued for another business".to_string());
+    }
+    if is_revoked(&claims.jti, now, conn) {
+        return Err("Token revoked".to_string());
+    }
+    Ok(claims)
+}
+
+#[derive(Default)]
+struct RevocationCache {
+    revoked: HashSet<String>,
+    loaded_at: Option<i64>,
+}
+
+pub(crate) fn is_revoked(jti: &str, now: i64, conn: &mut redis::Connection) -> bool {
+    let stale = REVOCATIONS
+        .read()
+        .unwrap()
+        .loaded_at
+        .is_none_or(|loaded_at| now - loaded_at >= REVOCATION_REFRESH_SECS);
+    if stale {
+        let revoked = load_revocations(now, conn);
+        *REVOCATIONS.write().unwrap() = RevocationCache {
+            revoked,
+            loaded_at: Some(now),
+        };
+    }
+    REVOCATIONS.read().unwrap().revoked.contains(jti)
+}
+
+/// Ids of revoked tokens that haven't expired yet. Entries are dropped once
+/// the token would have expired anyway, so the set stays small.
+fn load_revocations(now: i64, conn: &mut redis::Connection) -> HashSet<String> {
+    let _: () = redis::cmd("ZREMRANGEBYSCORE")
+        .arg(REVOKED_TOKENS_REDIS_KEY)
+        .arg("-inf")
+        .arg(format!("({}", now))
+        .query(conn)
+        .unwrap_or(());
+    let mut revoked: HashSet<String> = redis::cmd("ZRANGEBYSCORE")
+        .arg(REVOKED_TOKENS_REDIS_KEY)
+        .arg(now)
+        .arg("+inf")
+        .query(conn)
+        .unwrap_or_default();
+    let legacy: HashMap<String, i64> =
+        serde_json::from_str(&fetch_data_from_redis(LEGACY_REVOKED_TOKENS_REDIS_KEY, conn))
+            .unwrap_or_default();
+    revoked.extend(legacy.into_iter().filter(|(_, exp)| *exp >= now).map(|(jti, _)| jti));
+    revoked
+}
+
+/// Adds the token to the revocation set. Each revocation is its own member,
+/// so concurrent revocations don't overwrite each other.
+pub fn revoke_signed_token(claims: &TokenClaims, conn: &mut redis::Connection) {
+    let _: () = redis::cmd("ZADD")
+        .arg(REVOKED_TOKENS_REDIS_KEY)
+        .arg(claims.exp)
+        .arg(&claims.jti)
+        .query(conn)
+        .unwrap_or(());
+    let now = Utc::now().timestamp();
+    let revoked = load_revocations(now, conn);
+    let mut cache = REVOCATIONS.write().unwrap();
+    cache.revoked = revoked;
+    cache.loaded_at = Some(now);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn signer(keys: &[(&str, &str)]) -> TokenSigner {
+        TokenSigner::new(
+            keys.iter()
+                .map(|(kid, secret)| SigningKey {
+                    kid: kid.to_string(),
+                    secret: secret.as_bytes().to_vec(),
+                })
+                .collect(),
+        )
+        .unwrap()
+    }
+
+    fn claims(exp: i64) -> TokenClaims {
+        TokenClaims {
+            business_name: "test102".to_string(),
+            customer_key: "+919876543210".to_string(),
+            role: Role::Cashier,
+            exp,
+            jti: "abc".to_string(),
+        }
+    }
+
+    const SECRET_A: &str = "0123456789abcdef0123456789abcdef";
+    const SECRET_B: &str = "fedcba9876543210fedcba9876543210";
+
+    #[test]
+    fn test_sign_and_verify() {
+        let signer = signer(&[("k1", SECRET_A)]);
+        let token = signer.sign(&claims(2_000));
+        assert!(is_signed_token(&token));
+        assert_eq!(signer.verify(&token, 1_000), Ok(claims(2_000)));
+        assert_eq!(signer.verify(&token, 3_000), Err("Token expired".to_string()));
+    }
+
+    #[test]
+    fn test_tampered_token_is_rejected() {
+        let signer = signer(&[("k1", SECRET_A)]);
+        let token = signer.sign(&claims(2_000));
+        let mut forged = claims(2_000);
+        forged.role = Role::Admin;
+        let forged_payload =
+            base64::encode_config(serde_json::to_vec(&forged).unwrap(), base64::URL_SAFE_NO_PAD);
+        let parts: Vec<&str> = token.split('.').collect();
+        let tampered = format!("{}.{}.{}.{}", parts[0], parts[1], forged_payload, parts[3]);
+        assert_eq!(
+            signer.verify(&tampered, 1_000),
+            Err("Invalid token signature".to_string())
+        );
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let old = signer(&[("k1", SECRET_A)]);
+        let rotated = signer(&[("k2", SECRET_B), ("k1", SECRET_A)]);
+        let old_token = old.sign(&claims(2_000));
+        let new_token = rotated.sign(&claims(2_000));
+        assert!(new_token.starts_with("v1.k2."));
+        assert!(rotated.verify(&old_token, 1_000).is_ok());
+        assert_eq!(
+            old.verify(&new_token, 1_000),
+            Err("Unknown signing key".to_string())
+        );
+    }
+
+    #[test]
+    fn test_from_config() {
+        let signer = TokenSigner::from_config(&format!("k2:{},k1:{}", SECRET_B, SECRET_A)).unwrap();
+        assert!(signer.sign(&claims(2_000)).starts_with("v1.k2."));
+        assert!(TokenSigner::from_config("k1:short").is_err());
+        assert!(TokenSigner::from_config("no-separator").is_err());
+        assert!(TokenSigner::from_config("").is_err());
+    }
+}
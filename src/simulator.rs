@@ -0,0 +1,436 @@
+use crate::{coalitions, fetch_data_from_redis, outlets, parse_customer_discount_details, policy, transactions};
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// One bill from the store, as the simulator replays it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoricalBill {
+    pub customer_id: String,
+    pub date: NaiveDate,
+    /// The bill before any discount. Weeks without a transaction log only
+    /// have the amounts paid after discount, which are used instead.
+    pub amount: f64,
+    /// Pool the bill paid into and out of, named as in period keys: the
+    /// business, one of its outlets or a coalition.
+    pub pool: String,
+}
+
+/// How a period's pool is shared among the customers eligible for it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DistributionMode {
+    /// The same share for everyone, as the live engine pays: the pool over
+    /// the customers eligible for it plus those billed in the period so far.
+    #[default]
+    Equal,
+    /// Shares in proportion to what each customer spent in the period
+    /// that filled the pool.
+    ProRata,
+}
+
+/// An alternative policy to replay history through.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct SimulationPolicy {
+    pub pool_percentage: f64,
+    pub distribution: DistributionMode,
+    /// Most taken off one bill.
+    pub max_discount: Option<f64>,
+    /// Most taken off one bill, as a fraction of it.
+    pub max_discount_percentage: Option<f64>,
+    pub period_days: u32,
+    /// Spend in a period a customer needs to share the next period's pool.
+    pub min_eligible_spend: f64,
+}
+
+impl Default for SimulationPolicy {
+    fn default() -> SimulationPolicy {
+        SimulationPolicy {
+            pool_percentage: crate::policy::DEFAULT_POOL_PERCENTAGE,
+            distribution: DistributionMode::Equal,
+            max_discount: None,
+            max_discount_percentage: None,
+            period_days: 7,
+            min_eligible_spend: 0.0,
+        }
+    }
+}
+
+impl SimulationPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.pool_percentage) {
+            return Err("pool_percentage must be between 0 and 1".to_string());
+        }
+        if self.period_days == 0 {
+            return Err("period_days must be at least 1".to_string());
+        }
+        if self.max_discount.is_some_and(|cap| cap.is_nan() || cap < 0.0) {
+            return Err("max_discount must not be negative".to_string());
+        }
+        if self.max_discount_percentage.is_some_and(|cap| !(0.0..=1.0).contains(&cap)) {
+            return Err("max_discount_percentage must be between 0 and 1".to_string());
+        }
+        if self.min_eligible_spend.is_nan() || self.min_eligible_spend < 0.0 {
+            return Err("min_eligible_spend must not be negative".to_string());
+        }
+        Ok(())
+    }
+
+    fn cap(&self, share: f64, amount: f64) -> f64 {
+        let mut discount = share.min(amount);
+        if let Some(cap) = self.max_discount {
+            discount = discount.min(cap);
+        }
+        if let Some(cap) = self.max_discount_percentage {
+            discount = discount.min(amount * cap);
+        }
+        discount.max(0.0)
+    }
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct SimulationReport {
+    pub policy: SimulationPolicy,
+    pub bills: usize,
+    pub total_billed: f64,
+    pub total_pooled: f64,
+    /// Discounts the business would have funded.
+    pub total_discount: f64,
+    /// Customers eligible for at least one period's pool.
+    pub customers_eligible: usize,
+    pub periods: Vec<SimulatedPeriod>,
+    pub customers: Vec<CustomerOutcome>,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct SimulatedPeriod {
+    pub period_start: String,
+    pub bills: usize,
+    pub billed: f64,
+    pub pooled: f64,
+    pub discount: f64,
+    pub eligible_customers: usize,
+    /// Eligible this period but not the one before.
+    pub newly_eligible: usize,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct CustomerOutcome {
+    pub customer_id: String,
+    pub bills: usize,
+    pub billed: f64,
+    pub discount: f64,
+    pub eligible_periods: usize,
+}
+
+/// Replays `bills` through `policy`, with periods of `policy.period_days`
+/// counted from `start`. Like the live engine, each period's pool is paid
+/// out in the next one, on every eligible customer's first bill of the day,
+/// so the first period only fills the pool. Each of the bills' pools is
+/// filled and paid out separately.
+pub fn simulate(bills: &[HistoricalBill], start: NaiveDate, policy: &SimulationPolicy) -> SimulationReport {
+    let mut bills: Vec<&HistoricalBill> = bills.iter().filter(|bill| bill.date >= start).collect();
+    bills.sort_by_key(|bill| bill.date);
+    let period_of = |date: NaiveDate| ((date - start).num_days() / policy.period_days as i64) as usize;
+    let period_count = bills.last().map_or(0, |bill| period_of(bill.date) + 1);
+
+    let mut report = SimulationReport {
+        policy: policy.clone(),
+        ..SimulationReport::default()
+    };
+    let mut customers: BTreeMap<String, CustomerOutcome> = BTreeMap::new();
+    let mut ever_eligible: HashSet<String> = HashSet::new();
+    // Per pool: spend per customer in the period before the current one,
+    // and the pool it filled.
+    let mut previous_spend: HashMap<String, HashMap<String, f64>> = HashMap::new();
+    let mut previous_pools: HashMap<String, f64> = HashMap::new();
+    let mut previously_eligible: HashSet<(String, String)> = HashSet::new();
+    let mut remaining = bills.into_iter().peekable();
+
+    for period in 0..period_count {
+        let eligible: HashMap<&String, HashMap<&String, f64>> = previous_spend
+            .iter()
+            .map(|(pool, spend)| {
+                let eligible = spend
+                    .iter()
+                    .filter(|(_, spend)| **spend >= policy.min_eligible_spend)
+                    .map(|(customer_id, spend)| (customer_id, *spend))
+                    .collect();
+                (pool, eligible)
+            })
+            .collect();
+        let eligible_pairs: HashSet<(String, String)> = eligible
+            .iter()
+            .flat_map(|(pool, customers)| customers.keys().map(|id| ((*pool).clone(), (*id).clone())))
+            .collect();
+
+        let period_start = start + Duration::days(period as i64 * policy.period_days as i64);
+        let mut summary = SimulatedPeriod {
+            period_start: period_start.format("%d-%b-%Y").to_string(),
+            eligible_customers: eligible_pairs.len(),
+            newly_eligible: eligible_pairs.difference(&previously_eligible).count(),
+            ..SimulatedPeriod::default()
+        };
+        let mut spend: HashMap<String, HashMap<String, f64>> = HashMap::new();
+        let mut pooled: HashMap<String, f64> = HashMap::new();
+        let mut paid_on: HashSet<(String, String, NaiveDate)> = HashSet::new();
+        while let Some(bill) = remaining.next_if(|bill| period_of(bill.date) == period) {
+            let pool_spend = spend.entry(bill.pool.clone()).or_default();
+            let first_today = paid_on.insert((bill.pool.clone(), bill.customer_id.clone(), bill.date));
+            let share = match eligible.get(&bill.pool).and_then(|customers| {
+                customers.get(&bill.customer_id).map(|spend| (customers, *spend))
+            }) {
+                Some((customers, customer_spend)) if first_today => {
+                    let previous_pool = previous_pools.get(&bill.pool).copied().unwrap_or(0.0);
+                    match policy.distribution {
+                        // Customers billed earlier in the period count too,
+                        // as they do in the live engine.
+                        DistributionMode::Equal => {
+                            previous_pool / (customers.len() + pool_spend.len()) as f64
+                        }
+                        DistributionMode::ProRata => {
+                            let eligible_spend: f64 = customers.values().sum();
+                            if eligible_spend > 0.0 {
+                                previous_pool * customer_spend / eligible_spend
+                            } else {
+                                0.0
+                            }
+                        }
+                    }
+                }
+                _ => 0.0,
+            };
+            let discount = policy.cap(share, bill.amount);
+            let paid = bill.amount - discount;
+            summary.bills += 1;
+            summary.billed += bill.amount;
+            summary.discount += discount;
+            summary.pooled += paid * policy.pool_percentage;
+            *pooled.entry(bill.pool.clone()).or_default() += paid * policy.pool_percentage;
+            *pool_spend.entry(bill.customer_id.clone()).or_default() += paid;
+
+            let outcome = customers.entry(bill.customer_id.clone()).or_insert_with(|| CustomerOutcome {
+                customer_id: bill.customer_id.clone(),
+                ..CustomerOutcome::default()
+            });
+            outcome.bills += 1;
+            outcome.billed += bill.amount;
+            outcome.discount += discount;
+        }
+
+        for (_, customer_id) in &eligible_pairs {
+            ever_eligible.insert(customer_id.clone());
+            if let Some(outcome) = customers.get_mut(customer_id) {
+                outcome.eligible_periods += 1;
+            }
+        }
+        previously_eligible = eligible_pairs;
+        previous_pools = pooled;
+        previous_spend = spend;
+
+        report.bills += summary.bills;
+        report.total_billed += summary.billed;
+        report.total_pooled += summary.pooled;
+        report.total_discount += summary.discount;
+        report.periods.push(summary);
+    }
+    report.customers_eligible = ever_eligible.len();
+    report.customers = customers.into_values().collect();
+    report
+}
+
+/// Reads `business_name`'s bills from `from` to `to` out of the store. The
+/// transaction log is used where it exists; weeks without one fall back to
+/// the weekly blobs of the business and its outlets. A coalition's pool is
+/// filled and paid out by all its members, so their bills in it are read
+/// too. Only reads.
+pub fn load_history(
+    business_name: &str,
+    from: NaiveDate,
+    to: NaiveDate,
+    conn: &mut redis::Connection,
+) -> Vec<HistoricalBill> {
+    let business_policy = policy::load_policy(business_name, conn);
+    let coalitions: Vec<coalitions::Coalition> = coalitions::list_coalitions(conn)
+        .into_iter()
+        .filter(|coalition| coalition.members.iter().any(|member| member == business_name))
+        .collect();
+    let mut bills = Vec::new();
+    let mut monday = from - Duration::days(from.weekday().num_days_from_monday() as i64);
+    while monday <= to {
+        let days: Vec<NaiveDate> = (0..7)
+            .map(|day| monday + Duration::days(day))
+            .filter(|day| (from..=to).contains(day))
+            .collect();
+        let mut logged: Vec<(DateTime<FixedOffset>, HistoricalBill)> = Vec::new();
+        for day in &days {
+            for record in transactions::load_transactions(business_name, *day, conn) {
+                let pool = match (&record.coalition_id, business_policy.pool_scope, &record.outlet_id) {
+                    (Some(coalition_id), _, _) => coalitions::coalition_pool_name(coalition_id),
+                    (None, policy::PoolScope::Outlet, Some(outlet_id)) => {
+                        outlets::outlet_pool_name(business_name, outlet_id)
+                    }
+                    _ => business_name.to_string(),
+                };
+                logged.extend(logged_bill(record, pool));
+            }
+        }
+        let mut week: Vec<HistoricalBill> = if logged.is_empty() {
+            outlets::pool_redis_keys(business_name, monday, conn)
+                .iter()
+                .flat_map(|key| blob_bills(key, from, to, conn))
+                .collect()
+        } else {
+            for coalition in &coalitions {
+                for member in coalition.members.iter().filter(|member| *member != business_name) {
+                    for day in &days {
+                        for record in transactions::load_transactions(member, *day, conn) {
+                            if record.coalition_id.as_deref() == Some(coalition.coalition_id.as_str()) {
+                                let pool = coalitions::coalition_pool_name(&coalition.coalition_id);
+                                logged.extend(logged_bill(record, pool));
+                            }
+                        }
+                    }
+                }
+            }
+            logged.sort_by_key(|(timestamp, _)| *timestamp);
+            logged.into_iter().map(|(_, bill)| bill).collect()
+        };
+        bills.append(&mut week);
+        monday += Duration::days(7);
+    }
+    bills.sort_by_key(|bill| bill.date);
+    bills
+}
+
+fn logged_bill(record: transactions::TransactionRecord, pool: String) -> Option<(DateTime<FixedOffset>, HistoricalBill)> {
+    let timestamp = DateTime::parse_from_rfc3339(&record.timestamp).ok()?;
+    Some((
+        timestamp,
+        HistoricalBill {
+            customer_id: record.customer_id,
+            date: timestamp.date_naive(),
+            amount: record.bill_amount,
+            pool,
+        },
+    ))
+}
+
+fn blob_bills(redis_key: &str, from: NaiveDate, to: NaiveDate, conn: &mut redis::Connection) -> Vec<HistoricalBill> {
+    let pool = redis_key.split_once("___").map_or(redis_key, |(pool, _)| pool);
+    let details = parse_customer_discount_details(&fetch_data_from_redis(redis_key, conn));
+    let mut bills = Vec::new();
+    for (customer_id, days) in details.customer_expense_map {
+        for (date, amounts) in days {
+            let Ok(date) = NaiveDate::parse_from_str(&date, "%d-%b-%Y") else {
+                continue;
+            };
+            if !(from..=to).contains(&date) {
+                continue;
+            }
+            bills.extend(
+                amounts
+                    .split(',')
+                    .filter_map(|amount| amount.trim().parse::<f64>().ok())
+                    .map(|amount| HistoricalBill {
+                        customer_id: customer_id.clone(),
+                        date,
+                        amount,
+                        pool: pool.to_string(),
+                    }),
+            );
+        }
+    }
+    bills
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bill(customer_id: &str, day: u32, amount: f64) -> HistoricalBill {
+        HistoricalBill {
+            customer_id: customer_id.to_string(),
+            date: NaiveDate::from_ymd_opt(2026, 10, day).unwrap(),
+            amount,
+            pool: "test102".to_string(),
+        }
+    }
+
+    fn history() -> Vec<HistoricalBill> {
+        vec![
+            bill("a", 5, 1000.0),
+            bill("b", 6, 500.0),
+            bill("c", 7, 50.0),
+            // Next period: a twice on one day, b once, d new.
+            bill("a", 12, 200.0),
+            bill("a", 12, 100.0),
+            bill("b", 13, 100.0),
+            bill("d", 14, 100.0),
+        ]
+    }
+
+    fn start() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 10, 5).unwrap()
+    }
+
+    #[test]
+    fn test_equal_shares_with_eligibility_threshold() {
+        let policy = SimulationPolicy {
+            pool_percentage: 0.1,
+            min_eligible_spend: 100.0,
+            ..SimulationPolicy::default()
+        };
+        let report = simulate(&history(), start(), &policy);
+        assert_eq!(report.periods.len(), 2);
+        assert_eq!(report.periods[0].discount, 0.0);
+        assert!((report.periods[0].pooled - 155.0).abs() < 1e-9);
+        // c spent too little, so a and b share 155, a only on the first bill.
+        // By b's bill, a has been billed this period too, so it counts.
+        assert_eq!(report.periods[1].eligible_customers, 2);
+        assert_eq!(report.periods[1].newly_eligible, 2);
+        assert_eq!(report.customers_eligible, 2);
+        assert!((report.periods[1].discount - 155.0 / 2.0 - 155.0 / 3.0).abs() < 1e-9);
+        let a = &report.customers[0];
+        assert_eq!((a.bills, a.eligible_periods), (3, 1));
+        assert!((a.discount - 77.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_pro_rata_shares_and_caps() {
+        let policy = SimulationPolicy {
+            pool_percentage: 0.1,
+            distribution: DistributionMode::ProRata,
+            max_discount: Some(90.0),
+            max_discount_percentage: Some(0.5),
+            ..SimulationPolicy::default()
+        };
+        let report = simulate(&history(), start(), &policy);
+        let discount = |id: &str| report.customers.iter().find(|c| c.customer_id == id).unwrap().discount;
+        // Pool of 155 over spend of 1550: a 100 capped at 90, b 50 capped at half of 100, c 5.
+        assert!((discount("a") - 90.0).abs() < 1e-9);
+        assert!((discount("b") - 50.0).abs() < 1e-9);
+        assert_eq!(discount("d"), 0.0);
+        assert!(policy.validate().is_ok());
+        assert!(SimulationPolicy { period_days: 0, ..policy }.validate().is_err());
+    }
+
+    #[test]
+    fn test_pools_are_paid_out_separately() {
+        let mut bills = history();
+        for bill in bills.iter_mut().filter(|bill| bill.customer_id == "b") {
+            bill.pool = "test102@mg_road".to_string();
+        }
+        let policy = SimulationPolicy {
+            pool_percentage: 0.1,
+            ..SimulationPolicy::default()
+        };
+        let report = simulate(&bills, start(), &policy);
+        let discount = |id: &str| report.customers.iter().find(|c| c.customer_id == id).unwrap().discount;
+        // a and c share a's pool of 105; b has the outlet's 50 to itself.
+        assert!((discount("a") - 105.0 / 2.0).abs() < 1e-9);
+        assert!((discount("b") - 50.0).abs() < 1e-9);
+        assert_eq!(report.periods[1].eligible_customers, 3);
+    }
+}
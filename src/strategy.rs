@@ -0,0 +1,155 @@
+use crate::items::{self, LineItem};
+use crate::policy::BusinessPolicy;
+use crate::CustomerDiscountDetails;
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// Name of the strategy businesses use unless their policy picks another.
+pub const DEFAULT_STRATEGY: &str = "equal_split";
+
+lazy_static! {
+    static ref STRATEGIES: RwLock<HashMap<String, Arc<dyn DiscountStrategy>>> = {
+        let mut strategies: HashMap<String, Arc<dyn DiscountStrategy>> = HashMap::new();
+        strategies.insert(DEFAULT_STRATEGY.to_string(), Arc::new(EqualSplit));
+        RwLock::new(strategies)
+    };
+}
+
+/// What a strategy knows about the bill being processed.
+pub struct DiscountContext<'a> {
+    pub business_name: &'a str,
+    pub customer_key: &'a str,
+    pub bill_amount: f64,
+    pub items: &'a [LineItem],
+    pub policy: &'a BusinessPolicy,
+    /// The period whose pool is being paid out.
+    pub previous_period: &'a CustomerDiscountDetails,
+    /// The period in progress, before this bill.
+    pub current_period: &'a CustomerDiscountDetails,
+    /// Whether the customer was already billed today.
+    pub billed_today: bool,
+    /// Consecutive weeks the customer has been billed in, this one included.
+    pub streak_weeks: u32,
+    pub now: DateTime<Utc>,
+    /// Contribution rate of an active campaign, in place of the policy's.
+    pub campaign_pool_percentage: Option<f64>,
+}
+
+/// Decides who shares a pool, how much a share is worth and what a bill
+/// puts in. Streak and campaign multipliers, referral bonuses and wallets
+/// are applied on top by `process_discount`, whatever the strategy.
+pub trait DiscountStrategy: Send + Sync {
+    /// Whether the customer gets a share of the pool on this bill.
+    fn is_eligible(&self, context: &DiscountContext) -> bool;
+
+    /// What one share of the pool is worth to the customer. Also prices
+    /// referral bonuses paid in pool shares.
+    fn share_value(&self, context: &DiscountContext) -> f64;
+
+    /// What the bill puts into the next period's pool once `final_amount`
+    /// is known.
+    fn pool_contribution(&self, context: &DiscountContext, final_amount: f64) -> f64;
+}
+
+/// The weekly pool split equally: customers billed last week get an equal
+/// share of what last week's bills put in, on their first bill of each day.
+pub struct EqualSplit;
+
+impl EqualSplit {
+    fn eligible_customers(context: &DiscountContext) -> f64 {
+        context.previous_period.total_eligible_customers + context.current_period.total_eligible_customers
+    }
+}
+
+impl DiscountStrategy for EqualSplit {
+    fn is_eligible(&self, context: &DiscountContext) -> bool {
+        !context.billed_today
+            && context.previous_period.customer_expense_map.contains_key(context.customer_key)
+            && EqualSplit::eligible_customers(context) > 0.0
+    }
+
+    fn share_value(&self, context: &DiscountContext) -> f64 {
+        theloyalgame_core::equal_share(
+            context.previous_period.total_pooled_amount,
+            EqualSplit::eligible_customers(context),
+        )
+    }
+
+    fn pool_contribution(&self, context: &DiscountContext, final_amount: f64) -> f64 {
+        // A campaign replaces the default rate; category rates still apply.
+        items::pool_contribution(
+            context.items,
+            context.bill_amount,
+            final_amount,
+            context.campaign_pool_percentage.unwrap_or(context.policy.pool_percentage),
+            &context.policy.category_rates,
+        )
+    }
+}
+
+/// Makes `strategy` available to businesses whose policy names it. Call it
+/// before serving requests; a later registration under the same name
+/// replaces the earlier one.
+pub fn register_strategy(name: &str, strategy: Arc<dyn DiscountStrategy>) {
+    STRATEGIES.write().unwrap().insert(name.to_string(), strategy);
+}
+
+pub fn is_registered(name: &str) -> bool {
+    STRATEGIES.read().unwrap().contains_key(name)
+}
+
+/// The strategy called `name`, or the default if none is registered under it.
+pub fn strategy_for(name: &str) -> Arc<dyn DiscountStrategy> {
+    let strategies = STRATEGIES.read().unwrap();
+    strategies.get(name).cloned().unwrap_or_else(|| {
+        println!("Unknown discount strategy {}, using {}", name, DEFAULT_STRATEGY);
+        strategies[DEFAULT_STRATEGY].clone()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_equal_split() {
+        let policy = BusinessPolicy::default();
+        let previous_period = CustomerDiscountDetails {
+            total_pooled_amount: 90.0,
+            total_eligible_customers: 2.0,
+            customer_expense_map: [("+919876543210".to_string(), HashMap::new())].into(),
+            ..CustomerDiscountDetails::default()
+        };
+        let current_period = CustomerDiscountDetails {
+            total_eligible_customers: 1.0,
+            ..CustomerDiscountDetails::default()
+        };
+        let mut context = DiscountContext {
+            business_name: "test102",
+            customer_key: "+919876543210",
+            bill_amount: 200.0,
+            items: &[],
+            policy: &policy,
+            previous_period: &previous_period,
+            current_period: &current_period,
+            billed_today: false,
+            streak_weeks: 2,
+            now: Utc::now(),
+            campaign_pool_percentage: None,
+        };
+        let strategy = strategy_for(DEFAULT_STRATEGY);
+        assert!(strategy.is_eligible(&context));
+        assert_eq!(strategy.share_value(&context), 30.0);
+        assert_eq!(strategy.pool_contribution(&context, 100.0), 3.0);
+
+        context.campaign_pool_percentage = Some(0.05);
+        assert_eq!(strategy.pool_contribution(&context, 100.0), 5.0);
+        context.billed_today = true;
+        assert!(!strategy.is_eligible(&context));
+        context.billed_today = false;
+        context.customer_key = "+919812345678";
+        assert!(!strategy.is_eligible(&context));
+    }
+}
@@ -0,0 +1,108 @@
+use crate::policy::StreakBonus;
+use crate::{delete_data_from_redis, fetch_data_from_redis, persist_data_to_redis};
+use chrono::{Duration, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+/// Consecutive weekly periods a customer has been billed in, stored under
+/// `streak:<business>:<customer>`. Kept apart from the weekly blobs so it
+/// outlives the two periods the pool looks at.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CustomerStreak {
+    /// Monday of the last period the customer was billed in, as `%d-%b-%Y`
+    /// like the weekly blob keys.
+    pub last_period: String,
+    pub weeks: u32,
+}
+
+impl CustomerStreak {
+    /// The streak after a visit in the period starting `monday`. A second
+    /// visit in the same period changes nothing; skipping a period restarts
+    /// the count.
+    pub fn visit(current: Option<&CustomerStreak>, monday: NaiveDate) -> CustomerStreak {
+        let weeks = match current {
+            Some(streak) if streak.last_period == period_label(monday) => streak.weeks,
+            Some(streak) if streak.last_period == period_label(monday - Duration::days(7)) => streak.weeks + 1,
+            _ => 1,
+        };
+        CustomerStreak { last_period: period_label(monday), weeks }
+    }
+}
+
+pub fn period_label(monday: NaiveDate) -> String {
+    monday.format("%d-%b-%Y").to_string()
+}
+
+/// The multiplier earned by a `weeks` long streak: that of the longest
+/// configured streak it reaches, or 1.
+pub fn streak_multiplier(bonuses: &[StreakBonus], weeks: u32) -> f64 {
+    bonuses
+        .iter()
+        .filter(|bonus| bonus.min_weeks <= weeks)
+        .max_by_key(|bonus| bonus.min_weeks)
+        .map_or(1.0, |bonus| bonus.multiplier)
+}
+
+pub fn load_streak(
+    business_name: &str,
+    customer_key: &str,
+    conn: &mut redis::Connection,
+) -> Option<CustomerStreak> {
+    serde_json::from_str(&fetch_data_from_redis(&streak_redis_key(business_name, customer_key), conn)).ok()
+}
+
+pub fn save_streak(
+    business_name: &str,
+    customer_key: &str,
+    streak: &CustomerStreak,
+    conn: &mut redis::Connection,
+) {
+    persist_data_to_redis(
+        &streak_redis_key(business_name, customer_key),
+        serde_json::to_string(streak).unwrap(),
+        conn,
+    );
+}
+
+pub fn delete_streak(business_name: &str, customer_key: &str, conn: &mut redis::Connection) {
+    delete_data_from_redis(&streak_redis_key(business_name, customer_key), conn);
+}
+
+fn streak_redis_key(business_name: &str, customer_key: &str) -> String {
+    format!("streak:{}:{}", business_name, customer_key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn monday(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 10, day).unwrap()
+    }
+
+    #[test]
+    fn test_streak_survives_rollover_and_resets_on_gap() {
+        let first = CustomerStreak::visit(None, monday(5));
+        assert_eq!(first.weeks, 1);
+        let same_week = CustomerStreak::visit(Some(&first), monday(5));
+        assert_eq!(same_week.weeks, 1);
+        let next_week = CustomerStreak::visit(Some(&same_week), monday(12));
+        assert_eq!(next_week.weeks, 2);
+        let third_week = CustomerStreak::visit(Some(&next_week), monday(19));
+        assert_eq!(third_week, CustomerStreak { last_period: "19-Oct-2026".to_string(), weeks: 3 });
+        // Skipping the week of the 26th starts over.
+        assert_eq!(CustomerStreak::visit(Some(&third_week), monday(19) + Duration::days(14)).weeks, 1);
+    }
+
+    #[test]
+    fn test_streak_multiplier_picks_longest_reached() {
+        let bonuses = vec![
+            StreakBonus { min_weeks: 5, multiplier: 2.0 },
+            StreakBonus { min_weeks: 3, multiplier: 1.5 },
+        ];
+        assert_eq!(streak_multiplier(&bonuses, 2), 1.0);
+        assert_eq!(streak_multiplier(&bonuses, 3), 1.5);
+        assert_eq!(streak_multiplier(&bonuses, 4), 1.5);
+        assert_eq!(streak_multiplier(&bonuses, 9), 2.0);
+        assert_eq!(streak_multiplier(&[], 9), 1.0);
+    }
+}
@@ -0,0 +1,192 @@
+use crate::audit;
+use crate::business;
+use crate::auth::{AuthError, Principal, Role};
+use crate::error::ApiError;
+use crate::secrets::{from_hex, to_hex};
+use crate::{
+    delete_data_from_redis, fetch_data_from_redis, persist_data_to_redis,
+    persist_data_to_redis_if_absent,
+};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Requests whose timestamp is further than this from the server clock are
+/// rejected; inside the window each signature is accepted once.
+pub const SIGNATURE_WINDOW_SECS: i64 = 300;
+
+/// A POS terminal allowed to bill for one business by signing its requests.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Terminal {
+    pub terminal_id: String,
+    pub business_name: String,
+    pub label: String,
+    pub secret: String,
+    /// Outlet the terminal is installed at; its bills are credited there.
+    #[serde(default)]
+    pub outlet_id: Option<String>,
+}
+
+impl Terminal {
+    pub fn principal(&self) -> Principal {
+        Principal {
+            business_name: Some(self.business_name.clone()),
+            role: Role::Cashier,
+            label: format!("terminal:{}", self.terminal_id),
+            customer_key: None,
+        }
+    }
+}
+
+pub fn register_terminal(
+    business_name: &str,
+    label: &str,
+    outlet_id: Option<&str>,
+    conn: &mut redis::Connection,
+) -> Terminal {
+    let terminal = Terminal {
+        terminal_id: format!("term_{}", Uuid::new_v4().simple()),
+        business_name: business_name.to_string(),
+        label: label.to_string(),
+        secret: format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple()),
+        outlet_id: outlet_id.map(str::to_string),
+    };
+    persist_data_to_redis(
+        &terminal_redis_key(&terminal.terminal_id),
+        serde_json::to_string(&terminal).unwrap(),
+        conn,
+    );
+    println!(
+        "Registered terminal - Id: {}, Business: {}, Label: {}",
+        terminal.terminal_id, terminal.business_name, terminal.label
+    );
+    terminal
+}
+
+pub fn load_terminal(terminal_id: &str, conn: &mut redis::Connection) -> Option<Terminal> {
+    serde_json::from_str(&fetch_data_from_redis(&terminal_redis_key(terminal_id), conn)).ok()
+}
+
+/// Removes a terminal of `business_name`, so its signatures are refused from
+/// then on. Recorded in the business's audit log.
+pub fn delete_terminal(
+    business_name: &str,
+    terminal_id: &str,
+    actor: &str,
+    conn: &mut redis::Connection,
+) -> Result<(), ApiError> {
+    let terminal = load_terminal(terminal_id, conn)
+        .filter(|terminal| terminal.business_name == business_name)
+        .ok_or_else(|| ApiError::not_found("Terminal not found"))?;
+    delete_data_from_redis(&terminal_redis_key(terminal_id), conn);
+    audit::record_audit(
+        business_name,
+        actor,
+        "terminal_deleted",
+        serde_json::json!({ "terminal_id": terminal.terminal_id, "label": terminal.label }),
+        conn,
+    );
+    Ok(())
+}
+
+/// The string a terminal signs: method, path (with query), timestamp and the
+/// hex SHA-256 of the body, one per line.
+pub fn canonical_request(method: &str, path: &str, timestamp: i64, body: &[u8]) -> String {
+    format!(
+        "{}\n{}\n{}\n{}",
+        method.to_uppercase(),
+        path,
+        timestamp,
+        to_hex(&Sha256::digest(body))
+    )
+}
+
+/// Hex HMAC-SHA256 of the canonical request, as sent in `X-Signature`.
+pub fn sign_request(secret: &str, method: &str, path: &str, timestamp: i64, body: &[u8]) -> String {
+    to_hex(&request_mac(secret, method, path, timestamp, body).finalize().into_bytes())
+}
+
+fn request_mac(secret: &str, method: &str, path: &str, timestamp: i64, body: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(canonical_request(method, path, timestamp, body).as_bytes());
+    mac
+}
+
+pub struct SignedRequest<'a> {
+    pub terminal_id: &'a str,
+    pub timestamp: &'a str,
+    pub signature: &'a str,
+    pub method: &'a str,
+    pub path: &'a str,
+    pub body: &'a [u8],
+}
+
+/// Verifies a terminal's signature, clock skew and that the signature has
+/// not been seen before. Returns the terminal that signed it.
+pub fn verify_signed_request(
+    request: &SignedRequest,
+    now: i64,
+    conn: &mut redis::Connection,
+) -> Result<Terminal, AuthError> {
+    let unauthenticated = |message: &str| AuthError::Unauthenticated(message.to_string());
+    let terminal = load_terminal(request.terminal_id, conn)
+        .ok_or_else(|| unauthenticated("Unknown terminal."))?;
+    let timestamp: i64 = request
+        .timestamp
+        .parse()
+        .map_err(|_| unauthenticated("Invalid X-Timestamp."))?;
+    if (now - timestamp).abs() > SIGNATURE_WINDOW_SECS {
+        return Err(unauthenticated("Request timestamp outside the allowed window."));
+    }
+    let signature = from_hex(request.signature).ok_or_else(|| unauthenticated("Invalid request signature."))?;
+    request_mac(&terminal.secret, request.method, request.path, timestamp, request.body)
+        .verify_slice(&signature)
+        .map_err(|_| unauthenticated("Invalid request signature."))?;
+    let first_use = persist_data_to_redis_if_absent(
+        &format!("terminal_nonce:{}:{}", terminal.terminal_id, to_hex(&signature)),
+        timestamp.to_string(),
+        (2 * SIGNATURE_WINDOW_SECS) as u64,
+        conn,
+    );
+    if !first_use {
+        return Err(unauthenticated("Replayed request."));
+    }
+    if !business::is_registered(&terminal.business_name, conn) {
+        return Err(AuthError::Forbidden(format!(
+            "Business '{}' is not registered.",
+            terminal.business_name
+        )));
+    }
+    Ok(terminal)
+}
+
+fn terminal_redis_key(terminal_id: &str) -> String {
+    format!("terminal:{}", terminal_id)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_canonical_request() {
+        assert_eq!(
+            canonical_request("post", "/terminal/discounts", 1_700_000_000, b""),
+            "POST\n/terminal/discounts\n1700000000\ne3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[test]
+    fn test_signature_covers_every_part() {
+        let signature = sign_request("secret", "POST", "/terminal/discounts", 100, b"{}");
+        assert_eq!(signature.len(), 64);
+        assert_ne!(signature, sign_request("other", "POST", "/terminal/discounts", 100, b"{}"));
+        assert_ne!(signature, sign_request("secret", "GET", "/terminal/discounts", 100, b"{}"));
+        assert_ne!(signature, sign_request("secret", "POST", "/terminal/other", 100, b"{}"));
+        assert_ne!(signature, sign_request("secret", "POST", "/terminal/discounts", 101, b"{}"));
+        assert_ne!(signature, sign_request("secret", "POST", "/terminal/discounts", 100, b"[]"));
+    }
+}
@@ -0,0 +1,99 @@
+use crate::items::LineItem;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// One billed transaction, appended to a per-business, per-day list under
+/// `transactions:<business>:<dd-Mon-YYYY>`. The weekly blobs only keep the
+/// amounts; this log keeps who billed what.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct TransactionRecord {
+    pub timestamp: String,
+    pub business_name: String,
+    /// Loyalty profile billed. Older entries call this `phone_number`.
+    #[serde(alias = "phone_number")]
+    pub customer_id: String,
+    pub bill_amount: f64,
+    pub discount: f64,
+    pub final_amount: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub terminal_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outlet_id: Option<String>,
+    /// Campaigns that boosted this bill.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub campaign_ids: Vec<String>,
+    /// The bill's line items, when it was itemized.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub items: Vec<LineItem>,
+    /// What the bill put into next week's pool. Missing on older entries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool_contribution: Option<f64>,
+    /// Pool share granted on this bill, taken off or banked in a wallet.
+    /// Missing on older entries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool_share: Option<f64>,
+    /// Coalition whose pool the bill paid into and out of.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coalition_id: Option<String>,
+    /// Fraud rules the bill tripped; it was queued for review.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fraud_flags: Vec<String>,
+    /// Eligibility rules that kept the bill from a pool share.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failed_rules: Vec<String>,
+}
+
+pub fn transactions_redis_key(business_name: &str, date: NaiveDate) -> String {
+    format!("transactions:{}:{}", business_name, date.format("%d-%b-%Y"))
+}
+
+pub fn record_transaction(record: &TransactionRecord, conn: &mut redis::Connection) {
+    let date = chrono::DateTime::parse_from_rfc3339(&record.timestamp)
+        .map(|timestamp| timestamp.date_naive())
+        .unwrap_or_else(|_| chrono::Utc::now().date_naive());
+    let _: () = redis::cmd("RPUSH")
+        .arg(transactions_redis_key(&record.business_name, date))
+        .arg(serde_json::to_string(record).unwrap())
+        .query(conn)
+        .unwrap_or(());
+}
+
+pub fn load_transactions(
+    business_name: &str,
+    date: NaiveDate,
+    conn: &mut redis::Connection,
+) -> Vec<TransactionRecord> {
+    load_transactions_at(&transactions_redis_key(business_name, date), conn)
+}
+
+pub fn load_transactions_at(redis_key: &str, conn: &mut redis::Connection) -> Vec<TransactionRecord> {
+    let entries: Vec<String> = redis::cmd("LRANGE")
+        .arg(redis_key)
+        .arg(0)
+        .arg(-1)
+        .query(conn)
+        .unwrap_or_default();
+    entries
+        .iter()
+        .filter_map(|entry| serde_json::from_str(entry).ok())
+        .collect()
+}
+
+/// Overwrites the log at `redis_key`. Only for migrations; entries appended
+/// between the read and this call are lost.
+pub fn replace_transactions_at(
+    redis_key: &str,
+    records: &[TransactionRecord],
+    conn: &mut redis::Connection,
+) {
+    let mut pipe = redis::pipe();
+    pipe.cmd("DEL").arg(redis_key).ignore();
+    for record in records {
+        pipe.cmd("RPUSH")
+            .arg(redis_key)
+            .arg(serde_json::to_string(record).unwrap())
+            .ignore();
+    }
+    let _: () = pipe.query(conn).unwrap_or(());
+}
@@ -0,0 +1,118 @@
+use crate::{delete_data_from_redis, fetch_data_from_redis, persist_data_to_redis};
+use chrono::{Duration, NaiveDate};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+const DATE_FORMAT: &str = "%d-%b-%Y";
+
+/// A customer's banked pool shares at one business, stored under
+/// `wallet:<business>:<customer>`. While `enabled`, shares are credited here
+/// instead of coming off the bill.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, ToSchema)]
+pub struct Wallet {
+    pub enabled: bool,
+    /// Credits in the order they were made; redemptions use the oldest first.
+    pub lots: Vec<WalletLot>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct WalletLot {
+    pub amount: f64,
+    /// `%d-%b-%Y`, like the weekly blob keys.
+    pub credited_on: String,
+    /// Last day the credit can be redeemed; never expires when unset.
+    pub expires_on: Option<String>,
+}
+
+impl Wallet {
+    pub fn balance(&self) -> f64 {
+        self.lots.iter().map(|lot| lot.amount).sum()
+    }
+
+    /// Drops credits that expired before `today`. Returns the amount lost.
+    pub fn expire(&mut self, today: NaiveDate) -> f64 {
+        let expired = |lot: &WalletLot| {
+            lot.expires_on
+                .as_deref()
+                .and_then(|date| NaiveDate::parse_from_str(date, DATE_FORMAT).ok())
+                .is_some_and(|date| date < today)
+        };
+        let lost = self.lots.iter().filter(|lot| expired(lot)).map(|lot| lot.amount).sum();
+        self.lots.retain(|lot| !expired(lot));
+        lost
+    }
+
+    pub fn credit(&mut self, amount: f64, today: NaiveDate, expiry_days: Option<u32>) {
+        if amount <= 0.0 {
+            return;
+        }
+        self.lots.push(WalletLot {
+            amount,
+            credited_on: today.format(DATE_FORMAT).to_string(),
+            expires_on: expiry_days
+                .map(|days| (today + Duration::days(days as i64)).format(DATE_FORMAT).to_string()),
+        });
+    }
+
+    /// Takes up to `amount` out, oldest credits first. Returns what was taken.
+    pub fn redeem(&mut self, amount: f64) -> f64 {
+        let mut left = amount.max(0.0);
+        for lot in self.lots.iter_mut() {
+            let taken = lot.amount.min(left);
+            lot.amount -= taken;
+            left -= taken;
+        }
+        self.lots.retain(|lot| lot.amount > 0.0);
+        amount.max(0.0) - left
+    }
+}
+
+pub fn load_wallet(business_name: &str, customer_key: &str, conn: &mut redis::Connection) -> Wallet {
+    serde_json::from_str(&fetch_data_from_redis(&wallet_redis_key(business_name, customer_key), conn))
+        .unwrap_or_default()
+}
+
+pub fn save_wallet(business_name: &str, customer_key: &str, wallet: &Wallet, conn: &mut redis::Connection) {
+    persist_data_to_redis(
+        &wallet_redis_key(business_name, customer_key),
+        serde_json::to_string(wallet).unwrap(),
+        conn,
+    );
+}
+
+pub fn delete_wallet(business_name: &str, customer_key: &str, conn: &mut redis::Connection) {
+    delete_data_from_redis(&wallet_redis_key(business_name, customer_key), conn);
+}
+
+fn wallet_redis_key(business_name: &str, customer_key: &str) -> String {
+    format!("wallet:{}:{}", business_name, customer_key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn day(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 10, day).unwrap()
+    }
+
+    #[test]
+    fn test_wallet_redeems_oldest_first_and_expires() {
+        let mut wallet = Wallet { enabled: true, lots: Vec::new() };
+        wallet.credit(30.0, day(5), Some(10));
+        wallet.credit(20.0, day(12), None);
+        assert_eq!(wallet.balance(), 50.0);
+
+        assert_eq!(wallet.redeem(40.0), 40.0);
+        assert_eq!(wallet.lots.len(), 1);
+        assert_eq!(wallet.lots[0].credited_on, "12-Oct-2026");
+        assert_eq!(wallet.redeem(100.0), 10.0);
+        assert_eq!(wallet.balance(), 0.0);
+
+        wallet.credit(15.0, day(5), Some(10));
+        wallet.credit(5.0, day(12), None);
+        assert_eq!(wallet.expire(day(15)), 0.0);
+        assert_eq!(wallet.expire(day(16)), 15.0);
+        assert_eq!(wallet.balance(), 5.0);
+    }
+}
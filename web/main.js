@@ -5,14 +5,44 @@ const baseURL = "http://127.0.0.1:3030";
 
 // State object to track application state
 const state = {
-  username: null,
+  username: "test102", // Business the widget is embedded for
   token: localStorage.getItem("authToken") || "no-token",
   feedbackPromptCount: 0,
   feedbackDeclinedCount: 0,
-  step: "phone", // New step tracker: "phone" -> "amount" -> "done"
+  step: "phone", // New step tracker: "phone" -> "otp" -> "amount" -> "done"
   phone: null,   // Store phone number temporarily
+  pendingAmount: null, // Amount waiting for the phone to be verified
+  referralCode: new URLSearchParams(window.location.search).get("ref"), // From a shared ?ref=CODE link
+  snapshot: null, // Pool snapshot for previewing the discount while typing
 };
 
+// The wasm-bindgen build of core/ (see the README); the widget works without it
+let previewModule = null;
+function loadPreview() {
+  if (!previewModule) {
+    previewModule = import("./pkg/theloyalgame_core.js")
+      .then(async (core) => {
+        await core.default();
+        return core;
+      })
+      .catch((error) => {
+        console.info("Discount preview unavailable:", error);
+        return null;
+      });
+  }
+  return previewModule;
+}
+
+// Random id kept in this browser so the server can spot referral abuse
+function deviceId() {
+  let id = localStorage.getItem("deviceId");
+  if (!id) {
+    id = crypto.randomUUID();
+    localStorage.setItem("deviceId", id);
+  }
+  return id;
+}
+
 // Utility functions
 function createElement(tag, className, innerHTML) {
   const element = document.createElement(tag);
@@ -53,8 +83,10 @@ function addMessageElement(element, isBot) {
   messageElement.scrollIntoView({ behavior: "smooth" });
 }
 
+// Loose check only; the server normalizes the number (country code, spaces, leading 0)
 function validatePhone(phone) {
-  return phone && phone.length === 10 && /^\d+$/.test(phone);
+  const digits = (phone || "").replace(/[\s().-]/g, "");
+  return /^(\+|00)?\d{8,15}$/.test(digits);
 }
 
 function validateAmount(amount) {
@@ -63,28 +95,115 @@ function validateAmount(amount) {
 
 // Fetch discount from the server
 async function fetchDiscount(phone, amount) {
-  console.log("Current token state:", state.token);
-  const url = `${baseURL}/get_discount/${encodeURIComponent(
-    state.username
-  )}/phone_number_amount/${encodeURIComponent(phone)},${encodeURIComponent(
-    amount
-  )}/token/${encodeURIComponent(state.token)}`;
-  console.log("Fetching discount with URL:", url);
-  return fetch(url, { headers: { Accept: "text/plain" } });
+  return fetch(`${baseURL}/v1/discounts`, {
+    method: "POST",
+    headers: {
+      "Content-Type": "application/json",
+      Accept: "application/json",
+      Authorization: `Bearer ${state.token}`,
+    },
+    body: JSON.stringify({
+      business_name: state.username,
+      phone_number: phone,
+      amount: amount,
+      referral_code: state.referralCode || undefined,
+      device_id: deviceId(),
+    }),
+  });
 }
 
-// Generate a new token
-async function generateToken(phone) {
+// Fetch what the customer's next bill would be paid from, for previews
+async function loadSnapshot() {
+  state.snapshot = null;
+  const core = await loadPreview();
+  if (!core) return;
+  try {
+    // The phone goes in the body so it stays out of URLs and access logs
+    const response = await fetch(`${baseURL}/v1/pool_snapshot/${encodeURIComponent(state.username)}`, {
+      method: "POST",
+      headers: {
+        "Content-Type": "application/json",
+        Accept: "application/json",
+        Authorization: `Bearer ${state.token}`,
+      },
+      body: JSON.stringify({ customer_id: state.phone }),
+    });
+    if (response.ok) {
+      state.snapshot = await response.json();
+    }
+  } catch (error) {
+    console.info("Pool snapshot unavailable:", error);
+  }
+}
+
+// Show the expected discount for the amount being typed; the bill stays authoritative
+async function updatePreview(amountText) {
+  const hint = document.getElementById("preview-hint");
+  if (!hint) return;
+  const core = await loadPreview();
+  if (!core || state.step !== "amount" || !state.snapshot || !validateAmount(amountText)) {
+    hint.textContent = "";
+    return;
+  }
+  const quote = JSON.parse(
+    core.preview_discount(
+      JSON.stringify({ snapshot: state.snapshot, bill_amount: parseFloat(amountText) })
+    )
+  );
+  if (quote.error) {
+    hint.textContent = "";
+  } else if (quote.wallet_credited > 0) {
+    hint.textContent = `👛 About $${quote.wallet_credited.toFixed(2)} would go to your wallet`;
+  } else {
+    hint.textContent = `💡 Expected discount: $${quote.discount.toFixed(2)}${quote.estimate ? " (estimate)" : ""}`;
+  }
+}
+
+// Ask for the bill amount and get ready to preview it
+function askForAmount() {
+  state.step = "amount";
+  addMessage("📏 Please enter the bill amount (e.g., 600.50)", true);
+  loadSnapshot();
+}
+
+// Turn an error response ({code, status, message, request_id}) into an Error
+async function apiError(response) {
+  try {
+    const body = await response.json();
+    const error = new Error(body.message || `HTTP error! status: ${response.status}`);
+    error.code = body.code;
+    return error;
+  } catch (_) {
+    return new Error(`HTTP error! status: ${response.status}`);
+  }
+}
+
+// Ask the server to send a one-time password to the phone
+async function requestOtp(phone) {
+  const response = await fetch(`${baseURL}/request_otp`, {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({ business_name: state.username, phone: phone }),
+  });
+  if (!response.ok) {
+    throw await apiError(response);
+  }
+}
+
+// Exchange a verified one-time password for a new token
+async function generateToken(phone, otp) {
   try {
     const response = await fetch(
-      `${baseURL}/generate_token?phone=${encodeURIComponent(phone)}`,
+      `${baseURL}/generate_token?business_name=${encodeURIComponent(
+        state.username
+      )}&phone=${encodeURIComponent(phone)}&otp=${encodeURIComponent(otp)}`,
       {
         method: "GET",
         headers: { Accept: "application/json" },
       }
     );
     if (!response.ok) {
-      throw new Error(`Failed to generate token: ${response.statusText}`);
+      throw await apiError(response);
     }
     const data = await response.json();
     if (!data.token) {
@@ -100,46 +219,20 @@ async function generateToken(phone) {
 
 // Format the discount response
 function formatDiscountResponse(data) {
-  if (!data || typeof data !== "string") {
+  if (!data || typeof data !== "object") {
     console.error("Invalid response data:", data);
     return "⚠️ Error: Invalid response from server.";
   }
 
-  console.log("Raw Response Data:", data);
-
-  if (data.includes("Not authorized / Token expired")) {
-    return "⚠️ Session expired. Please try again!";
-  }
-
-  const rawLines = data.split("\n ; ");
-  console.log("Raw Lines:", rawLines);
-
-  const result = {};
-
-  rawLines.forEach((line) => {
-    const cleanedLine = line.trim().replace(/;/g, "");
-    console.log("Processing Line:", cleanedLine);
-
-    const [key, value] = cleanedLine.split(/:\s*/, 2);
-    if (key && value) {
-      const normalizedKey = key.toLowerCase().replace(/\s+/g, "_");
-      result[normalizedKey] = value.trim();
-      console.log(`Parsed Key: ${normalizedKey}, Value: ${value.trim()}`);
-    } else {
-      console.log("Failed to parse line:", cleanedLine);
-    }
-  });
-
-  console.log("Parsed Result:", result);
+  console.log("Discount outcome:", data);
 
-  const phoneNumber = result.phone_number || "N/A";
-  const finalBillAmount = result.final_bill_amount
-    ? parseFloat(result.final_bill_amount).toFixed(2)
-    : "N/A";
-  const discountGiven = result.discount_given
-    ? result.discount_given.replace("%", "").trim()
-    : "0.00";
-  const hasTransaction = result.has_transaction || "false";
+  const phoneNumber = data.customer_display || data.customer_id || "N/A";
+  const finalBillAmount =
+    typeof data.final_amount === "number" ? data.final_amount.toFixed(2) : "N/A";
+  const discountGiven =
+    typeof data.discount_percentage === "number"
+      ? data.discount_percentage.toFixed(2)
+      : "0.00";
 
   let message = `
     🎉 Discount Details:<br>
@@ -148,8 +241,23 @@ function formatDiscountResponse(data) {
     - Discount Given: ${discountGiven}%
   `;
 
+  if (typeof data.wallet_balance === "number") {
+    if (data.wallet_credited > 0) {
+      message += `<br>👛 $${data.wallet_credited.toFixed(2)} saved to your wallet`;
+    }
+    message += `<br>👛 Wallet balance: $${data.wallet_balance.toFixed(2)}`;
+  }
+
+  if (data.referral_bonus > 0) {
+    message += `<br>🤝 Referral bonus: $${data.referral_bonus.toFixed(2)}`;
+  }
+
+  if (data.streak_multiplier > 1 && parseFloat(discountGiven) > 0) {
+    message += `<br>🔥 ${data.streak_weeks}-week streak! ${data.streak_multiplier}× reward`;
+  }
+
   if (parseFloat(discountGiven) === 0) {
-    if (hasTransaction === "true") {
+    if (data.has_transaction) {
       message += `<br>ℹ️ Note: You've already received a discount this week. Try again next week!`;
     } else {
       message += `<br>ℹ️ Note: The weekly discount pool has been exhausted. Try again next week!`;
@@ -338,6 +446,7 @@ async function handleFeedbackSubmit(phone, form) {
     let response;
     if (photoFile) {
       const formData = new FormData();
+      formData.append("business_name", state.username);
       formData.append("phone_number", phone);
       formData.append("rating", parseInt(rating));
       formData.append("comment", comment);
@@ -345,11 +454,13 @@ async function handleFeedbackSubmit(phone, form) {
 
       response = await fetch(`${baseURL}/submit_feedback`, {
         method: "POST",
+        headers: { Authorization: `Bearer ${state.token}` },
         body: formData,
         signal: AbortSignal.timeout(10000),
       });
     } else {
       const jsonData = {
+        business_name: state.username,
         phone_number: phone,
         rating: parseInt(rating),
         comment: comment,
@@ -359,6 +470,7 @@ async function handleFeedbackSubmit(phone, form) {
         method: "POST",
         headers: {
           "Content-Type": "application/json",
+          Authorization: `Bearer ${state.token}`,
         },
         body: JSON.stringify(jsonData),
         signal: AbortSignal.timeout(10000),
@@ -366,12 +478,12 @@ async function handleFeedbackSubmit(phone, form) {
     }
 
     if (!response.ok) {
-      const errorText = await response.text();
-      console.error(`Feedback submission failed with status ${response.status}: ${errorText}`);
-      if (response.status === 413) {
+      const error = await apiError(response);
+      console.error(`Feedback submission failed with status ${response.status}: ${error.message}`);
+      if (error.code === "payload_too_large") {
         throw new Error("File size too large. Please upload a smaller image (max 1 MB).");
       }
-      throw new Error(`Failed to submit feedback: ${response.status} - ${errorText}`);
+      throw new Error(`Failed to submit feedback: ${error.message}`);
     }
 
     console.log("Feedback submitted successfully, closing form");
@@ -384,6 +496,81 @@ async function handleFeedbackSubmit(phone, form) {
   }
 }
 
+// Send an OTP and wait for the customer to type it in
+async function startPhoneVerification() {
+  state.token = null;
+  localStorage.removeItem("authToken");
+  try {
+    await requestOtp(state.phone);
+    state.step = "otp";
+    addMessage("🔐 We sent a 6-digit code to your phone. Please enter it.", true);
+  } catch (error) {
+    console.error("OTP request error:", error);
+    addMessage(`⚠️ ${error.message || "Failed to send a code. Please try again!"}`, true);
+    resetConversation();
+  }
+}
+
+function resetConversation() {
+  state.step = "phone";
+  state.phone = null;
+  state.pendingAmount = null;
+  state.snapshot = null;
+  updatePreview("");
+}
+
+// Fetch and show the discount for the current phone
+async function showDiscount(amount) {
+  state.step = "done";
+  updatePreview("");
+  try {
+    addMessage("⏳ Fetching your discount...", true);
+    const response = await fetchDiscount(state.phone, amount);
+    if (response.status === 401) {
+      addMessage(
+        "⚠️ Your session has expired. Let's verify your phone again.",
+        true
+      );
+      state.pendingAmount = amount;
+      await startPhoneVerification();
+      return;
+    }
+    if (!response.ok) throw await apiError(response);
+
+    const data = await response.json();
+    console.log("Response Data:", data);
+    state.referralCode = null; // Only ever good for the first bill
+    const formattedResponse = formatDiscountResponse(data);
+    addMessage(formattedResponse, true);
+    addMessage("✅ Discount applied successfully!", true);
+
+    if (state.feedbackDeclinedCount < 3) {
+      try {
+        showRatingPrompt(state.phone);
+        state.feedbackPromptCount++;
+      } catch (error) {
+        console.error("Error in showRatingPrompt:", error);
+        addMessage(
+          "⚠️ Failed to show rating prompt. Please try again!",
+          true
+        );
+      }
+    } else {
+      addMessage("Thank you for using our service! 😊", true);
+    }
+
+    // Reset state for the next interaction
+    resetConversation();
+  } catch (error) {
+    console.error("Error in showDiscount:", error);
+    addMessage("⚠️ Failed to get discount. Please try again!", true);
+    state.referralCode = null; // A refused referral shouldn't block the next try
+    state.token = null;
+    localStorage.removeItem("authToken");
+    resetConversation(); // Reset step on error
+  }
+}
+
 // Handle form submission with step-by-step input
 async function handleSubmit(event) {
   event.preventDefault();
@@ -402,14 +589,39 @@ async function handleSubmit(event) {
   if (state.step === "phone") {
     if (!validatePhone(inputText)) {
       addMessage(
-        "⚠️ Please enter a valid 10-digit phone number (e.g., 9898989898)",
+        "⚠️ Please enter a valid phone number (e.g., 9898989898 or +91 98989 89898)",
         true
       );
       return;
     }
     state.phone = inputText;
-    state.step = "amount";
-    addMessage("📏 Please enter the bill amount (e.g., 600.50)", true);
+    // Tokens only act for the phone they were issued to
+    if (!state.token || state.token === "no-token" || localStorage.getItem("authPhone") !== inputText) {
+      await startPhoneVerification();
+      return;
+    }
+    askForAmount();
+  } else if (state.step === "otp") {
+    try {
+      state.token = await generateToken(state.phone, inputText);
+      localStorage.setItem("authToken", state.token);
+      localStorage.setItem("authPhone", state.phone);
+      console.log("Generated and Stored Token:", state.token);
+      addMessage("✅ Phone verified!", true);
+    } catch (error) {
+      addMessage(`⚠️ ${error.message || "Invalid code. Please try again!"}`, true);
+      if (!/Invalid OTP/.test(error.message || "")) {
+        resetConversation();
+      }
+      return;
+    }
+    if (state.pendingAmount !== null) {
+      const amount = state.pendingAmount;
+      state.pendingAmount = null;
+      await showDiscount(amount);
+    } else {
+      askForAmount();
+    }
   } else if (state.step === "amount") {
     if (!validateAmount(inputText)) {
       addMessage(
@@ -418,76 +630,7 @@ async function handleSubmit(event) {
       );
       return;
     }
-    const amount = parseFloat(inputText);
-    state.step = "done";
-
-    try {
-      state.username = "test102";
-      if (!state.token || state.token === "no-token") {
-        addMessage("🔑 Generating a new token...", true);
-        state.token = await generateToken(state.phone);
-        localStorage.setItem("authToken", state.token);
-        console.log("Generated and Stored Token:", state.token);
-        addMessage("✅ New token generated successfully!", true);
-      }
-
-      addMessage("⏳ Fetching your discount...", true);
-      const response = await fetchDiscount(state.phone, amount);
-      if (!response.ok)
-        throw new Error(`HTTP error! status: ${response.status}`);
-
-      const data = await response.text();
-      console.log("Response Data:", data);
-      if (data.includes("Not authorized / Token expired")) {
-        addMessage(
-          "⚠️ Your session has expired. Generating a new token...",
-          true
-        );
-        state.token = null;
-        localStorage.removeItem("authToken");
-        state.token = await generateToken(state.phone);
-        localStorage.setItem("authToken", state.token);
-        console.log("Regenerated Token:", state.token);
-        addMessage("✅ New token generated successfully!", true);
-        const retryResponse = await fetchDiscount(state.phone, amount);
-        if (!retryResponse.ok)
-          throw new Error(`Retry failed! status: ${retryResponse.status}`);
-        const retryData = await retryResponse.text();
-        const formattedResponse = formatDiscountResponse(retryData);
-        addMessage(formattedResponse, true);
-        addMessage("✅ Discount applied successfully!", true);
-      } else {
-        const formattedResponse = formatDiscountResponse(data);
-        addMessage(formattedResponse, true);
-        addMessage("✅ Discount applied successfully!", true);
-      }
-
-      if (state.feedbackDeclinedCount < 3) {
-        try {
-          showRatingPrompt(state.phone);
-          state.feedbackPromptCount++;
-        } catch (error) {
-          console.error("Error in showRatingPrompt:", error);
-          addMessage(
-            "⚠️ Failed to show rating prompt. Please try again!",
-            true
-          );
-        }
-      } else {
-        addMessage("Thank you for using our service! 😊", true);
-      }
-
-      // Reset state for the next interaction
-      state.step = "phone";
-      state.phone = null;
-    } catch (error) {
-      console.error("Error in handleSubmit:", error);
-      addMessage("⚠️ Failed to get discount. Please try again!", true);
-      state.token = null;
-      localStorage.removeItem("authToken");
-      state.step = "phone"; // Reset step on error
-      state.phone = null;
-    }
+    await showDiscount(parseFloat(inputText));
   }
 }
 
@@ -504,4 +647,9 @@ document.addEventListener("DOMContentLoaded", () => {
 
   // Event listener for form submission
   form.addEventListener("submit", handleSubmit);
+
+  const input = document.getElementById("input-message");
+  if (input) {
+    input.addEventListener("input", () => updatePreview(input.value.trim()));
+  }
 });
\ No newline at end of file